@@ -1,36 +1,64 @@
-use super::{state::State, Stats, Suggestion};
-use crate::{finesse::FinesseFinder, place::PlaceFinder, shape::ShapeTable, Config, Input};
+use super::{board_rating, state::State, Heuristic, Placement, Stats, Suggestion};
+use crate::{
+    finesse::FinesseFinder,
+    place::{Place, PlaceFinder},
+    shape::ShapeTable,
+    BasicMatrix, Config, Input, KickSystem, Orientation, TraceHook,
+};
 use std::{
-    collections::HashMap,
-    sync::{mpsc, Arc, RwLock},
+    collections::{hash_map::Entry, HashMap},
+    sync::{mpsc, Arc, Mutex, RwLock},
 };
+use thiserror::Error;
 
 use super::b_star::{Search, Step};
 
 // Analysis handle
 
+/// Difference in rating at which a move counts for about a third as much as the best move
+/// towards `Analysis::forcedness()`. Roughly the cost of placing an extra piece.
+const FORCEDNESS_SCALE: f64 = 10.0;
+
 // Re-exports
-pub use super::b_star::MoveId;
+pub use super::b_star::{Frontier, MoveId};
 
 /// A handle to a blockfish analysis running in the background.
 pub struct Analysis {
     moves: HashMap<MoveId, Move>,
     trace_inputs: Box<TraceInputsFn>,
+    replay: Option<Replay>,
+    respawn: Option<Box<RespawnFn>>,
     stats: Arc<RwLock<Option<Stats>>>,
+    frontier: Arc<Mutex<Option<Frontier>>>,
     rx: mpsc::Receiver<Msg>,
     all_tx: Option<mpsc::Sender<Suggestion>>,
+    base_score: i64,
+    root_queue_len: usize,
+    sticky_best: Option<MoveId>,
 }
 
 /// Indicates that the analysis has finished and no new updates to any moves will happen.
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct AnalysisDone;
 
+/// Error produced when the inputs for a sequence of placements can't be reconstructed,
+/// e.g. because the trace doesn't belong to the analyzed state.
+#[derive(Clone, Debug, Eq, PartialEq, Error)]
+pub enum ReconstructError {
+    #[error("trace index {0} out of range")]
+    TraceIndexOutOfRange(usize),
+    #[error("no input sequence found for placement {0} of the trace")]
+    FinesseFailed(usize),
+}
+
 /// Holds the latest information about a move.
 #[derive(Debug, Eq, PartialEq)]
 struct Move {
     iteration: usize,
     rating: i64,
     trace: Vec<usize>,
+    // path cost and heuristic parts of `rating`
+    g_h: (i64, i64),
 }
 
 /// Message type sent from worker thread to `Analysis` handle.
@@ -43,25 +71,36 @@ struct Msg {
 /// Used by the worker thread to send information to the `Analysis` handle.
 struct AnalysisSink {
     stats: Arc<RwLock<Option<Stats>>>,
+    frontier: Arc<Mutex<Option<Frontier>>>,
     tx: mpsc::SyncSender<Msg>,
 }
 
 impl Analysis {
     /// Constructs a `(sink, handle)` pair. The analysis handle will use `trace_inputs` as
     /// the algorithm for computing inputs from a trace.
-    fn new(trace_inputs: impl Fn(&[usize]) -> Vec<Input> + Send + 'static) -> (AnalysisSink, Self) {
+    fn new(
+        trace_inputs: impl Fn(&[usize]) -> Result<Vec<Input>, ReconstructError> + Send + 'static,
+    ) -> (AnalysisSink, Self) {
         let (tx, rx) = mpsc::sync_channel(256);
         let stats = Arc::new(RwLock::new(None));
+        let frontier = Arc::new(Mutex::new(None));
         (
             AnalysisSink {
                 stats: stats.clone(),
+                frontier: frontier.clone(),
                 tx,
             },
             Analysis {
                 moves: HashMap::with_capacity(128),
                 trace_inputs: Box::new(trace_inputs),
+                replay: None,
+                respawn: None,
                 all_tx: None,
+                base_score: 0,
+                root_queue_len: 0,
+                sticky_best: None,
                 stats,
+                frontier,
                 rx,
             },
         )
@@ -71,11 +110,15 @@ impl Analysis {
     fn recv(&mut self, msg: Msg) -> Option<MoveId> {
         // send to all-suggestions channel if listening
         if let Some(all_tx) = self.all_tx.as_ref() {
-            let inputs = (self.trace_inputs)(&msg.mov.trace);
-            let rating = msg.mov.rating;
-            if all_tx.send(Suggestion { inputs, rating }).is_err() {
-                log::warn!("all-suggestions channel dropped");
-                self.all_tx = None;
+            match (self.trace_inputs)(&msg.mov.trace) {
+                Ok(inputs) => {
+                    let rating = msg.mov.rating;
+                    if all_tx.send(Suggestion { inputs, rating }).is_err() {
+                        log::warn!("all-suggestions channel dropped");
+                        self.all_tx = None;
+                    }
+                }
+                Err(err) => log::warn!("failed to reconstruct suggestion: {}", err),
             }
         }
 
@@ -102,6 +145,60 @@ impl Analysis {
         (lhs.rating, lhs.iteration).cmp(&(rhs.rating, rhs.iteration))
     }
 
+    /// Like `cmp`, but settles ties in rating by preferring the move whose first placement
+    /// takes fewer inputs to perform, before falling back to the earlier iteration. The
+    /// inputs are only reconstructed for moves with equal ratings.
+    ///
+    /// Panics if the inputs can't be reconstructed.
+    pub fn cmp_by_finesse(&self, lhs: MoveId, rhs: MoveId) -> std::cmp::Ordering {
+        let lhs = self.moves.get(&lhs).expect("invalid id");
+        let rhs = self.moves.get(&rhs).expect("invalid id");
+        let input_count = |mov: &Move| {
+            let len = std::cmp::min(1, mov.trace.len());
+            (self.trace_inputs)(&mov.trace[..len])
+                .unwrap_or_else(|err| panic!("{}", err))
+                .len()
+        };
+        lhs.rating
+            .cmp(&rhs.rating)
+            .then_with(|| input_count(lhs).cmp(&input_count(rhs)))
+            .then(lhs.iteration.cmp(&rhs.iteration))
+    }
+
+    /// Returns every move rated within `tol` of the best move, sorted by `cmp`, e.g. to
+    /// present moves that are about equally good as alternatives. Empty if there are no
+    /// moves yet.
+    pub fn best_group(&self, tol: i64) -> Vec<MoveId> {
+        let best = match self.moves.values().map(|m| m.rating).min() {
+            Some(best) => best,
+            None => return vec![],
+        };
+        let mut group = self
+            .moves
+            .iter()
+            .filter(|(_, m)| m.rating <= best.saturating_add(tol))
+            .map(|(&m_id, _)| m_id)
+            .collect::<Vec<_>>();
+        group.sort_by(|&m, &n| self.cmp(m, n));
+        group
+    }
+
+    /// Returns the best move, except that the move previously returned by this method is
+    /// kept as long as the best move doesn't beat its rating by more than `margin`. This
+    /// keeps the displayed recommendation from flipping between near-equal moves as the
+    /// search progresses. Returns `None` if there are no moves yet.
+    pub fn sticky_best(&mut self, margin: i64) -> Option<MoveId> {
+        let best = self.all_moves().min_by(|&m, &n| self.cmp(m, n))?;
+        if let Some(prev) = self.sticky_best {
+            let (prev_rating, best_rating) = (self.moves[&prev].rating, self.moves[&best].rating);
+            if prev_rating.saturating_sub(best_rating) <= margin {
+                return Some(prev);
+            }
+        }
+        self.sticky_best = Some(best);
+        Some(best)
+    }
+
     /// Polls the analysis for any progress. Returns `Ok(Some(m))` if move `m`'s rating
     /// changed. Returns `Ok(None)` if no progress was made since the last poll. Returns
     /// `Err(AnalysisDone)` if the analysis is over.
@@ -119,6 +216,21 @@ impl Analysis {
         }
     }
 
+    /// Receives all of the progress made since the last poll without blocking, and returns
+    /// every move whose rating changed, in the order they first changed. Unlike `poll`,
+    /// this doesn't indicate whether the analysis is over.
+    pub fn poll_all(&mut self) -> Vec<MoveId> {
+        let mut changed = vec![];
+        while let Ok(msg) = self.rx.try_recv() {
+            if let Some(move_id) = self.recv(msg) {
+                if !changed.contains(&move_id) {
+                    changed.push(move_id);
+                }
+            }
+        }
+        changed
+    }
+
     /// Blocks until the analysis thread finishes. This is a non-spinning version of
     /// `while !self.poll().is_err() {}`.
     pub fn wait(&mut self) {
@@ -127,16 +239,227 @@ impl Analysis {
         }
     }
 
+    /// Converts this analysis into a stream that yields `m` whenever move `m`'s rating
+    /// changes, and ends once the analysis is over. Progress is forwarded to the stream by
+    /// a background thread, so nothing is busy-polled while waiting.
+    #[cfg(feature = "async")]
+    pub fn into_stream(mut self) -> impl futures::Stream<Item = MoveId> {
+        let (tx, rx) = futures::channel::mpsc::unbounded();
+        std::thread::spawn(move || {
+            while let Ok(msg) = self.rx.recv() {
+                if let Some(move_id) = self.recv(msg) {
+                    if tx.unbounded_send(move_id).is_err() {
+                        // stream was dropped
+                        return;
+                    }
+                }
+            }
+        });
+        rx
+    }
+
     /// Returns the `Suggestion` for the given move, containing at most `len`
     /// placements. `len` should be `std::usize::MAX` in order to get the inputs for the
     /// entire sequence. `len` may be `0` to just get the move's rating.
+    ///
+    /// Panics if the inputs can't be reconstructed; see `try_suggestion`.
     pub fn suggestion(&self, m_id: MoveId, len: usize) -> Suggestion {
+        self.try_suggestion(m_id, len)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Like `suggestion`, but returns an error instead of panicking if the inputs for the
+    /// move's sequence can't be reconstructed.
+    pub fn try_suggestion(&self, m_id: MoveId, len: usize) -> Result<Suggestion, ReconstructError> {
         let mov = self.moves.get(&m_id).expect("invalid id");
         let len = std::cmp::min(len, mov.trace.len());
-        Suggestion {
-            inputs: (self.trace_inputs)(&mov.trace[..len]),
+        Ok(Suggestion {
+            inputs: (self.trace_inputs)(&mov.trace[..len])?,
             rating: mov.rating,
+        })
+    }
+
+    /// Returns just the first input of the given move's sequence, e.g. for hinting which
+    /// key to press next. Only the first placement's inputs are computed. Returns `None`
+    /// if the inputs can't be reconstructed (see `try_suggestion`).
+    pub fn next_input(&self, m_id: MoveId) -> Option<Input> {
+        let mov = self.moves.get(&m_id).expect("invalid id");
+        let len = std::cmp::min(1, mov.trace.len());
+        (self.trace_inputs)(&mov.trace[..len])
+            .ok()?
+            .first()
+            .cloned()
+    }
+
+    /// Returns the matrix resulting from performing the first `steps` placements of the
+    /// given move's sequence (and clearing any completed lines). `steps` may be `0` to get
+    /// the root matrix, or `usize::MAX` to get the matrix after the entire sequence.
+    pub fn board_after(&self, m_id: MoveId, steps: usize) -> BasicMatrix {
+        let mov = self.moves.get(&m_id).expect("invalid id");
+        let len = std::cmp::min(steps, mov.trace.len());
+        self.replay()
+            .run(&mov.trace[..len], |_, _| ())
+            .matrix()
+            .clone()
+    }
+
+    /// Returns the greatest height of the matrix after any placement in the given move's
+    /// sequence, e.g. to warn about sequences that temporarily stack dangerously high.
+    pub fn peak_height(&self, m_id: MoveId) -> u16 {
+        let mov = self.moves.get(&m_id).expect("invalid id");
+        if mov.trace.is_empty() {
+            return 0;
         }
+        // the callback sees the state before each placement, so skip the root
+        let mut peak = 0;
+        let mut at_root = true;
+        let last = self.replay().run(&mov.trace, |s, _| {
+            if !std::mem::take(&mut at_root) {
+                peak = peak.max(s.matrix().rows());
+            }
+        });
+        peak.max(last.matrix().rows())
+    }
+
+    /// Returns the placements making up the given move's sequence, containing at most
+    /// `len` placements.
+    pub fn placement_trace(&self, m_id: MoveId, len: usize) -> Vec<Placement> {
+        let mov = self.moves.get(&m_id).expect("invalid id");
+        let len = std::cmp::min(len, mov.trace.len());
+        let mut placements = vec![];
+        self.replay().run(&mov.trace[..len], |_, pl| {
+            placements.push(Placement::from(pl));
+        });
+        placements
+    }
+
+    /// Returns the total number of lines cleared by all of the placements in the given
+    /// move's sequence.
+    pub fn total_lines(&self, m_id: MoveId) -> u16 {
+        let mov = self.moves.get(&m_id).expect("invalid id");
+        let mut lines = 0;
+        self.replay().run(&mov.trace, |s, pl| {
+            lines += s.rows_cleared_by(pl).len() as u16;
+        });
+        lines
+    }
+
+    /// Returns the first placement of the given move's sequence, e.g. for labelling the
+    /// move by its opening piece.
+    pub fn first_placement(&self, m_id: MoveId) -> Placement {
+        self.placement_trace(m_id, 1)
+            .pop()
+            .expect("move has empty sequence")
+    }
+
+    /// Returns a short description of the first placement of the given move, such as
+    /// "Place J flat on the left, clearing 1 line", e.g. for presenting moves as text.
+    pub fn describe(&self, m_id: MoveId, shtb: &ShapeTable) -> String {
+        let pl = self.first_placement(m_id);
+        let root = &self.replay().root;
+        let shape = shtb.shape(pl.color).expect("placement has no shape");
+        let place = Place::new(shape, (pl.row, pl.col, pl.orientation), pl.did_hold);
+        let lines = root.rows_cleared_by(&place).len();
+        let (min_col, max_col) = pl
+            .cells(shtb)
+            .fold((u16::MAX, 0), |(lo, hi), (_, j)| (lo.min(j), hi.max(j)));
+        // compare the center of the piece against thirds of the matrix
+        let center = (min_col as u32 + max_col as u32 + 1) * 3;
+        let cols = root.matrix().cols() as u32 * 2;
+        let region = if center < cols {
+            "on the left"
+        } else if center > cols * 2 {
+            "on the right"
+        } else {
+            "in the middle"
+        };
+        let orientation = match shape.canonical_orientation(pl.orientation) {
+            Orientation::R0 => "flat",
+            Orientation::R1 => "rotated right",
+            Orientation::R2 => "upside down",
+            Orientation::R3 => "rotated left",
+        };
+        let mut desc = format!(
+            "{} {} {} {}",
+            if pl.did_hold {
+                "Hold and place"
+            } else {
+                "Place"
+            },
+            pl.color.as_char(),
+            orientation,
+            region
+        );
+        if lines > 0 {
+            let plural = if lines == 1 { "" } else { "s" };
+            desc += &format!(", clearing {} line{}", lines, plural);
+        }
+        desc
+    }
+
+    /// Returns the rating of the given move split into `(g, h)`: the path cost for the
+    /// pieces placed and the heuristic part, which includes rewards. These always add up
+    /// to the move's rating.
+    pub fn g_h(&self, m_id: MoveId) -> (i64, i64) {
+        self.moves.get(&m_id).expect("invalid id").g_h
+    }
+
+    /// Returns the rating of the given move relative to the score of the root matrix
+    /// (lower is better). Unlike absolute ratings, these can be compared between
+    /// analyses of different roots.
+    pub fn relative_rating(&self, m_id: MoveId) -> i64 {
+        let mov = self.moves.get(&m_id).expect("invalid id");
+        mov.rating - self.base_score
+    }
+
+    /// Returns the change in score caused by each placement in the given move's sequence,
+    /// i.e. the score of the board after the placement minus the score of the board before
+    /// it. Negative deltas are improvements. The deltas add up to the difference between
+    /// the score after the entire sequence and the score of the root.
+    pub fn step_deltas(&self, m_id: MoveId) -> Vec<i64> {
+        let mov = self.moves.get(&m_id).expect("invalid id");
+        let replay = self.replay();
+        let mut scores = vec![];
+        let last = replay.run(&mov.trace, |s, _| scores.push(replay.score(s)));
+        scores.push(replay.score(&last));
+        scores.windows(2).map(|w| w[1] - w[0]).collect()
+    }
+
+    /// Returns how "forced" the position is, between `0.0` and `1.0`. This is `1.0` if
+    /// there is only one move, and decreases as more moves are rated close to the best
+    /// move. Each move is weighted by `exp(-d / FORCEDNESS_SCALE)`, where `d` is how much
+    /// worse it is rated than the best move, and the result is the best move's share of
+    /// the total weight. Returns `0.0` if no moves have been found.
+    pub fn forcedness(&self) -> f64 {
+        let best = match self.moves.values().map(|m| m.rating).min() {
+            Some(best) => best,
+            None => return 0.0,
+        };
+        let total: f64 = self
+            .moves
+            .values()
+            .map(|m| (-((m.rating - best) as f64) / FORCEDNESS_SCALE).exp())
+            .sum();
+        1.0 / total
+    }
+
+    /// Returns the raw trace of the given move's sequence, i.e. the index of each
+    /// placement as produced by the placement finder from the previous state. Most users
+    /// should use `suggestion()` or `placement_trace()` instead.
+    pub fn trace(&self, m_id: MoveId) -> &[usize] {
+        let mov = self.moves.get(&m_id).expect("invalid id");
+        &mov.trace
+    }
+
+    /// Returns the number of pieces placed by the given move's sequence.
+    pub fn pieces_used(&self, m_id: MoveId) -> usize {
+        self.trace(m_id).len()
+    }
+
+    /// Returns the number of pieces in the root state (including the hold piece) that are
+    /// not placed by the given move's sequence.
+    pub fn pieces_remaining(&self, m_id: MoveId) -> usize {
+        self.root_queue_len.saturating_sub(self.pieces_used(m_id))
     }
 
     /// Returns the statistics gathered about the analysis, if any. Should be `Some` only
@@ -144,6 +467,31 @@ impl Analysis {
     pub fn stats(&self) -> Option<Stats> {
         self.stats.read().map_or(None, |s| s.clone())
     }
+
+    /// Takes the suspended state of the search, which can be passed to `AI::resume()` to
+    /// continue the search later. Only available after the analysis finishes, and only if
+    /// `Config::keep_frontier` was set.
+    pub fn take_frontier(&mut self) -> Option<Frontier> {
+        self.frontier.lock().ok()?.take()
+    }
+
+    /// Replaces this analysis with an analysis of `new_root`, typically the state after
+    /// the first placement of a move. If this analysis has finished and kept its frontier
+    /// (see `Config::keep_frontier`), and `new_root` is the state after one of the root's
+    /// placements, the search below that placement is carried over (see
+    /// `Frontier::reroot()`), so the best sequence found there is known right away.
+    /// Otherwise, the search starts over. The new analysis does not send suggestions to
+    /// the all-suggestions channel. Panics if there is no root state to start from.
+    pub fn reroot(&mut self, new_root: State) {
+        let respawn = self.respawn.take().expect("no root state to reroot from");
+        let frontier = self.take_frontier();
+        *self = respawn(new_root, frontier);
+    }
+
+    /// Returns the root state to replay move sequences from. Panics if there is none.
+    fn replay(&self) -> &Replay {
+        self.replay.as_ref().expect("no root state to replay")
+    }
 }
 
 impl AnalysisSink {
@@ -153,8 +501,12 @@ impl AnalysisSink {
         self.tx.send(msg).is_ok()
     }
 
-    /// Finishes the analysis after first setting the collected stats to `stats`.
-    fn finish(self, stats: Stats) {
+    /// Finishes the analysis after first setting the collected stats to `stats`, and the
+    /// suspended search to `frontier`.
+    fn finish(self, stats: Stats, frontier: Option<Frontier>) {
+        if let Ok(mut f) = self.frontier.lock() {
+            *f = frontier;
+        }
         if let Ok(mut s) = self.stats.write() {
             *s = Some(stats);
         }
@@ -163,13 +515,66 @@ impl AnalysisSink {
 
 // Analysis thread
 
-fn analysis(shtb: Arc<ShapeTable>, cfg: Config, root: State, sink: AnalysisSink) {
+fn analysis(
+    shtb: Arc<ShapeTable>,
+    cfg: Config,
+    heuristic: Option<Arc<Heuristic>>,
+    root: State,
+    resume: Option<Frontier>,
+    sink: AnalysisSink,
+) {
     let start_time = std::time::Instant::now();
+    if let Err(err) = cfg.parameters.check_column_weights(root.matrix().cols()) {
+        diagnostic(
+            cfg.trace_hook.as_ref(),
+            log::Level::Warn,
+            format_args!("invalid score parameters: {}", err),
+        );
+    }
     let mut iteration = 0;
     let mut global_min = std::i64::MAX;
+    let mut best_move = None;
+    let mut best_per_iteration = vec![];
+    let mut max_depth = 0;
+    let mut filter = RatingFilter::new(cfg.min_rating_delta);
+    // placements of pieces speculated beyond the root's queue are left out of the moves
+    let known_len = root.queue_len();
 
-    let mut search = Search::new(&shtb, cfg.parameters);
-    search.start(root);
+    let mut search = match resume.filter(|f| *f.root() == root) {
+        Some(frontier) => {
+            // report the moves found before the search was suspended
+            for (move_id, rating, g_h, trace) in frontier.best_moves() {
+                max_depth = std::cmp::max(max_depth, trace.len());
+                if rating < global_min {
+                    global_min = rating;
+                    best_move = Some(move_id);
+                }
+                let msg = filter.filter(Msg {
+                    changed_move_id: Some(move_id),
+                    mov: Move {
+                        iteration,
+                        rating,
+                        trace: trace[..trace.len().min(known_len)].to_vec(),
+                        g_h,
+                    },
+                });
+                if let Some(msg) = msg {
+                    if !sink.send(msg) {
+                        return;
+                    }
+                }
+            }
+            Search::resume(&shtb, &cfg, heuristic, frontier)
+        }
+        None => {
+            let mut search = Search::new(&shtb, &cfg, heuristic);
+            search.start(root);
+            search
+        }
+    };
+    if log::log_enabled!(log::Level::Trace) {
+        search.trace_steps(|ev| log::trace!("{:?}", ev));
+    }
 
     while search.node_count() < cfg.search_limit {
         let msg;
@@ -177,35 +582,53 @@ fn analysis(shtb: Arc<ShapeTable>, cfg: Config, root: State, sink: AnalysisSink)
             Ok(Step::RatingChanged {
                 move_id,
                 rating,
-                trace,
+                mut trace,
+                g_h,
             }) => {
                 iteration += 1;
-                log::debug!(
-                    "{:<2?} --> {:>3?}{} iter {}",
-                    move_id,
-                    rating,
-                    if rating < global_min { "*" } else { " " },
-                    iteration
+                max_depth = std::cmp::max(max_depth, trace.len());
+                trace.truncate(known_len);
+                diagnostic(
+                    cfg.trace_hook.as_ref(),
+                    log::Level::Debug,
+                    format_args!(
+                        "{:<2?} --> {:>3?}{} iter {}",
+                        move_id,
+                        rating,
+                        if rating < global_min { "*" } else { " " },
+                        iteration
+                    ),
                 );
-                global_min = std::cmp::min(rating, global_min);
-                msg = Some(Msg {
+                if rating < global_min {
+                    global_min = rating;
+                    best_move = Some(move_id);
+                }
+                msg = filter.filter(Msg {
                     changed_move_id: Some(move_id),
                     mov: Move {
                         iteration,
                         rating,
                         trace,
+                        g_h,
                     },
                 });
             }
 
-            Ok(Step::SequenceRejected { trace, rating }) => {
+            Ok(Step::SequenceRejected {
+                mut trace,
+                rating,
+                g_h,
+            }) => {
                 iteration += 1;
+                max_depth = std::cmp::max(max_depth, trace.len());
+                trace.truncate(known_len);
                 msg = Some(Msg {
                     changed_move_id: None,
                     mov: Move {
                         iteration,
                         rating,
                         trace,
+                        g_h,
                     },
                 });
             }
@@ -217,66 +640,417 @@ fn analysis(shtb: Arc<ShapeTable>, cfg: Config, root: State, sink: AnalysisSink)
             Err(_) => break,
         }
 
+        if cfg.record_best_per_iteration {
+            // record once each time the iteration count advances
+            let is_new = best_per_iteration
+                .last()
+                .map_or(true, |&(i, _, _)| i < iteration);
+            if let (true, Some(move_id)) = (is_new, best_move) {
+                best_per_iteration.push((iteration, move_id, global_min));
+            }
+        }
+
         if let Some(msg) = msg {
             if !sink.send(msg) {
-                log::warn!("handle disconnected mid-analysis");
+                diagnostic(
+                    cfg.trace_hook.as_ref(),
+                    log::Level::Warn,
+                    format_args!("handle disconnected mid-analysis"),
+                );
                 return;
             }
         }
     }
 
-    sink.finish(Stats {
+    // report the final rating of moves whose last improvements were held back
+    for msg in filter.into_held() {
+        if !sink.send(msg) {
+            return;
+        }
+    }
+
+    let stats = Stats {
         iterations: iteration,
         nodes: search.node_count(),
+        pruned: search.prune_count(),
         time_taken: std::time::Instant::now() - start_time,
-    });
+        best_per_iteration,
+        max_depth,
+    };
+    let frontier = if cfg.keep_frontier {
+        Some(search.suspend())
+    } else {
+        None
+    };
+    sink.finish(stats, frontier);
+}
+
+/// Runs an analysis of `root` to completion on the current thread and returns the
+/// suggestion for the best move, or an empty suggestion with the root's rating if there
+/// are no moves. The search stops after `cfg.search_limit` nodes, same as `spawn()`, but
+/// no heuristic, frontier or diagnostics are involved, so the result only depends on
+/// `shtb`, `cfg` and `root`, and is identical across runs.
+///
+/// The search itself is deterministic; the nondeterminism of a spawned analysis comes from
+/// the timing of the worker thread relative to polls, and from `Analysis::all_moves()`
+/// iterating a `HashMap`. Ties are broken the same way as `Analysis::cmp`, which does not
+/// depend on iteration order.
+pub fn analyze_deterministic(shtb: &ShapeTable, cfg: &Config, root: State) -> Suggestion {
+    let mut moves = HashMap::new();
+    let mut iteration = 0;
+    let mut search = Search::new(shtb, cfg, None);
+    search.start(root.clone());
+    while search.node_count() < cfg.search_limit {
+        match search.step() {
+            Ok(Step::RatingChanged {
+                move_id,
+                rating,
+                mut trace,
+                ..
+            }) => {
+                iteration += 1;
+                trace.truncate(root.queue_len());
+                moves.insert(move_id, (rating, iteration, trace));
+            }
+            Ok(Step::SequenceRejected { .. }) => iteration += 1,
+            Ok(Step::Other) => {}
+            Err(_) => break,
+        }
+    }
+    match moves
+        .values()
+        .min_by_key(|&&(rating, iteration, _)| (rating, iteration))
+    {
+        Some((rating, _, trace)) => Suggestion {
+            inputs: reconstruct_inputs(shtb, cfg, root, trace),
+            rating: *rating,
+        },
+        None => Suggestion {
+            inputs: vec![],
+            rating: board_rating(&cfg.parameters, &root),
+        },
+    }
+}
+
+/// Emits a diagnostic message from the worker thread to `hook` if set, otherwise to the
+/// global logger at `level`.
+fn diagnostic(hook: Option<&TraceHook>, level: log::Level, args: std::fmt::Arguments) {
+    match hook {
+        Some(hook) => hook.call(&args.to_string()),
+        None => log::log!(level, "{}", args),
+    }
+}
+
+/// Used by the worker thread to suppress reporting insignificant rating changes. The
+/// latest suppressed change of each move is held back, to be reported once the search
+/// ends.
+struct RatingFilter {
+    min_delta: i64,
+    global_min: i64,
+    last_sent: HashMap<MoveId, i64>,
+    held: HashMap<MoveId, Msg>,
+}
+
+impl RatingFilter {
+    fn new(min_delta: i64) -> Self {
+        Self {
+            min_delta,
+            global_min: i64::MAX,
+            last_sent: HashMap::with_capacity(128),
+            held: HashMap::new(),
+        }
+    }
+
+    /// Returns `true` if the new rating for `move_id` should be reported, which is when
+    /// it improved by at least `min_delta` since the last reported rating, or if it is the
+    /// best rating seen so far.
+    fn accept(&mut self, move_id: MoveId, rating: i64) -> bool {
+        let is_best = rating < self.global_min;
+        self.global_min = std::cmp::min(rating, self.global_min);
+        match self.last_sent.entry(move_id) {
+            Entry::Occupied(mut e) => {
+                if is_best || e.get().saturating_sub(rating) >= self.min_delta {
+                    e.insert(rating);
+                    true
+                } else {
+                    false
+                }
+            }
+            Entry::Vacant(e) => {
+                e.insert(rating);
+                true
+            }
+        }
+    }
+
+    /// Returns `msg` if its rating change should be reported (see `accept`), otherwise
+    /// holds it back in place of any earlier change to the same move and returns `None`.
+    fn filter(&mut self, msg: Msg) -> Option<Msg> {
+        let move_id = msg.changed_move_id.expect("message has no move");
+        if self.accept(move_id, msg.mov.rating) {
+            self.held.remove(&move_id);
+            Some(msg)
+        } else {
+            self.held.insert(move_id, msg);
+            None
+        }
+    }
+
+    /// Returns the changes that were held back and not superseded by a reported change,
+    /// in the order they occurred.
+    fn into_held(self) -> Vec<Msg> {
+        let mut held = self.held.into_values().collect::<Vec<_>>();
+        held.sort_by_key(|msg| msg.mov.iteration);
+        held
+    }
 }
 
 // Computing inputs
 
-type TraceInputsFn = dyn Fn(&[usize]) -> Vec<Input> + Send;
+type TraceInputsFn = dyn Fn(&[usize]) -> Result<Vec<Input>, ReconstructError> + Send;
+type RespawnFn = dyn FnOnce(State, Option<Frontier>) -> Analysis + Send;
+
+/// The root state of an analysis, along with everything needed to replay traces from it
+/// and score the states along the way.
+struct Replay {
+    shtb: Arc<ShapeTable>,
+    cfg: Config,
+    heuristic: Option<Arc<Heuristic>>,
+    root: State,
+}
+
+impl Replay {
+    /// Performs each placement in `trace` starting from the root state, returning the
+    /// final state. See `replay_trace`.
+    fn run<'s>(&'s self, trace: &[usize], f: impl FnMut(&State, &Place<'s>)) -> State {
+        replay_trace(&self.shtb, &self.cfg, self.root.clone(), trace, f)
+    }
+
+    /// Returns the score of `state`, using the custom heuristic if there is one.
+    fn score(&self, state: &State) -> i64 {
+        match &self.heuristic {
+            Some(h) => h(state.matrix()),
+            None => board_rating(&self.cfg.parameters, state),
+        }
+    }
+}
+
+/// Performs each placement in `trace` starting from `state0`, returning the final state.
+/// `f` is called on every placement along with the state just before placing it. Panics
+/// if the trace is invalid; see `try_replay_trace`.
+fn replay_trace<'s>(
+    shtb: &'s ShapeTable,
+    cfg: &Config,
+    state0: State,
+    trace: &[usize],
+    f: impl FnMut(&State, &Place<'s>),
+) -> State {
+    try_replay_trace(shtb, cfg, state0, trace, f).unwrap_or_else(|err| panic!("{}", err))
+}
 
-fn reconstruct_inputs(shtb: &ShapeTable, state0: State, trace: &[usize]) -> Vec<Input> {
-    let mut pfind = PlaceFinder::new(&shtb);
-    let mut ffind = FinesseFinder::new();
+/// Like `replay_trace`, but returns an error if an index in the trace is out of range
+/// instead of panicking.
+fn try_replay_trace<'s>(
+    shtb: &'s ShapeTable,
+    cfg: &Config,
+    state0: State,
+    trace: &[usize],
+    mut f: impl FnMut(&State, &Place<'s>),
+) -> Result<State, ReconstructError> {
+    let mut pfind = PlaceFinder::new(&shtb, cfg.movement)
+        .with_order(cfg.placement_order)
+        .with_kick_system(cfg.kick_system)
+        .with_max_soft_drop(cfg.max_soft_drop)
+        .with_require_clear(cfg.require_clear);
     let mut state = state0;
-    let mut inputs = vec![];
     for &idx in trace {
         let pl = state
             .placements(&mut pfind)
             .find(|pl| pl.idx == idx)
-            .expect("trace idx out of range");
-        if pl.did_hold {
-            inputs.push(Input::Hold);
-        }
-        inputs.extend(
-            ffind
-                .find(state.matrix(), pl.shape, pl.normal())
-                .expect("finesse finder failed"),
-        );
-        inputs.push(Input::HD);
+            .ok_or(ReconstructError::TraceIndexOutOfRange(idx))?;
+        f(&state, &pl);
         state.place(&pl);
     }
-    inputs
+    Ok(state)
+}
+
+/// Computes the inputs to perform the placements in `trace` starting from `state0`.
+/// Panics if they can't be reconstructed; see `try_reconstruct_inputs`.
+fn reconstruct_inputs(
+    shtb: &ShapeTable,
+    cfg: &Config,
+    state0: State,
+    trace: &[usize],
+) -> Vec<Input> {
+    try_reconstruct_inputs(shtb, cfg, state0, trace).unwrap_or_else(|err| panic!("{}", err))
+}
+
+/// Like `reconstruct_inputs`, but returns an error instead of panicking.
+fn try_reconstruct_inputs(
+    shtb: &ShapeTable,
+    cfg: &Config,
+    state0: State,
+    trace: &[usize],
+) -> Result<Vec<Input>, ReconstructError> {
+    let mut ffind = FinesseFinder::new(cfg.movement)
+        .with_costs(cfg.finesse)
+        .with_kick_system(cfg.kick_system);
+    let mut inputs = vec![];
+    let mut failed = None;
+    let mut step = 0;
+    try_replay_trace(shtb, cfg, state0.clone(), trace, |state, pl| {
+        if failed.is_none() {
+            match ffind.find(state.matrix(), pl.shape, pl.normal()) {
+                Ok(finesse) => {
+                    if pl.did_hold {
+                        inputs.push(Input::Hold);
+                    }
+                    inputs.extend(finesse);
+                    inputs.push(Input::HD);
+                }
+                Err(_) => failed = Some(ReconstructError::FinesseFailed(step)),
+            }
+        }
+        step += 1;
+    })?;
+    if let Some(err) = failed {
+        return Err(err);
+    }
+    Ok(inputs)
+}
+
+/// Simulates pressing `inputs` on the next piece of `state`, starting from its spawn
+/// location, up to and including the first hard drop. Returns the resulting placement
+/// and the number of inputs consumed, or `None` if an input was blocked or out of place,
+/// or if the inputs ran out before a hard drop.
+fn apply_inputs<'s>(
+    shtb: &'s ShapeTable,
+    kick_system: KickSystem,
+    state: &State,
+    inputs: &[Input],
+) -> Option<(Place<'s>, usize)> {
+    let matrix = state.matrix();
+    let (current, hold) = state.next();
+    let (shape, did_hold) = match inputs.first() {
+        Some(Input::Hold) => (shtb.shape(hold?)?, true),
+        _ => (shtb.shape(current?)?, false),
+    };
+    let mut tf = (matrix.rows() as i16, shape.spawn_col(), Orientation::R0);
+    for (i, &input) in inputs.iter().enumerate() {
+        match input {
+            Input::Hold if i == 0 => {}
+            Input::Hold => return None,
+            Input::SD => tf = shape.sonic_drop(matrix, tf),
+            Input::HD => {
+                let tf = shape.sonic_drop(matrix, tf);
+                return Some((Place::new(shape, tf, did_hold), i + 1));
+            }
+            _ => tf = shape.try_input(matrix, tf, input, kick_system)?,
+        }
+    }
+    None
+}
+
+/// Returns `true` if pressing `inputs` starting from `state0` performs exactly the
+/// placements in `trace`. Returns `false` if the trace itself is invalid.
+fn verify_inputs(
+    shtb: &ShapeTable,
+    cfg: &Config,
+    state0: State,
+    trace: &[usize],
+    inputs: &[Input],
+) -> bool {
+    let mut inputs = inputs;
+    let mut ok = true;
+    let replayed = try_replay_trace(shtb, cfg, state0, trace, |state, pl| {
+        ok = ok
+            && match apply_inputs(shtb, cfg.kick_system, state, inputs) {
+                Some((actual, n)) => {
+                    inputs = &inputs[n..];
+                    actual.did_hold == pl.did_hold && actual.normal() == pl.normal()
+                }
+                None => false,
+            };
+    });
+    replayed.is_ok() && ok && inputs.is_empty()
+}
+
+/// Returns `true` if the inputs reconstructed for the placements in `trace`, starting
+/// from `root`, reproduce exactly those placements when pressed. This is a check against
+/// bugs in finesse; it is `false` if the inputs can't be reconstructed at all, or if the
+/// trace is invalid.
+pub fn verify_trace(shtb: &ShapeTable, cfg: &Config, root: State, trace: &[usize]) -> bool {
+    match try_reconstruct_inputs(shtb, cfg, root.clone(), trace) {
+        Ok(inputs) => verify_inputs(shtb, cfg, root, trace, &inputs),
+        Err(_) => false,
+    }
 }
 
 // Putting it all together
 
+/// Optional settings for an analysis spawned by `spawn_with`.
+#[derive(Default)]
+pub struct SpawnOptions {
+    /// Channel to send every suggestion encountered to, see `AI::listen_all`.
+    pub all_suggestions_tx: Option<mpsc::Sender<Suggestion>>,
+    /// Heuristic to rate matrices with instead of the built-in evaluation function, see
+    /// `AI::set_heuristic`.
+    pub heuristic: Option<Arc<Heuristic>>,
+    /// Frontier of a suspended search to continue from, see `AI::resume`.
+    pub resume: Option<Frontier>,
+}
+
 /// Spawns a new analysis, returning a handle to it.
-pub fn spawn(
-    shtb: Arc<ShapeTable>,
-    cfg: Config,
-    root: State,
-    all_suggestions_tx: Option<mpsc::Sender<Suggestion>>,
-) -> Analysis {
+pub fn spawn(shtb: Arc<ShapeTable>, cfg: Config, root: State) -> Analysis {
+    spawn_with(shtb, cfg, root, SpawnOptions::default())
+}
+
+/// Like `spawn`, but with the optional settings in `opts`.
+pub fn spawn_with(shtb: Arc<ShapeTable>, cfg: Config, root: State, opts: SpawnOptions) -> Analysis {
+    let SpawnOptions {
+        all_suggestions_tx,
+        heuristic,
+        resume,
+    } = opts;
     let trace_inputs = {
         let shtb = shtb.clone();
         let state0 = root.clone();
-        move |t: &[usize]| reconstruct_inputs(&shtb, state0.clone(), t)
+        let cfg = cfg.clone();
+        move |t: &[usize]| try_reconstruct_inputs(&shtb, &cfg, state0.clone(), t)
+    };
+    let respawn = {
+        let shtb = shtb.clone();
+        let cfg = cfg.clone();
+        let heuristic = heuristic.clone();
+        move |new_root: State, frontier: Option<Frontier>| {
+            let frontier = frontier.and_then(|frontier| {
+                if *frontier.root() == new_root {
+                    Some(frontier)
+                } else {
+                    frontier.reroot(&shtb, &cfg, heuristic.clone(), &new_root)
+                }
+            });
+            let opts = SpawnOptions {
+                heuristic,
+                resume: frontier,
+                ..SpawnOptions::default()
+            };
+            spawn_with(shtb, cfg, new_root, opts)
+        }
     };
     let (sink, mut handle) = Analysis::new(trace_inputs);
     handle.all_tx = all_suggestions_tx;
-    std::thread::spawn(move || analysis(shtb, cfg, root, sink));
+    handle.respawn = Some(Box::new(respawn));
+    let replay = Replay {
+        shtb: shtb.clone(),
+        cfg: cfg.clone(),
+        heuristic: heuristic.clone(),
+        root: root.clone(),
+    };
+    handle.base_score = replay.score(&root);
+    handle.replay = Some(replay);
+    handle.root_queue_len = root.queue_len();
+    std::thread::spawn(move || analysis(shtb, cfg, heuristic, root, resume, sink));
     handle
 }
 
@@ -284,8 +1058,8 @@ pub fn spawn(
 mod test {
     use super::*;
 
-    fn spam_hd_traces(trace: &[usize]) -> Vec<Input> {
-        trace.iter().map(|_| Input::HD).collect()
+    fn spam_hd_traces(trace: &[usize]) -> Result<Vec<Input>, ReconstructError> {
+        Ok(trace.iter().map(|_| Input::HD).collect())
     }
 
     #[test]
@@ -296,6 +1070,7 @@ mod test {
             iteration: 1,
             rating: 1234,
             trace: vec![6, 7, 8],
+            g_h: (0, 0),
         };
         assert!(sink.send(Msg {
             changed_move_id: Some(MoveId::n(6)),
@@ -304,10 +1079,133 @@ mod test {
         assert_eq!(handle.poll(), Ok(Some(MoveId::n(6))));
         assert_eq!(handle.suggestion(MoveId::n(6), 0).rating, 1234);
         assert_eq!(handle.poll(), Ok(None));
-        sink.finish(Stats::default());
+        sink.finish(Stats::default(), None);
         assert_eq!(handle.poll(), Err(AnalysisDone));
     }
 
+    #[test]
+    fn test_analysis_best_group() {
+        let (sink, mut handle) = Analysis::new(spam_hd_traces);
+        assert!(handle.best_group(5).is_empty());
+        for &(m_id, rating) in [(0, 102), (1, 100), (2, 150), (3, 101)].iter() {
+            assert!(sink.send(Msg {
+                changed_move_id: Some(MoveId::n(m_id)),
+                mov: Move {
+                    iteration: m_id as usize,
+                    rating,
+                    trace: vec![m_id as usize],
+                    g_h: (0, 0),
+                },
+            }));
+        }
+        while let Ok(Some(_)) = handle.poll() {}
+        assert_eq!(
+            handle.best_group(2),
+            [MoveId::n(1), MoveId::n(3), MoveId::n(0)]
+        );
+        assert_eq!(handle.best_group(0), [MoveId::n(1)]);
+        assert_eq!(handle.best_group(100).len(), 4);
+    }
+
+    #[test]
+    fn test_analysis_poll_all() {
+        let (sink, mut handle) = Analysis::new(spam_hd_traces);
+        assert!(handle.poll_all().is_empty());
+        for &(m_id, iteration, rating) in [(0, 1, 100), (1, 2, 90), (0, 3, 80), (2, 4, 95)].iter() {
+            assert!(sink.send(Msg {
+                changed_move_id: Some(MoveId::n(m_id)),
+                mov: Move {
+                    iteration,
+                    rating,
+                    trace: vec![m_id as usize],
+                    g_h: (0, 0),
+                },
+            }));
+        }
+        assert_eq!(
+            handle.poll_all(),
+            [MoveId::n(0), MoveId::n(1), MoveId::n(2)]
+        );
+        assert_eq!(handle.suggestion(MoveId::n(0), 0).rating, 80);
+        assert_eq!(handle.all_moves().count(), 3);
+        assert!(handle.poll_all().is_empty());
+    }
+
+    #[test]
+    fn test_analysis_cmp_by_finesse() {
+        // the first placement of a trace `[n, ..]` takes `n` inputs
+        let (sink, mut handle) = Analysis::new(|trace: &[usize]| {
+            Ok(trace.iter().flat_map(|&n| vec![Input::Left; n]).collect())
+        });
+        for &(m_id, iteration, rating, inputs) in
+            [(0, 1, 100, 4), (1, 2, 100, 2), (2, 3, 90, 6)].iter()
+        {
+            assert!(sink.send(Msg {
+                changed_move_id: Some(MoveId::n(m_id)),
+                mov: Move {
+                    iteration,
+                    rating,
+                    trace: vec![inputs, 1],
+                    g_h: (0, 0),
+                },
+            }));
+        }
+        while let Ok(Some(_)) = handle.poll() {}
+        let sorted = |cmp: &dyn Fn(MoveId, MoveId) -> std::cmp::Ordering| {
+            let mut moves = handle.all_moves().collect::<Vec<_>>();
+            moves.sort_by(|&m, &n| cmp(m, n));
+            moves
+        };
+        assert_eq!(
+            sorted(&|m, n| handle.cmp(m, n)),
+            [MoveId::n(2), MoveId::n(0), MoveId::n(1)]
+        );
+        assert_eq!(
+            sorted(&|m, n| handle.cmp_by_finesse(m, n)),
+            [MoveId::n(2), MoveId::n(1), MoveId::n(0)]
+        );
+    }
+
+    #[test]
+    fn test_analysis_sticky_best() {
+        let (sink, mut handle) = Analysis::new(spam_hd_traces);
+        assert_eq!(handle.sticky_best(5), None);
+        let send = |m_id, iteration, rating| {
+            assert!(sink.send(Msg {
+                changed_move_id: Some(MoveId::n(m_id)),
+                mov: Move {
+                    iteration,
+                    rating,
+                    trace: vec![m_id as usize],
+                    g_h: (0, 0),
+                },
+            }));
+        };
+        send(0, 1, 100);
+        send(1, 2, 102);
+        while let Ok(Some(_)) = handle.poll() {}
+        assert_eq!(handle.sticky_best(5), Some(MoveId::n(0)));
+        // improvements within the margin don't change the sticky best
+        send(1, 3, 97);
+        while let Ok(Some(_)) = handle.poll() {}
+        assert_eq!(handle.sticky_best(5), Some(MoveId::n(0)));
+        send(1, 4, 95);
+        while let Ok(Some(_)) = handle.poll() {}
+        assert_eq!(handle.sticky_best(5), Some(MoveId::n(0)));
+        // but larger ones do
+        send(1, 5, 94);
+        while let Ok(Some(_)) = handle.poll() {}
+        assert_eq!(handle.sticky_best(5), Some(MoveId::n(1)));
+        send(0, 6, 92);
+        while let Ok(Some(_)) = handle.poll() {}
+        assert_eq!(handle.sticky_best(5), Some(MoveId::n(1)));
+        assert_eq!(handle.sticky_best(0), Some(MoveId::n(0)));
+        // the difference between extreme ratings saturates instead of overflowing
+        send(2, 7, i64::MIN);
+        while let Ok(Some(_)) = handle.poll() {}
+        assert_eq!(handle.sticky_best(i64::MAX - 1), Some(MoveId::n(2)));
+    }
+
     fn example_analysis(sink: AnalysisSink) {
         assert!(sink.send(Msg {
             changed_move_id: Some(MoveId::n(6)),
@@ -315,6 +1213,7 @@ mod test {
                 iteration: 1,
                 rating: 1234,
                 trace: vec![6, 7, 8],
+                g_h: (0, 0),
             }
         }));
         assert!(sink.send(Msg {
@@ -323,6 +1222,7 @@ mod test {
                 iteration: 2,
                 rating: 1233,
                 trace: vec![7, 8, 9, 10],
+                g_h: (0, 0),
             }
         }));
         assert!(sink.send(Msg {
@@ -331,6 +1231,7 @@ mod test {
                 iteration: 3,
                 rating: 1233,
                 trace: vec![6, 7, 9],
+                g_h: (0, 0),
             }
         }));
         assert!(sink.send(Msg {
@@ -339,10 +1240,58 @@ mod test {
                 iteration: 3,
                 rating: 1239,
                 trace: vec![6, 7],
+                g_h: (0, 0),
             }
         }));
     }
 
+    /// Runs an analysis of `root` under `cfg` to completion.
+    fn analyze(cfg: Config, root: State) -> Analysis {
+        let mut handle = spawn(Arc::new(crate::shape::srs()), cfg, root);
+        handle.wait();
+        handle
+    }
+
+    /// Returns the best move found by `handle`.
+    fn best_move(handle: &Analysis) -> MoveId {
+        handle
+            .all_moves()
+            .min_by(|&m, &n| handle.cmp(m, n))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_analysis_forcedness() {
+        use crate::{ai::Snapshot, BasicMatrix, Color};
+        let (sink, mut handle) = Analysis::new(spam_hd_traces);
+        assert_eq!(handle.forcedness(), 0.0);
+        example_analysis(sink);
+        handle.wait();
+        // two moves with identical ratings
+        assert!((handle.forcedness() - 0.5).abs() < 1e-9);
+
+        // an O piece in a matrix two columns wide has only one placement
+        let root: State = Snapshot {
+            hold: None,
+            queue: vec![Color::n('O')],
+            matrix: BasicMatrix::with_cols(2),
+        }
+        .into();
+        let handle = analyze(Config::default(), root);
+        assert_eq!(handle.all_moves().count(), 1);
+        assert_eq!(handle.forcedness(), 1.0);
+
+        let root: State = Snapshot {
+            hold: None,
+            queue: vec![Color::n('O')],
+            matrix: BasicMatrix::with_cols(10),
+        }
+        .into();
+        let handle = analyze(Config::default(), root);
+        let forcedness = handle.forcedness();
+        assert!(forcedness > 0.0 && forcedness < 0.5, "{}", forcedness);
+    }
+
     #[test]
     fn test_analysis_wait() {
         let (sink, mut handle) = Analysis::new(spam_hd_traces);
@@ -352,6 +1301,33 @@ mod test {
         assert_eq!(handle.poll(), Err(AnalysisDone));
     }
 
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_analysis_into_stream() {
+        use futures::{executor::block_on, StreamExt};
+        use std::collections::HashSet;
+        let (sink, handle) = Analysis::new(spam_hd_traces);
+        example_analysis(sink);
+        let streamed = block_on(handle.into_stream().collect::<Vec<_>>());
+        assert_eq!(streamed, vec![MoveId::n(6), MoveId::n(7), MoveId::n(6)]);
+
+        use crate::{ai::Snapshot, shape::srs, BasicMatrix, Color};
+        let root: State = Snapshot {
+            hold: None,
+            queue: "LTJS".chars().map(Color::n).collect(),
+            matrix: BasicMatrix::with_cols(10),
+        }
+        .into();
+        let cfg = Config {
+            search_limit: 2_000,
+            ..Config::default()
+        };
+        let handle = analyze(cfg.clone(), root.clone());
+        let stream = spawn(Arc::new(srs()), cfg, root).into_stream();
+        let streamed = block_on(stream.collect::<HashSet<_>>());
+        assert_eq!(streamed, handle.all_moves().collect::<HashSet<_>>());
+    }
+
     #[test]
     fn test_analysis_suggestion() {
         let (sink, mut handle) = Analysis::new(spam_hd_traces);
@@ -392,6 +1368,93 @@ mod test {
         assert_eq!(handle.cmp(MoveId::n(7), MoveId::n(6)), Less);
     }
 
+    #[test]
+    fn test_analysis_relative_rating() {
+        let (sink, mut handle) = Analysis::new(spam_hd_traces);
+        handle.base_score = 1000;
+        example_analysis(sink);
+        handle.wait();
+        for &m_id in &[MoveId::n(6), MoveId::n(7)] {
+            let rating = handle.suggestion(m_id, 0).rating;
+            assert_eq!(handle.relative_rating(m_id), rating - 1000);
+        }
+    }
+
+    #[test]
+    fn test_analysis_trace() {
+        let (sink, mut handle) = Analysis::new(spam_hd_traces);
+        example_analysis(sink);
+        handle.wait();
+        assert_eq!(handle.trace(MoveId::n(6)), &[6, 7, 9]);
+        assert_eq!(handle.trace(MoveId::n(7)), &[7, 8, 9, 10]);
+    }
+
+    #[test]
+    fn test_analysis_pieces_used() {
+        let (sink, mut handle) = Analysis::new(spam_hd_traces);
+        handle.root_queue_len = 5;
+        example_analysis(sink);
+        handle.wait();
+        assert_eq!(handle.pieces_used(MoveId::n(6)), 3);
+        assert_eq!(handle.pieces_used(MoveId::n(7)), 4);
+        assert_eq!(handle.pieces_remaining(MoveId::n(6)), 2);
+        assert_eq!(handle.pieces_remaining(MoveId::n(7)), 1);
+    }
+
+    #[test]
+    fn test_rating_filter() {
+        let mut filter = RatingFilter::new(10);
+        assert!(filter.accept(MoveId::n(0), 100));
+        assert!(filter.accept(MoveId::n(1), 150));
+        assert!(!filter.accept(MoveId::n(1), 145), "below threshold");
+        assert!(filter.accept(MoveId::n(1), 140), "cumulative change");
+        assert!(filter.accept(MoveId::n(0), 99), "new global best");
+        assert!(!filter.accept(MoveId::n(1), 135));
+        assert!(filter.accept(MoveId::n(1), 125), "large change");
+
+        let mut filter = RatingFilter::new(0);
+        assert!(filter.accept(MoveId::n(0), 100));
+        assert!(filter.accept(MoveId::n(1), 150));
+        assert!(filter.accept(MoveId::n(1), 149));
+    }
+
+    #[test]
+    fn test_analysis_min_rating_delta() {
+        use crate::{ai::Snapshot, basic_matrix, Color, Parameters};
+        let (xx, __) = (true, false);
+        let root: State = Snapshot {
+            hold: Some(Color::n('T')),
+            queue: "LJSZIO".chars().map(Color::n).collect(),
+            matrix: basic_matrix![
+                [xx, xx, __, xx, xx, xx, xx, __, xx, xx],
+                [__, xx, __, xx, xx, __, xx, __, xx, __],
+            ],
+        }
+        .into();
+        // the spin setup bonus lets deeper sequences improve on a move's first rating
+        let run = |min_rating_delta| {
+            let cfg = Config {
+                search_limit: 20_000,
+                min_rating_delta,
+                parameters: Parameters {
+                    spin_setup_bonus: 50,
+                    ..Parameters::default()
+                },
+                ..Config::default()
+            };
+            let handle = analyze(cfg, root.clone());
+            let best = best_move(&handle);
+            let mut ratings = handle
+                .all_moves()
+                .map(|m| (m, handle.suggestion(m, 0).rating))
+                .collect::<Vec<_>>();
+            ratings.sort();
+            (best, handle.suggestion(best, 0).rating, ratings)
+        };
+        // held back improvements are still reported by the end of the analysis
+        assert_eq!(run(1_000_000), run(0));
+    }
+
     #[test]
     fn test_analysis_statistics() {
         let (sink, handle) = Analysis::new(spam_hd_traces);
@@ -400,11 +1463,418 @@ mod test {
             iterations: 1,
             nodes: 2,
             time_taken: std::time::Duration::from_millis(300),
+            ..Stats::default()
         };
-        sink.finish(s.clone());
+        sink.finish(s.clone(), None);
         assert_eq!(handle.stats(), Some(s));
     }
 
+    #[test]
+    fn test_analysis_g_h() {
+        use crate::{ai::Snapshot, shape::srs, BasicMatrix, Color};
+        let root: State = Snapshot {
+            hold: None,
+            queue: "LTJS".chars().map(Color::n).collect(),
+            matrix: BasicMatrix::with_cols(10),
+        }
+        .into();
+        let mut cfg = Config {
+            search_limit: 20_000,
+            ..Config::default()
+        };
+        cfg.parameters.piece_costs = vec![(Color::n('T'), 7), (Color::n('S'), 3)]
+            .into_iter()
+            .collect();
+        let params = cfg.parameters.clone();
+        let cost =
+            |color: Color| params.piece_penalty + params.piece_costs.get(&color).unwrap_or(&0);
+        // squared column heights, so that bumpy stacks are rated worse than flat ones
+        let heuristic = |matrix: &BasicMatrix| {
+            (0..matrix.cols())
+                .map(|j| (matrix.col_height(j) as i64).pow(2))
+                .sum::<i64>()
+        };
+        let opts = SpawnOptions {
+            heuristic: Some(Arc::new(heuristic)),
+            ..SpawnOptions::default()
+        };
+        let mut handle = spawn_with(Arc::new(srs()), cfg, root, opts);
+        handle.wait();
+        assert!(handle.all_moves().count() > 1);
+        for m_id in handle.all_moves() {
+            let trace = handle.trace(m_id).to_vec();
+            let mut costs = vec![];
+            let mut last = None;
+            let end = handle.replay().run(&trace, |state, pl| {
+                costs.push(cost(pl.shape.color()));
+                last = Some(heuristic(state.matrix()));
+            });
+            let expected = if end.reached_goal() {
+                // sequences that clear the bottom row are rated by length
+                (trace.len() as i64, 0)
+            } else {
+                // the rest count the path cost and heuristic of their last two nodes
+                let g = costs.iter().sum::<i64>();
+                let parent_g = g - costs.last().unwrap();
+                (g + parent_g, heuristic(end.matrix()) + last.unwrap())
+            };
+            assert_eq!(handle.g_h(m_id), expected, "{:?}", trace);
+        }
+    }
+
+    #[test]
+    fn test_analysis_best_per_iteration() {
+        use crate::{ai::Snapshot, BasicMatrix, Color};
+        let root: State = Snapshot {
+            hold: None,
+            queue: "LTJS".chars().map(Color::n).collect(),
+            matrix: BasicMatrix::with_cols(10),
+        }
+        .into();
+        let cfg = Config {
+            search_limit: 1_000,
+            ..Config::default()
+        };
+        let handle = analyze(cfg.clone(), root.clone());
+        assert!(handle.stats().unwrap().best_per_iteration.is_empty());
+
+        let cfg = Config {
+            record_best_per_iteration: true,
+            ..cfg
+        };
+        let handle = analyze(cfg, root);
+        let stats = handle.stats().unwrap();
+        let best = &stats.best_per_iteration;
+        assert!(!best.is_empty());
+        for pair in best.windows(2) {
+            assert!(pair[0].0 < pair[1].0, "{:?}", pair);
+            assert!(pair[0].2 >= pair[1].2, "{:?}", pair);
+        }
+        let &(iteration, move_id, _) = best.last().unwrap();
+        assert!(iteration <= stats.iterations);
+        assert!(handle.moves.contains_key(&move_id));
+        assert_eq!(stats.max_depth, 4);
+    }
+
+    #[test]
+    fn test_analysis_adversarial_future() {
+        use crate::{ai::Snapshot, basic_matrix, shape::srs, Color, FutureModel, MovementModel};
+        let (xx, __) = (true, false);
+        let root: State = Snapshot {
+            hold: None,
+            queue: vec![Color::n('T')],
+            matrix: basic_matrix![[xx, __, __, xx, __, __], [__, xx, __, xx, xx, xx],],
+        }
+        .into();
+        let shtb = Arc::new(srs());
+        let best_board = |future| {
+            let cfg = Config {
+                search_limit: usize::MAX,
+                future,
+                ..Config::default()
+            };
+            let handle = analyze(cfg, root.clone());
+            let best = best_move(&handle);
+            handle.board_after(best, 1)
+        };
+        let mut pfind = PlaceFinder::new(&shtb, MovementModel::Full);
+        let mut dirty_colors = |matrix: &BasicMatrix| {
+            shtb.colors()
+                .into_iter()
+                .filter(|&c| {
+                    let state: State = Snapshot {
+                        hold: None,
+                        queue: vec![c],
+                        matrix: matrix.clone(),
+                    }
+                    .into();
+                    !state.has_clean_spot(&mut pfind)
+                })
+                .count()
+        };
+
+        // filling the gap in the middle leaves a well on the left that only some pieces fit
+        let known = best_board(FutureModel::Known);
+        assert!(dirty_colors(&known) > 0);
+        // standing the T up in the well leaves a clean spot for every piece
+        let adversarial = best_board(FutureModel::Adversarial(1));
+        assert_ne!(adversarial, known);
+        assert_eq!(dirty_colors(&adversarial), 0);
+    }
+
+    #[test]
+    fn test_analysis_on_queue_empty() {
+        use crate::{ai::Snapshot, basic_matrix, shape::srs, BagModel, Color, QueueEmptyPolicy};
+        let (xx, __) = (true, false);
+        // the bottom row is covered, so sequences don't end early by reaching the goal
+        let root: State = Snapshot {
+            hold: None,
+            queue: "LTJ".chars().map(Color::n).collect(),
+            matrix: basic_matrix![
+                [xx, xx, xx, xx, xx, xx, xx, xx, xx, __],
+                [__, __, __, __, __, __, __, __, __, xx],
+            ],
+        }
+        .into();
+        let shtb = Arc::new(srs());
+        // longest sequence searched, and longest sequence suggested
+        let longest_traces = |on_queue_empty, bag| {
+            let cfg = Config {
+                search_limit: 5_000,
+                on_queue_empty,
+                bag,
+                ..Config::default()
+            };
+            let mut search = Search::new(&shtb, &cfg, None);
+            search.start(root.clone());
+            let mut searched = 0;
+            while search.node_count() < cfg.search_limit {
+                match search.step() {
+                    Ok(Step::RatingChanged { trace, .. })
+                    | Ok(Step::SequenceRejected { trace, .. }) => {
+                        searched = std::cmp::max(searched, trace.len());
+                    }
+                    Ok(Step::Other) => {}
+                    Err(_) => break,
+                }
+            }
+            let handle = analyze(cfg, root.clone());
+            let suggested = handle
+                .all_moves()
+                .map(|m| {
+                    assert!(!handle.suggestion(m, usize::MAX).inputs.is_empty());
+                    handle.placement_trace(m, usize::MAX).len()
+                })
+                .max()
+                .unwrap();
+            (searched, suggested)
+        };
+        let bag = QueueEmptyPolicy::ContinueWithBag;
+        assert_eq!(
+            longest_traces(QueueEmptyPolicy::Stop, BagModel::SevenBag),
+            (3, 3)
+        );
+        // continues with the rest of the bag, I, O, S and Z, but only the known pieces are
+        // part of the suggestions
+        let (searched, suggested) = longest_traces(bag.clone(), BagModel::SevenBag);
+        assert!(searched > 3);
+        assert_eq!(suggested, 3);
+        // nothing is known about the pieces after the queue
+        assert_eq!(longest_traces(bag, BagModel::Random), (3, 3));
+    }
+
+    #[test]
+    fn test_analysis_stable_move_ids() {
+        use crate::{ai::Snapshot, BasicMatrix, Color, PlacementOrder};
+        let root: State = Snapshot {
+            hold: None,
+            queue: "TI".chars().map(Color::n).collect(),
+            matrix: BasicMatrix::with_cols(10),
+        }
+        .into();
+        let run = |placement_order, stable_move_ids| {
+            let cfg = Config {
+                search_limit: usize::MAX,
+                placement_order,
+                stable_move_ids,
+                ..Config::default()
+            };
+            let handle = analyze(cfg, root.clone());
+            let mut moves = handle
+                .all_moves()
+                .map(|m| {
+                    let pl = handle.first_placement(m);
+                    (m, (pl.color, pl.orientation, pl.row, pl.col, pl.did_hold))
+                })
+                .collect::<Vec<_>>();
+            moves.sort();
+            moves
+        };
+
+        let discovered = run(PlacementOrder::Discovery, true);
+        assert!(discovered.len() > 1);
+        assert_eq!(run(PlacementOrder::Discovery, true), discovered);
+        assert_eq!(run(PlacementOrder::Sorted, true), discovered);
+        // ids depend on the placement order otherwise
+        assert_ne!(
+            run(PlacementOrder::Sorted, false),
+            run(PlacementOrder::Discovery, false)
+        );
+    }
+
+    #[test]
+    fn test_analysis_require_clear() {
+        use crate::{ai::Snapshot, basic_matrix, shape::srs, Color};
+        let (xx, __) = (true, false);
+        let srs = srs();
+        let cfg = Config {
+            require_clear: true,
+            ..Config::default()
+        };
+        let analyze = |queue: &str| {
+            let root: State = Snapshot {
+                hold: None,
+                queue: queue.chars().map(Color::n).collect(),
+                matrix: basic_matrix![[xx, xx, xx, xx, xx, xx, __, __, __, __]],
+            }
+            .into();
+            analyze_deterministic(&srs, &cfg, root)
+        };
+        assert!(!analyze("I").inputs.is_empty());
+        // no placement of an O clears the bottom row
+        assert!(analyze("O").inputs.is_empty());
+    }
+
+    #[test]
+    fn test_analysis_try_suggestion() {
+        use crate::{ai::Snapshot, BasicMatrix, Color, KickSystem};
+        let analyze_queue = |cols, kick_system, queue: &str| {
+            let root: State = Snapshot {
+                hold: None,
+                queue: queue.chars().map(Color::n).collect(),
+                matrix: BasicMatrix::with_cols(cols),
+            }
+            .into();
+            let cfg = Config {
+                kick_system,
+                ..Config::default()
+            };
+            analyze(cfg, root)
+        };
+
+        let mut handle = analyze_queue(10, KickSystem::default(), "T");
+        let best = handle.all_moves().next().unwrap();
+        assert_eq!(
+            handle.try_suggestion(best, usize::MAX),
+            Ok(handle.suggestion(best, usize::MAX))
+        );
+        let mov = Move {
+            iteration: 0,
+            rating: 0,
+            trace: vec![999],
+            g_h: (0, 0),
+        };
+        handle.moves.insert(MoveId::n(999), mov);
+        assert_eq!(
+            handle.try_suggestion(MoveId::n(999), usize::MAX),
+            Err(ReconstructError::TraceIndexOutOfRange(999))
+        );
+
+        // the I piece spawns partially out of bounds, so without kicks it can't be placed
+        // anywhere, and no (unreachable) moves are offered
+        let handle = analyze_queue(4, KickSystem::None, "I");
+        assert_eq!(handle.all_moves().count(), 0);
+        let handle = analyze_queue(5, KickSystem::None, "I");
+        assert!(handle.all_moves().count() > 0);
+        for mov in handle.all_moves() {
+            assert!(handle.try_suggestion(mov, usize::MAX).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_analysis_perfect_clear_bonus() {
+        use crate::{ai::Snapshot, basic_matrix, Color, Parameters};
+        let (xx, __) = (true, false);
+        let root: State = Snapshot {
+            hold: None,
+            queue: "OIT".chars().map(Color::n).collect(),
+            matrix: basic_matrix![
+                [xx, xx, xx, xx, xx, xx, xx, xx, __, __],
+                [xx, xx, xx, xx, __, __, __, __, __, __],
+            ],
+        }
+        .into();
+        assert!(!root.is_empty());
+        let best = |perfect_clear_bonus| {
+            let cfg = Config {
+                search_limit: usize::MAX,
+                parameters: Parameters {
+                    perfect_clear_bonus,
+                    ..Parameters::default()
+                },
+                ..Config::default()
+            };
+            let handle = analyze(cfg, root.clone());
+            let best = best_move(&handle);
+            (
+                handle.pieces_used(best),
+                handle.board_after(best, usize::MAX),
+            )
+        };
+
+        // the O clears the bottom row right away, but leaves the top row behind
+        let (pieces, board) = best(0);
+        assert_eq!(pieces, 1);
+        assert!(board.rows() > 0);
+        // the I and then the O empty the board, even though the T is still in the queue
+        let (pieces, board) = best(100);
+        assert_eq!(pieces, 2);
+        assert_eq!(board.rows(), 0);
+    }
+
+    #[test]
+    fn test_analysis_trace_hook() {
+        use crate::{ai::Snapshot, BasicMatrix, Color};
+        let root: State = Snapshot {
+            hold: None,
+            queue: "LTJS".chars().map(Color::n).collect(),
+            matrix: BasicMatrix::with_cols(10),
+        }
+        .into();
+        let messages = Arc::new(Mutex::new(vec![]));
+        let hook = {
+            let messages = messages.clone();
+            TraceHook::new(move |msg| messages.lock().unwrap().push(msg.to_string()))
+        };
+        let cfg = Config {
+            search_limit: 1_000,
+            trace_hook: Some(hook),
+            ..Config::default()
+        };
+        assert_eq!(cfg.clone(), cfg);
+        analyze(cfg, root);
+        let messages = messages.lock().unwrap();
+        assert!(!messages.is_empty());
+        assert!(
+            messages.iter().all(|msg| msg.contains("iter")),
+            "{:?}",
+            messages
+        );
+    }
+
+    #[test]
+    fn test_analyze_deterministic() {
+        use crate::{ai::Snapshot, basic_matrix, shape::srs, Color};
+        let (xx, __) = (true, false);
+        let srs = srs();
+        let cfg = Config {
+            search_limit: 5_000,
+            ..Config::default()
+        };
+        let root: State = Snapshot {
+            hold: Some(Color::n('T')),
+            queue: "LJSZIO".chars().map(Color::n).collect(),
+            matrix: basic_matrix![
+                [xx, xx, __, xx, xx, xx, xx, __, xx, xx],
+                [__, xx, __, xx, xx, __, xx, __, xx, __],
+            ],
+        }
+        .into();
+        let first = analyze_deterministic(&srs, &cfg, root.clone());
+        let second = analyze_deterministic(&srs, &cfg, root.clone());
+        assert!(!first.inputs.is_empty());
+        assert_eq!(first, second);
+
+        let empty_queue = State::from(Snapshot {
+            hold: None,
+            queue: vec![],
+            matrix: root.matrix().clone(),
+        });
+        let sugg = analyze_deterministic(&srs, &cfg, empty_queue.clone());
+        assert!(sugg.inputs.is_empty());
+        assert_eq!(sugg.rating, board_rating(&cfg.parameters, &empty_queue));
+    }
+
     #[test]
     fn test_analysis_all_suggestions() {
         let (sink, mut handle) = Analysis::new(spam_hd_traces);
@@ -436,6 +1906,544 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_analysis_hard_drop_only() {
+        use crate::{ai::Snapshot, basic_matrix, shape::srs, Color, MovementModel};
+        let (xx, __) = (true, false);
+        let root: State = Snapshot {
+            hold: None,
+            queue: "TSZ".chars().map(Color::n).collect(),
+            matrix: basic_matrix![
+                [xx, xx, xx, xx, __, __, xx, xx, xx, xx],
+                [__, __, __, xx, __, __, xx, __, __, __],
+            ],
+        }
+        .into();
+        let cfg = Config {
+            search_limit: 1_000,
+            movement: MovementModel::HardDropOnly,
+            ..Config::default()
+        };
+        let (all_tx, all_rx) = mpsc::channel();
+        let opts = SpawnOptions {
+            all_suggestions_tx: Some(all_tx),
+            ..SpawnOptions::default()
+        };
+        let mut handle = spawn_with(Arc::new(srs()), cfg, root, opts);
+        handle.wait();
+        std::mem::drop(handle);
+        let suggestions = all_rx.iter().collect::<Vec<_>>();
+        assert!(!suggestions.is_empty());
+        for sugg in suggestions {
+            assert!(!sugg.inputs.contains(&Input::SD), "{:?}", sugg.inputs);
+        }
+    }
+
+    #[test]
+    fn test_analysis_custom_heuristic() {
+        use crate::{ai::Snapshot, shape::srs, BasicMatrix, Color};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        let root: State = Snapshot {
+            hold: None,
+            queue: vec![Color::n('I')],
+            matrix: BasicMatrix::with_cols(10),
+        }
+        .into();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let heuristic = {
+            let calls = calls.clone();
+            move |mat: &BasicMatrix| {
+                calls.fetch_add(1, Ordering::Relaxed);
+                mat.rows() as i64
+            }
+        };
+        let cfg = Config::default();
+        let opts = SpawnOptions {
+            heuristic: Some(Arc::new(heuristic)),
+            ..SpawnOptions::default()
+        };
+        let mut handle = spawn_with(Arc::new(srs()), cfg, root, opts);
+        handle.wait();
+        assert!(calls.load(Ordering::Relaxed) > 0);
+        let best = best_move(&handle);
+        // flat I piece: h = 1 row, g = 1 piece penalty, parent (root) h = 0
+        let piece_penalty = Config::default().parameters.piece_penalty;
+        assert_eq!(handle.suggestion(best, 0).rating, piece_penalty + 1);
+        assert_eq!(handle.relative_rating(best), piece_penalty + 1);
+    }
+
+    #[test]
+    fn test_analysis_prune_margin() {
+        use crate::{ai::Snapshot, basic_matrix, Color};
+        let (xx, __) = (true, false);
+        let root: State = Snapshot {
+            hold: None,
+            queue: "LTS".chars().map(Color::n).collect(),
+            matrix: basic_matrix![
+                [xx, xx, xx, __, xx, xx, xx, xx, xx, xx],
+                [xx, xx, xx, xx, xx, xx, __, xx, xx, xx],
+                [__, xx, xx, xx, xx, xx, xx, xx, xx, xx],
+            ],
+        }
+        .into();
+        let run = |prune_margin| {
+            let cfg = Config {
+                search_limit: usize::MAX,
+                prune_margin,
+                ..Config::default()
+            };
+            let handle = analyze(cfg, root.clone());
+            let best = best_move(&handle);
+            let stats = handle.stats().unwrap();
+            (
+                stats.iterations,
+                stats.pruned,
+                handle.suggestion(best, 0).rating,
+            )
+        };
+        let (full_iters, full_pruned, full_rating) = run(None);
+        let (pruned_iters, pruned, pruned_rating) = run(Some(10));
+        assert!(pruned_iters < full_iters);
+        assert_eq!(pruned_rating, full_rating);
+        assert_eq!(full_pruned, 0);
+        assert!(pruned > 0);
+    }
+
+    #[test]
+    fn test_analysis_max_depth() {
+        use crate::{ai::Snapshot, BasicMatrix, Color};
+        let root: State = Snapshot {
+            hold: None,
+            queue: "LTJSZIO".chars().map(Color::n).collect(),
+            matrix: BasicMatrix::with_cols(10),
+        }
+        .into();
+        let cfg = Config {
+            search_limit: 2_000,
+            max_depth: Some(3),
+            ..Config::default()
+        };
+        let handle = analyze(cfg, root);
+        assert!(handle.all_moves().next().is_some());
+        for m in handle.all_moves() {
+            let len = handle.moves[&m].trace.len();
+            assert!(len <= 3, "trace too long: {}", len);
+        }
+    }
+
+    #[test]
+    fn test_analysis_forced_first() {
+        use crate::{ai::Snapshot, shape::srs, BasicMatrix, Color, MovementModel};
+        let root: State = Snapshot {
+            hold: None,
+            queue: "LTJS".chars().map(Color::n).collect(),
+            matrix: BasicMatrix::with_cols(10),
+        }
+        .into();
+        let shtb = Arc::new(srs());
+        let mut pfind = PlaceFinder::new(&shtb, MovementModel::Full);
+        let pl = root.placements(&mut pfind).nth(5).unwrap();
+        let forced = Placement::from(&pl);
+        let cfg = Config {
+            search_limit: 1_000,
+            forced_first: Some(forced),
+            ..Config::default()
+        };
+        let handle = analyze(cfg, root);
+        assert_eq!(handle.all_moves().count(), 1);
+        for m in handle.all_moves() {
+            assert_eq!(handle.trace(m)[0], pl.idx);
+        }
+    }
+
+    #[test]
+    fn test_analysis_resume() {
+        use crate::{ai::Snapshot, shape::srs, BasicMatrix, Color};
+        let root: State = Snapshot {
+            hold: None,
+            queue: "LTJSZ".chars().map(Color::n).collect(),
+            matrix: BasicMatrix::with_cols(10),
+        }
+        .into();
+        let shtb = Arc::new(srs());
+        let cfg = Config {
+            search_limit: 500,
+            keep_frontier: true,
+            ..Config::default()
+        };
+        let mut handle = analyze(cfg.clone(), root.clone());
+        let frontier = handle.take_frontier().unwrap();
+        assert_eq!(*frontier.root(), root);
+        assert!(handle.take_frontier().is_none());
+
+        let cfg = Config {
+            search_limit: 1_000,
+            ..cfg
+        };
+        let opts = SpawnOptions {
+            resume: Some(frontier),
+            ..SpawnOptions::default()
+        };
+        let mut resumed = spawn_with(shtb, cfg, root, opts);
+        resumed.wait();
+        // moves found before suspending are still known
+        for m in handle.all_moves() {
+            assert!(resumed.moves[&m].rating <= handle.moves[&m].rating);
+        }
+        assert!(resumed.take_frontier().is_some());
+    }
+
+    #[test]
+    fn test_analysis_reroot() {
+        use crate::{ai::Snapshot, BasicMatrix, Color};
+        let root: State = Snapshot {
+            hold: None,
+            queue: "LTJSZ".chars().map(Color::n).collect(),
+            matrix: BasicMatrix::with_cols(10),
+        }
+        .into();
+        let cfg = Config {
+            search_limit: 500,
+            keep_frontier: true,
+            ..Config::default()
+        };
+        let mut handle = analyze(cfg, root);
+        let best = best_move(&handle);
+        let trace = handle.trace(best).to_vec();
+        let child = handle.replay().run(&trace[..1], |_, _| ());
+
+        handle.reroot(child);
+        // the rest of the best sequence is reported before anything else
+        let first = loop {
+            if let Some(m) = handle.poll().unwrap() {
+                break m;
+            }
+        };
+        assert_eq!(handle.trace(first), &trace[1..]);
+        handle.wait();
+        let best = best_move(&handle);
+        assert!(handle.moves[&best].rating <= handle.moves[&first].rating);
+    }
+
+    #[test]
+    fn test_analysis_board_after() {
+        use crate::{ai::Snapshot, basic_matrix, shape::srs, Color};
+        let (xx, __) = (true, false);
+        let matrix = basic_matrix![
+            [xx, xx, xx, __, xx, xx, xx, xx, xx, xx],
+            [xx, xx, xx, xx, xx, xx, __, xx, xx, xx],
+        ];
+        let root: State = Snapshot {
+            hold: None,
+            queue: "LTIO".chars().map(Color::n).collect(),
+            matrix: matrix.clone(),
+        }
+        .into();
+        let shtb = Arc::new(srs());
+        let cfg = Config {
+            search_limit: 1_000,
+            ..Config::default()
+        };
+        let handle = analyze(cfg.clone(), root.clone());
+        let best = best_move(&handle);
+        let trace = handle.moves[&best].trace.clone();
+        let end = replay_trace(&shtb, &cfg, root.clone(), &trace, |_, _| ());
+        assert_eq!(handle.board_after(best, 0), matrix);
+        assert_eq!(handle.board_after(best, trace.len()), *end.matrix());
+        assert_eq!(handle.board_after(best, usize::MAX), *end.matrix());
+        let first = replay_trace(&shtb, &cfg, root, &trace[..1], |_, _| ());
+        assert_eq!(handle.board_after(best, 1), *first.matrix());
+    }
+
+    #[test]
+    fn test_analysis_describe() {
+        use crate::{ai::Snapshot, basic_matrix, shape::srs, Color, MovementModel};
+        let (xx, __) = (true, false);
+        let root: State = Snapshot {
+            hold: None,
+            queue: "JO".chars().map(Color::n).collect(),
+            matrix: basic_matrix![[__, __, __, xx, xx, xx, xx, xx, xx, xx]],
+        }
+        .into();
+        let shtb = Arc::new(srs());
+        let mut pfind = PlaceFinder::new(&shtb, MovementModel::Full);
+        let mut describe = |keep: &dyn Fn(&Place) -> bool| {
+            let first = root
+                .placements(&mut pfind)
+                .find(|pl| keep(pl))
+                .map(|pl| Placement::from(&pl));
+            let cfg = Config {
+                forced_first: first,
+                ..Config::default()
+            };
+            let handle = analyze(cfg, root.clone());
+            let m_id = handle.all_moves().next().unwrap();
+            handle.describe(m_id, &shtb)
+        };
+        let min_col = |pl: &Place| pl.shape.cells(pl.tf).map(|(_, j)| j).min().unwrap();
+        assert_eq!(
+            describe(&|pl| pl.tf.2 == Orientation::R0 && min_col(pl) == 0 && !pl.did_hold),
+            "Place J flat on the left, clearing 1 line"
+        );
+        assert_eq!(
+            describe(&|pl| min_col(pl) == 4 && pl.did_hold),
+            "Hold and place O flat in the middle"
+        );
+        assert_eq!(
+            describe(&|pl| pl.tf.2 == Orientation::R1 && min_col(pl) == 8 && !pl.did_hold),
+            "Place J rotated right on the right"
+        );
+    }
+
+    #[test]
+    fn test_analysis_step_deltas() {
+        use crate::{ai::Snapshot, basic_matrix, shape::srs, Color};
+        let (xx, __) = (true, false);
+        let root: State = Snapshot {
+            hold: None,
+            queue: "SZTL".chars().map(Color::n).collect(),
+            matrix: basic_matrix![
+                [xx, xx, xx, xx, __, xx, xx, xx, xx, xx],
+                [xx, __, xx, xx, xx, xx, xx, xx, xx, xx],
+                [xx, xx, xx, xx, xx, xx, __, __, xx, xx],
+            ],
+        }
+        .into();
+        let shtb = Arc::new(srs());
+        let cfg = Config {
+            search_limit: 1_000,
+            ..Config::default()
+        };
+        let handle = analyze(cfg.clone(), root.clone());
+        let rating = |s: &State| board_rating(&cfg.parameters, s);
+        for m in handle.all_moves() {
+            let trace = handle.moves[&m].trace.clone();
+            let deltas = handle.step_deltas(m);
+            assert_eq!(deltas.len(), trace.len());
+            let first = replay_trace(&shtb, &cfg, root.clone(), &trace[..1], |_, _| ());
+            assert_eq!(deltas[0], rating(&first) - rating(&root));
+            let end = replay_trace(&shtb, &cfg, root.clone(), &trace, |_, _| ());
+            assert_eq!(deltas.iter().sum::<i64>(), rating(&end) - rating(&root));
+        }
+    }
+
+    #[test]
+    fn test_analysis_peak_height() {
+        use crate::{ai::Snapshot, basic_matrix, shape::srs, Color};
+        let (xx, __) = (true, false);
+        let root: State = Snapshot {
+            hold: None,
+            queue: "II".chars().map(Color::n).collect(),
+            matrix: basic_matrix![
+                [xx, xx, xx, xx, xx, xx, xx, xx, xx, __],
+                [xx, xx, xx, xx, xx, xx, xx, xx, xx, __],
+                [xx, xx, xx, xx, xx, xx, xx, xx, xx, __],
+                [xx, xx, xx, xx, xx, xx, xx, xx, xx, __],
+            ],
+        }
+        .into();
+        let shtb = Arc::new(srs());
+        let cfg = Config::default();
+
+        // find the placement from `state` resulting in a matrix of height `rows`
+        let mut pfind = PlaceFinder::new(&shtb, cfg.movement);
+        let mut step = |state: &State, rows| {
+            state
+                .placements(&mut pfind)
+                .find(|pl| {
+                    let mut next = state.clone();
+                    next.place(pl);
+                    next.matrix().rows() == rows
+                })
+                .unwrap()
+        };
+        // spike a vertical I on top of the stack, then clear 4 lines with the other I
+        let pl1 = step(&root, 8);
+        let mut state = root.clone();
+        state.place(&pl1);
+        let pl2 = step(&state, 4);
+        let trace = vec![pl1.idx, pl2.idx];
+        let m_id = MoveId::n(pl1.idx as i32);
+
+        let (sink, mut handle) = Analysis::new(spam_hd_traces);
+        handle.replay = Some(Replay {
+            shtb,
+            cfg,
+            heuristic: None,
+            root,
+        });
+        assert!(sink.send(Msg {
+            changed_move_id: Some(m_id),
+            mov: Move {
+                iteration: 1,
+                rating: 0,
+                trace,
+                g_h: (0, 0),
+            }
+        }));
+        assert_eq!(handle.poll(), Ok(Some(m_id)));
+        assert_eq!(handle.board_after(m_id, 2).rows(), 4);
+        assert_eq!(handle.peak_height(m_id), 8);
+    }
+
+    #[test]
+    fn test_analysis_total_lines() {
+        use crate::{ai::Snapshot, basic_matrix, shape::srs, Color};
+        let (xx, __) = (true, false);
+        let root: State = Snapshot {
+            hold: None,
+            queue: "JI".chars().map(Color::n).collect(),
+            matrix: basic_matrix![
+                [xx, xx, xx, xx, xx, xx, xx, xx, xx, __],
+                [xx, xx, xx, xx, xx, xx, xx, xx, xx, __],
+                [xx, xx, xx, xx, xx, xx, xx, xx, xx, __],
+                [xx, xx, xx, xx, xx, xx, xx, xx, xx, __],
+                [xx, xx, xx, xx, xx, xx, xx, __, __, __],
+            ],
+        }
+        .into();
+        let shtb = Arc::new(srs());
+        let cfg = Config::default();
+
+        // clear the top line with the J, uncovering the well for the I
+        let mut pfind = PlaceFinder::new(&shtb, cfg.movement);
+        let pl1 = root
+            .placements(&mut pfind)
+            .find(|pl| root.rows_cleared_by(pl).len() == 1)
+            .map(|pl| Placement::from(&pl))
+            .unwrap();
+
+        let handle = analyze(cfg, root);
+        let m_id = handle
+            .all_moves()
+            .find(|&m| handle.first_placement(m) == pl1)
+            .unwrap();
+        // the best continuation is the tetris with the I
+        assert_eq!(handle.total_lines(m_id), 5);
+    }
+
+    #[test]
+    fn test_analysis_next_input() {
+        use crate::{ai::Snapshot, basic_matrix, Color};
+        let (xx, __) = (true, false);
+        let root: State = Snapshot {
+            hold: Some(Color::n('I')),
+            queue: "SZT".chars().map(Color::n).collect(),
+            matrix: basic_matrix![[xx, xx, xx, xx, __, __, xx, xx, xx, xx]],
+        }
+        .into();
+        let cfg = Config {
+            search_limit: 1_000,
+            ..Config::default()
+        };
+        let mut handle = analyze(cfg, root);
+        for m in handle.all_moves() {
+            let expected = handle.suggestion(m, 1).inputs.first().cloned();
+            assert!(expected.is_some());
+            assert_eq!(handle.next_input(m), expected);
+        }
+        let mov = Move {
+            iteration: 0,
+            rating: 0,
+            trace: vec![999],
+            g_h: (0, 0),
+        };
+        handle.moves.insert(MoveId::n(999), mov);
+        assert_eq!(handle.next_input(MoveId::n(999)), None);
+    }
+
+    #[test]
+    fn test_analysis_first_placement() {
+        use crate::{ai::Snapshot, basic_matrix, Color};
+        let (xx, __) = (true, false);
+        let root: State = Snapshot {
+            hold: None,
+            queue: "LTIO".chars().map(Color::n).collect(),
+            matrix: basic_matrix![
+                [xx, xx, xx, __, xx, xx, xx, xx, xx, xx],
+                [xx, xx, xx, xx, xx, xx, __, xx, xx, xx],
+            ],
+        }
+        .into();
+        let cfg = Config {
+            search_limit: 1_000,
+            ..Config::default()
+        };
+        let handle = analyze(cfg, root);
+        for m in handle.all_moves() {
+            let pl = handle.first_placement(m);
+            assert_eq!(pl.idx, handle.moves[&m].trace[0]);
+            let expected_color = if pl.did_hold { 'T' } else { 'L' };
+            assert_eq!(pl.color, Color::n(expected_color));
+            assert_eq!(handle.placement_trace(m, 1), vec![pl]);
+        }
+    }
+
+    #[test]
+    fn test_verify_trace() {
+        use crate::{ai::Snapshot, basic_matrix, shape::srs, Color};
+        let (xx, __) = (true, false);
+        let root: State = Snapshot {
+            hold: None,
+            queue: "LTIO".chars().map(Color::n).collect(),
+            matrix: basic_matrix![
+                [xx, xx, xx, __, xx, xx, xx, xx, xx, xx],
+                [xx, xx, xx, xx, xx, xx, __, xx, xx, xx],
+            ],
+        }
+        .into();
+        let shtb = srs();
+        let cfg = Config::default();
+        let mut pfind = PlaceFinder::new(&shtb, cfg.movement);
+        let n = root.placements(&mut pfind).count();
+        for idx in 0..n {
+            assert!(verify_trace(&shtb, &cfg, root.clone(), &[idx]), "{}", idx);
+            assert!(
+                verify_trace(&shtb, &cfg, root.clone(), &[idx, 0, 3]),
+                "{}",
+                idx
+            );
+        }
+        assert!(!verify_trace(&shtb, &cfg, root, &[n]));
+    }
+
+    #[test]
+    fn test_verify_inputs_corrupted() {
+        use crate::{ai::Snapshot, shape::srs, BasicMatrix, Color};
+        let root: State = Snapshot {
+            hold: None,
+            queue: "TJ".chars().map(Color::n).collect(),
+            matrix: BasicMatrix::with_cols(10),
+        }
+        .into();
+        let shtb = srs();
+        let cfg = Config::default();
+        let mut pfind = PlaceFinder::new(&shtb, cfg.movement);
+        let idx = root
+            .placements(&mut pfind)
+            .find(|pl| !pl.did_hold && pl.tf.1 != pl.shape.spawn_col())
+            .unwrap()
+            .idx;
+        let trace = [idx];
+        let mut inputs = reconstruct_inputs(&shtb, &cfg, root.clone(), &trace);
+        assert!(verify_inputs(&shtb, &cfg, root.clone(), &trace, &inputs));
+        // skip the last input before the hard drop
+        inputs.remove(inputs.len() - 2);
+        assert!(!verify_inputs(&shtb, &cfg, root.clone(), &trace, &inputs));
+        // hard drop too early
+        assert!(!verify_inputs(
+            &shtb,
+            &cfg,
+            root.clone(),
+            &trace,
+            &[Input::HD]
+        ));
+        // trailing inputs
+        let mut inputs = reconstruct_inputs(&shtb, &cfg, root.clone(), &trace);
+        inputs.push(Input::HD);
+        assert!(!verify_inputs(&shtb, &cfg, root, &trace, &inputs));
+    }
+
     #[test]
     fn test_analysis_is_send() {
         let (_, handle) = Analysis::new(spam_hd_traces);
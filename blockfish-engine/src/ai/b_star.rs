@@ -1,16 +1,27 @@
 use super::{
-    eval::{eval, penalty},
+    eval::{blocks_well, eval, fits_notch, has_spin_slot, is_i_well_tetris, penalty},
     state::State,
+    Heuristic, Snapshot,
 };
 use crate::{
-    config::Parameters,
+    config::{BagModel, Config, FutureModel, Parameters, QueueEmptyPolicy},
+    matrix::BasicMatrix,
     place::{Place, PlaceFinder},
-    shape::ShapeTable,
+    shape::{NormalizedShapeTransform, ShapeTable},
+    Color,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BinaryHeap, HashMap},
+    sync::Arc,
 };
-use std::collections::{BinaryHeap, HashMap};
 
 // Search algorithm
 
+/// Best rating found for a move, along with its split into `(g, h)` (see `Node::g_h`) and
+/// the trace of the sequence that achieved it.
+type MoveBest = (i64, (i64, i64), Vec<usize>);
+
 /// An instance of the "B*" search algorithm.
 ///
 /// B* is a slight modification of A* to better fit the needs of blockfish. The primary
@@ -48,26 +59,72 @@ use std::collections::{BinaryHeap, HashMap};
 ///   placed pieces, in order to prioritize short sequences at the very end of the cheese
 ///   race.
 pub struct Search<'s> {
+    // shapes of the pieces, for rating the held piece and spotting spin slots
+    shape_table: &'s ShapeTable,
     // parameters to the heuristic function
     params: Parameters,
-    // holds the best rating for each move
-    move_best: HashMap<MoveId, i64>,
+    // custom heuristic function to use instead of the built-in one, if any
+    heuristic: Option<Arc<Heuristic>>,
+    // "h" values already computed for each matrix, so that transpositions aren't
+    // evaluated again
+    h_cache: HCache,
+    // rates states at the end of the queue under `FutureModel::Adversarial`
+    adversary: Option<Adversary<'s>>,
+    // nodes whose f value is worse than the best at their level by more than this margin
+    // are discarded
+    prune_margin: Option<i64>,
+    // best f value of any node pushed to each depth level
+    lvl_best_f: Vec<i64>,
+    // nodes with traces of this length are treated as terminal, if set
+    max_depth: Option<usize>,
+    // the only placement allowed from the root node, if set
+    forced_first: Option<(Color, NormalizedShapeTransform)>,
+    // if set, move ids are derived from the root placements (see `root_move_ids`)
+    stable_move_ids: bool,
+    // id of the move for each root placement index, if `stable_move_ids` is set
+    root_move_ids: Vec<MoveId>,
+    // holds the best rating for each move, along with its split into path cost and
+    // heuristic and the trace that achieved it
+    move_best: HashMap<MoveId, MoveBest>,
+    // root state of the search
+    root: Option<State>,
+    // bag to speculate on once the known queue is used up, if any (see
+    // `QueueEmptyPolicy::ContinueWithBag`)
+    speculate: Option<BagModel>,
+    // pieces dealt after the known queue, and the number of pieces in the root state
+    // (including the hold piece) after which they are dealt
+    speculative: Vec<Color>,
+    known_len: usize,
     // fringe set for each depth level
     lvls: Vec<BinaryHeap<Node>>,
     // index of current depth level either being selected from or expanded into
     lvl_idx: usize,
     // current node being expanded
     node: Option<Node>,
+    // number of successors of `node` that have been generated so far
+    node_expanded: usize,
     // placement generator; only used when `node` is not `None`
     pfind: PlaceFinder<'s>,
     // total number of nodes generated
     node_count: usize,
+    // number of successors discarded without being added to the fringe set
+    prune_count: usize,
+    // number of nodes expanded so far
+    expand_count: usize,
+    // receives an event for every expanded node, if set
+    step_sink: Option<Box<dyn FnMut(StepEvent) + 's>>,
 }
 
 /// Opaque identifier that indicates a "move" -- the next placement one make after the
 /// initial state.
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
-pub struct MoveId(u8);
+///
+/// By default, ids are assigned in the order that placements are generated. If
+/// `Config::stable_move_ids` is set, the id is instead derived from the color and the
+/// normalized position and orientation of the placement. Moves that share a column and
+/// orientation but land on different rows (e.g. a tuck under an overhang) are told apart
+/// by their row, so ids never collide on matrices of up to `MAX_ROWS` rows.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub struct MoveId(u64);
 
 /// Indicates what happened as a result of a step of the algorithm. Returned by
 /// `Search::step()`.
@@ -82,6 +139,8 @@ pub enum Step {
         trace: Vec<usize>,
         /// The new rating for this move.
         rating: i64,
+        /// The rating split into the path cost and the heuristic, see `Node::g_h`.
+        g_h: (i64, i64),
     },
 
     /// Indicates a node was discovered but was rejected since it is not better than the
@@ -91,39 +150,315 @@ pub enum Step {
         trace: Vec<usize>,
         /// The sequence's rating.
         rating: i64,
+        /// The rating split into the path cost and the heuristic, see `Node::g_h`.
+        g_h: (i64, i64),
     },
 
     Other,
 }
 
+/// Describes a node being expanded by the search. Used for profiling the search via
+/// `Search::trace_steps()`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StepEvent {
+    /// Number of nodes expanded before this one.
+    pub index: usize,
+    /// Length of the node's trace.
+    pub depth: usize,
+    /// Path cost of the node.
+    pub g: i64,
+    /// Heuristic value of the node.
+    pub h: i64,
+    /// Overall evaluation of the node, i.e. `g + h` minus any rewards.
+    pub f: i64,
+}
+
+/// The suspended state of a search, which can be used to resume it later via
+/// `Search::resume()`. Holds every node that has not been expanded yet.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Frontier {
+    root: State,
+    lvls: Vec<Vec<Node>>,
+    lvl_idx: usize,
+    lvl_best_f: Vec<i64>,
+    node: Option<Node>,
+    node_expanded: usize,
+    move_best: Vec<(MoveId, MoveBest)>,
+    node_count: usize,
+    prune_count: usize,
+    expand_count: usize,
+}
+
+impl Frontier {
+    /// Returns the root state of the suspended search.
+    pub fn root(&self) -> &State {
+        &self.root
+    }
+
+    /// Returns the best rating found for each move so far, along with its split into path
+    /// cost and heuristic and the trace of the sequence that achieved it.
+    pub fn best_moves(&self) -> impl Iterator<Item = (MoveId, i64, (i64, i64), &[usize])> {
+        self.move_best
+            .iter()
+            .map(|(m_id, (rating, g_h, trace))| (*m_id, *rating, *g_h, trace.as_slice()))
+    }
+
+    /// Converts this frontier into one for a search rooted at `new_root`, which should be
+    /// the state after one of the root's placements, e.g. once the first piece of the
+    /// suggested move has been placed. The nodes below that placement are kept, with
+    /// their evaluations adjusted to the new root, and the best sequence found for that
+    /// placement is kept as the best sequence of its next move. The rest of the search is
+    /// discarded, and statistics start over. Returns `None` if no placement of the root
+    /// leads to `new_root`. `cfg` and `heuristic` should be the same as the original
+    /// search's.
+    pub fn reroot(
+        self,
+        shape_table: &ShapeTable,
+        cfg: &Config,
+        heuristic: Option<Arc<Heuristic>>,
+        new_root: &State,
+    ) -> Option<Frontier> {
+        let mut search = Search::new(shape_table, cfg, heuristic);
+        search.init_root(&self.root);
+        // the goal flag is ignored, since a new root never starts out having reached it
+        let old_root = Node::root(0, self.root.clone());
+        let placements = self.root.placements(&mut search.pfind).collect::<Vec<_>>();
+        let child = placements
+            .iter()
+            .map(|pl| search.succ(&old_root, pl))
+            .find(|node| {
+                node.state.matrix() == new_root.matrix()
+                    && node.state.hold() == new_root.hold()
+                    && node.state.queue().eq(new_root.queue())
+            })?;
+        let child_idx = child.trace[0];
+        search.init_root(new_root);
+        let root = Node::root(search.h(new_root), new_root.clone());
+
+        // removes the child's placement from the front of the node's trace. the path cost
+        // and rewards are adjusted to the shorter trace, but the evaluation of the state is
+        // the same.
+        let shift = |node: Node| {
+            if node.trace.len() < 2 || node.trace[0] != child_idx {
+                return None;
+            }
+            // the child is an ancestor of both the node and its parent, so its path cost
+            // and rewards are part of both of their evaluations
+            Some(Node {
+                g: node.g - child.g,
+                parent_g: node.parent_g - child.g,
+                h: node.h.saturating_add(child.reward),
+                parent_h: node.parent_h.saturating_add(child.reward),
+                reward: node.reward - child.reward,
+                trace: node.trace[1..].to_vec(),
+                state: node.state,
+            })
+        };
+        let child_unexpanded = self
+            .lvls
+            .first()
+            .map_or(false, |lvl| lvl.iter().any(|n| n.trace == [child_idx]));
+        let mut lvls = self
+            .lvls
+            .into_iter()
+            .skip(1)
+            .map(|lvl| lvl.into_iter().filter_map(&shift).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        while lvls.last().map_or(false, Vec::is_empty) {
+            lvls.pop();
+        }
+        let lvl_best_f = lvls
+            .iter()
+            .map(|lvl| lvl.iter().map(Node::f).min().unwrap_or(i64::MAX))
+            .collect();
+        let node_count = lvls.iter().map(Vec::len).sum();
+        // continue expanding the node that was being expanded if it is still part of the
+        // search, or else the new root if it was never expanded
+        let (node, node_expanded) = match self.node {
+            Some(node) if node.trace == [child_idx] => (Some(root.clone()), self.node_expanded),
+            Some(node) => match shift(node) {
+                Some(node) => (Some(node), self.node_expanded),
+                None if child_unexpanded => (Some(root.clone()), 0),
+                None => (None, 0),
+            },
+            None if child_unexpanded => (Some(root.clone()), 0),
+            None => (None, 0),
+        };
+        let lvl_idx = node.as_ref().map_or(0, |n| n.trace.len());
+
+        // replay the best sequence below the child from the new root to rate it from there
+        let best = self
+            .move_best
+            .into_iter()
+            .find(|(_, (_, _, trace))| trace.len() >= 2 && trace[0] == child_idx as usize);
+        let move_best = best.and_then(|(_, (_, _, trace))| {
+            let mut node = root;
+            let mut first = None;
+            for &idx in &trace[1..] {
+                let pl = node
+                    .state
+                    .placements(&mut search.pfind)
+                    .find(|pl| pl.idx == idx)?;
+                if first.is_none() {
+                    first = Some(if search.stable_move_ids {
+                        MoveId::of(&pl)
+                    } else {
+                        MoveId(pl.idx as u64)
+                    });
+                }
+                node = search.succ(&node, &pl);
+            }
+            let rating = node.rating(&search.params);
+            Some((
+                first?,
+                (rating, node.g_h(&search.params), node.trace().collect()),
+            ))
+        });
+
+        Some(Frontier {
+            root: new_root.clone(),
+            lvls,
+            lvl_idx,
+            lvl_best_f,
+            node,
+            node_expanded,
+            move_best: move_best.into_iter().collect(),
+            node_count,
+            prune_count: 0,
+            expand_count: 0,
+        })
+    }
+}
+
 /// Indicates that the search is over since there are no more placements left to analyze.
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub struct SearchTerminated;
 
 impl<'s> Search<'s> {
-    /// Constructs a new instance of the "B*" search algorithm.
-    pub fn new(shape_table: &'s ShapeTable, params: Parameters) -> Self {
+    /// Constructs a new instance of the "B*" search algorithm, using the parameters,
+    /// movement model, placement order, pruning margin and maximum depth from `cfg`. If
+    /// `heuristic` is `Some`, it is used to compute the "h" value of nodes instead of the
+    /// built-in evaluation function.
+    pub fn new(
+        shape_table: &'s ShapeTable,
+        cfg: &Config,
+        heuristic: Option<Arc<Heuristic>>,
+    ) -> Self {
         Self {
-            params,
+            shape_table,
+            params: cfg.parameters.clone(),
+            heuristic,
+            h_cache: HCache::new(cfg.search_limit),
+            adversary: match cfg.future {
+                FutureModel::Known => None,
+                FutureModel::Adversarial(depth) => Some(Adversary::new(shape_table, cfg, depth)),
+            },
+            prune_margin: cfg.prune_margin,
+            lvl_best_f: Vec::with_capacity(8),
+            max_depth: cfg.max_depth,
+            forced_first: cfg.forced_first.as_ref().and_then(|pl| {
+                let shape = shape_table.shape(pl.color)?;
+                Some((pl.color, shape.normalize((pl.row, pl.col, pl.orientation))))
+            }),
+            stable_move_ids: cfg.stable_move_ids,
+            root_move_ids: vec![],
             move_best: HashMap::with_capacity(64),
+            root: None,
+            speculate: match cfg.on_queue_empty {
+                QueueEmptyPolicy::Stop => None,
+                QueueEmptyPolicy::ContinueWithBag => Some(cfg.bag.clone()),
+            },
+            speculative: vec![],
+            known_len: 0,
             lvls: Vec::with_capacity(8),
             lvl_idx: 0,
             node: None,
-            pfind: PlaceFinder::new(shape_table),
+            node_expanded: 0,
+            pfind: PlaceFinder::new(shape_table, cfg.movement)
+                .with_order(cfg.placement_order)
+                .with_kick_system(cfg.kick_system)
+                .with_max_soft_drop(cfg.max_soft_drop)
+                .with_require_clear(cfg.require_clear),
             node_count: 0,
+            prune_count: 0,
+            expand_count: 0,
+            step_sink: None,
         }
     }
 
+    /// Calls `sink` with a `StepEvent` every time a node is expanded, in the order they
+    /// are expanded. Intended for profiling the search.
+    pub fn trace_steps(&mut self, sink: impl FnMut(StepEvent) + 's) {
+        self.step_sink = Some(Box::new(sink));
+    }
+
     /// Starts the search at `root_state`.
     pub fn start(&mut self, root_state: State) {
         for lvl in self.lvls.iter_mut() {
             lvl.clear();
         }
+        self.lvl_best_f.clear();
+        self.h_cache.clear();
         self.lvl_idx = 0;
+        self.init_root(&root_state);
         root_state.placements(&mut self.pfind);
+        self.root = Some(root_state.clone());
 
-        self.node = Some(Node::root(&self.params, root_state));
+        let root = Node::root(self.h(&root_state), root_state);
         self.node_count = 1;
+        self.prune_count = 0;
+        self.node_expanded = 0;
+        self.expand_count = 0;
+        self.expanding(&root);
+        self.node = Some(root);
+    }
+
+    /// Stops the search, returning its state so that it may be resumed later. Panics if
+    /// the search was never started.
+    pub fn suspend(self) -> Frontier {
+        Frontier {
+            root: self.root.expect("search was not started"),
+            lvls: self.lvls.into_iter().map(BinaryHeap::into_vec).collect(),
+            lvl_idx: self.lvl_idx,
+            lvl_best_f: self.lvl_best_f,
+            node: self.node,
+            node_expanded: self.node_expanded,
+            move_best: self.move_best.into_iter().collect(),
+            node_count: self.node_count,
+            prune_count: self.prune_count,
+            expand_count: self.expand_count,
+        }
+    }
+
+    /// Constructs a search that continues exactly where the search that produced
+    /// `frontier` was suspended. `cfg` and `heuristic` should be the same as the original
+    /// search's.
+    pub fn resume(
+        shape_table: &'s ShapeTable,
+        cfg: &Config,
+        heuristic: Option<Arc<Heuristic>>,
+        frontier: Frontier,
+    ) -> Self {
+        let mut search = Self::new(shape_table, cfg, heuristic);
+        search.init_root(&frontier.root);
+        search.root = Some(frontier.root);
+        // the heaps were stored in their internal order, so rebuilding them does not
+        // reorder any nodes
+        search.lvls = frontier.lvls.into_iter().map(BinaryHeap::from).collect();
+        search.lvl_idx = frontier.lvl_idx;
+        search.lvl_best_f = frontier.lvl_best_f;
+        search.move_best = frontier.move_best.into_iter().collect();
+        search.node_count = frontier.node_count;
+        search.prune_count = frontier.prune_count;
+        search.expand_count = frontier.expand_count;
+        if let Some(node) = frontier.node {
+            // regenerate the successors that were already generated
+            node.state.placements(&mut search.pfind);
+            for _ in search.pfind.by_ref().take(frontier.node_expanded) {}
+            search.node = Some(node);
+            search.node_expanded = frontier.node_expanded;
+        }
+        search
     }
 
     /// Returns the total number of generated nodes.
@@ -131,26 +466,39 @@ impl<'s> Search<'s> {
         self.node_count
     }
 
+    /// Returns the number of successors that were discarded without being added to the
+    /// fringe set, either by the pruning margin or by the forced first placement.
+    pub fn prune_count(&self) -> usize {
+        self.prune_count
+    }
+
     /// Runs one iteration of the algorithm. Returns `Ok(Some(rc))` it move rating was
     /// modified, `Ok(None)` if work was performed but no ratings were modified yet, or
     /// `Err(SearchTerminated)` if there are no more nodes remaining to be processed.
     pub fn step(&mut self) -> Result<Step, SearchTerminated> {
         if let Some(node) = self.node.take() {
             // best-first iteration phase
-            if node.is_terminal() {
+            if self.is_leaf(&node) {
                 // stop at terminal nodes
                 return Ok(match self.back_up(node) {
-                    (rating, trace, Some(move_id)) => Step::RatingChanged {
+                    (rating, g_h, trace, Some(move_id)) => Step::RatingChanged {
                         move_id,
                         rating,
                         trace,
+                        g_h,
                     },
-                    (rating, trace, None) => Step::SequenceRejected { rating, trace },
+                    (rating, g_h, trace, None) => Step::SequenceRejected { rating, trace, g_h },
                 });
             }
             // expansion
             if let Some(pl) = self.pfind.next() {
-                self.push(node.succ(&self.params, &pl));
+                self.node_expanded += 1;
+                if self.is_allowed(&node, &pl) {
+                    let succ = self.succ(&node, &pl);
+                    self.push(succ);
+                } else {
+                    self.prune_count += 1;
+                }
                 self.node = Some(node);
             } else {
                 self.pop()?;
@@ -163,8 +511,105 @@ impl<'s> Search<'s> {
         Ok(Step::Other)
     }
 
-    /// Adds `node` to the fringe set at the current level index.
+    /// Prepares for a search rooted at `root`: works out which pieces are speculated to
+    /// come after its queue, resets the adversary and computes the move ids.
+    fn init_root(&mut self, root: &State) {
+        let dealt = root.queue().collect::<Vec<_>>();
+        self.speculative = match &self.speculate {
+            Some(bag) => bag.rest_of_bag(&self.shape_table.colors(), &dealt),
+            None => vec![],
+        };
+        self.known_len = root.queue_len();
+        if let Some(adversary) = self.adversary.as_mut() {
+            adversary.reset(root, &self.speculative);
+        }
+        self.init_move_ids(root);
+    }
+
+    /// Computes the stable move id for each placement of the root state `root`, if stable
+    /// move ids are enabled.
+    fn init_move_ids(&mut self, root: &State) {
+        self.root_move_ids.clear();
+        if self.stable_move_ids {
+            let ids = root.placements(&mut self.pfind).map(|pl| MoveId::of(&pl));
+            self.root_move_ids.extend(ids);
+        }
+    }
+
+    /// Returns the id of the move that starts with root placement index `idx`.
+    fn move_id(&self, idx: u8) -> MoveId {
+        match self.root_move_ids.get(idx as usize) {
+            Some(&m_id) => m_id,
+            None => MoveId(idx as u64),
+        }
+    }
+
+    /// Returns `false` if placing `pl` from `node` is ruled out by the forced first
+    /// placement.
+    fn is_allowed(&self, node: &Node, pl: &Place) -> bool {
+        match self.forced_first {
+            Some((color, normal)) if node.trace.is_empty() => {
+                pl.shape.color() == color && pl.normal() == normal
+            }
+            _ => true,
+        }
+    }
+
+    /// Generates the successor of `node` by placing `pl`, rating states at the end of the
+    /// queue with the adversary if there is one. The speculative pieces are dealt once the
+    /// known queue is used up.
+    fn succ(&mut self, node: &Node, pl: &Place) -> Node {
+        let speculative = if node.trace.len() + 1 == self.known_len {
+            &self.speculative[..]
+        } else {
+            &[]
+        };
+        let (shape_table, params, heuristic, h_cache, adversary) = (
+            self.shape_table,
+            &self.params,
+            &self.heuristic,
+            &mut self.h_cache,
+            &mut self.adversary,
+        );
+        node.succ(params, pl, speculative, |s| {
+            let h = match adversary {
+                Some(adv) if s.queue_len() == 0 && !s.reached_goal() => {
+                    adv.h(params, heuristic, h_cache, s)
+                }
+                _ => cached_h(shape_table, params, heuristic, h_cache, s),
+            };
+            h.saturating_sub(hold_bonus(shape_table, params, s))
+        })
+    }
+
+    /// Computes the "h" value (remaining cost heuristic) for `state`.
+    fn h(&mut self, state: &State) -> i64 {
+        let (shape_table, params) = (self.shape_table, &self.params);
+        let h = cached_h(
+            shape_table,
+            params,
+            &self.heuristic,
+            &mut self.h_cache,
+            state,
+        );
+        h.saturating_sub(hold_bonus(shape_table, params, state))
+    }
+
+    /// Adds `node` to the fringe set at the current level index, unless it is pruned for
+    /// being too much worse than the best node at that level.
     fn push(&mut self, node: Node) {
+        if self.lvl_best_f.len() <= self.lvl_idx {
+            self.lvl_best_f.resize(self.lvl_idx + 1, i64::MAX);
+        }
+        let best_f = &mut self.lvl_best_f[self.lvl_idx];
+        if let Some(margin) = self.prune_margin {
+            if node.f() > best_f.saturating_add(margin) {
+                self.prune_count += 1;
+                return;
+            }
+        }
+        *best_f = std::cmp::min(*best_f, node.f());
+
         let lvl = match self.lvls.get_mut(self.lvl_idx) {
             Some(lvl) => lvl,
             None => {
@@ -184,28 +629,58 @@ impl<'s> Search<'s> {
         let node = lvl.pop().ok_or(SearchTerminated)?;
         self.node_count -= 1;
         node.state.placements(&mut self.pfind);
+        self.expanding(&node);
         self.node = Some(node);
+        self.node_expanded = 0;
         self.lvl_idx += 1;
         Ok(())
     }
 
+    /// Returns `true` if `node` should not be expanded, either because it is a terminal
+    /// node or because it is at the maximum depth.
+    fn is_leaf(&self, node: &Node) -> bool {
+        node.is_terminal() || self.max_depth.map_or(false, |d| node.trace.len() >= d)
+    }
+
+    /// Notifies the step sink (if any) that `node` is about to be expanded. Terminal nodes
+    /// are not expanded, so they are ignored.
+    fn expanding(&mut self, node: &Node) {
+        if self.is_leaf(node) {
+            return;
+        }
+        if let Some(sink) = self.step_sink.as_mut() {
+            sink(StepEvent {
+                index: self.expand_count,
+                depth: node.trace.len(),
+                g: node.g,
+                h: node.h.saturating_add(node.reward),
+                f: node.f(),
+            });
+        }
+        self.expand_count += 1;
+    }
+
     /// Selects the level index corresponding to the node with best evaluation.
     fn select(&mut self) {
         self.lvl_idx = (0..self.lvls.len())
-            .min_by_key(|&i| self.lvls[i].peek().map_or(std::i64::MAX, |n| n.f))
+            .min_by_key(|&i| self.lvls[i].peek().map_or(i64::MAX, Node::f))
             .unwrap_or(0);
     }
 
     /// Propogates `node`'s rating back to the move at the root of this node.
-    fn back_up(&mut self, node: Node) -> (i64, Vec<usize>, Option<MoveId>) {
-        let rating = node.rating();
-        let trace = node.trace().collect();
+    fn back_up(&mut self, node: Node) -> (i64, (i64, i64), Vec<usize>, Option<MoveId>) {
+        let rating = node.rating(&self.params);
+        let g_h = node.g_h(&self.params);
+        let trace = node.trace().collect::<Vec<_>>();
         let move_id = match node.trace.get(0) {
             Some(&idx) => {
-                let m_id = MoveId(idx as _);
-                let best = self.move_best.entry(m_id).or_insert(std::i64::MAX);
-                if rating < *best {
-                    *best = rating;
+                let m_id = self.move_id(idx);
+                let best = self
+                    .move_best
+                    .entry(m_id)
+                    .or_insert((i64::MAX, (0, 0), vec![]));
+                if rating < best.0 {
+                    *best = (rating, g_h, trace.clone());
                     Some(m_id)
                 } else {
                     None
@@ -213,7 +688,205 @@ impl<'s> Search<'s> {
             }
             None => None,
         };
-        (rating, trace, move_id)
+        (rating, g_h, trace, move_id)
+    }
+}
+
+/// Computes the "h" value for `state` using `heuristic`, or the built-in evaluation with
+/// `params` if `None`, minus `params.spin_setup_bonus` if the matrix has a spin slot. The
+/// value is looked up in `cache` first, in case the same matrix was already reached
+/// through a different sequence of placements.
+fn cached_h(
+    shape_table: &ShapeTable,
+    params: &Parameters,
+    heuristic: &Option<Arc<Heuristic>>,
+    cache: &mut HCache,
+    state: &State,
+) -> i64 {
+    let queue_len = state.queue_len();
+    if let Some(h) = cache.get(queue_len, state.matrix()) {
+        return h;
+    }
+    let mut h = match heuristic {
+        Some(h) => h(state.matrix()),
+        None => eval(state.matrix()).score_with_queue(params, queue_len),
+    };
+    if params.spin_setup_bonus != 0 && has_spin_slot(state.matrix(), shape_table) {
+        h = h.saturating_sub(params.spin_setup_bonus);
+    }
+    cache.insert(queue_len, state.matrix().clone(), h);
+    h
+}
+
+/// Cache of "h" values by matrix, indexed by the number of pieces left in the queue. The
+/// cache holds at most `cap` values; once full, it is emptied before inserting more, so
+/// memory use stays bounded no matter how long the search runs.
+struct HCache {
+    lvls: Vec<HashMap<BasicMatrix, i64>>,
+    len: usize,
+    cap: usize,
+}
+
+impl HCache {
+    fn new(cap: usize) -> Self {
+        Self {
+            lvls: Vec::with_capacity(8),
+            len: 0,
+            cap,
+        }
+    }
+
+    fn clear(&mut self) {
+        for lvl in self.lvls.iter_mut() {
+            lvl.clear();
+        }
+        self.len = 0;
+    }
+
+    fn get(&self, queue_len: usize, matrix: &BasicMatrix) -> Option<i64> {
+        self.lvls.get(queue_len)?.get(matrix).cloned()
+    }
+
+    fn insert(&mut self, queue_len: usize, matrix: BasicMatrix, h: i64) {
+        if self.len >= self.cap {
+            self.clear();
+        }
+        if self.lvls.len() <= queue_len {
+            self.lvls.resize_with(queue_len + 1, HashMap::new);
+        }
+        if self.lvls[queue_len].insert(matrix, h).is_none() {
+            self.len += 1;
+        }
+    }
+}
+
+/// Returns `params.hold_fit_bonus` if the held piece in `state` fits into a notch in the
+/// matrix, or 0 otherwise. The hold piece isn't part of the matrix, so unlike the rest of
+/// the "h" value this isn't cached by matrix.
+fn hold_bonus(shape_table: &ShapeTable, params: &Parameters, state: &State) -> i64 {
+    if params.hold_fit_bonus == 0 {
+        return 0;
+    }
+    match state.hold().and_then(|c| shape_table.shape(c)) {
+        Some(shape) if fits_notch(state.matrix(), shape) => params.hold_fit_bonus,
+        _ => 0,
+    }
+}
+
+/// Rates states at the end of the known queue by assuming that the worst possible pieces
+/// come next, see `FutureModel::Adversarial`.
+struct Adversary<'s> {
+    // shapes of the pieces, for spotting spin slots
+    shape_table: &'s ShapeTable,
+    // number of hypothetical pieces to place after the queue
+    depth: u8,
+    // every color
+    colors: Vec<Color>,
+    // which colors may come after the pieces dealt so far
+    bag: BagModel,
+    // pieces dealt since the beginning of the bag, starting with the known queue
+    dealt: Vec<Color>,
+    // placement generator for the hypothetical pieces
+    pfind: PlaceFinder<'s>,
+    // worst case "h" values already computed for each matrix, along with the pieces dealt
+    // before reaching it
+    cache: HashMap<(BasicMatrix, Vec<Color>), i64>,
+    // number of states the adversary may look beyond per search, after which states are
+    // rated as if it couldn't look any further
+    node_limit: usize,
+    // number of states looked beyond so far
+    node_count: usize,
+}
+
+impl<'s> Adversary<'s> {
+    fn new(shape_table: &'s ShapeTable, cfg: &Config, depth: u8) -> Self {
+        Self {
+            shape_table,
+            depth,
+            colors: shape_table.colors(),
+            bag: cfg.bag.clone(),
+            dealt: vec![],
+            pfind: PlaceFinder::new(shape_table, cfg.movement)
+                .with_order(cfg.placement_order)
+                .with_kick_system(cfg.kick_system)
+                .with_max_soft_drop(cfg.max_soft_drop)
+                .with_require_clear(cfg.require_clear),
+            cache: HashMap::new(),
+            node_limit: cfg.search_limit,
+            node_count: 0,
+        }
+    }
+
+    /// Resets the adversary for a search starting at `root`, whose queue is followed by the
+    /// `speculative` pieces.
+    fn reset(&mut self, root: &State, speculative: &[Color]) {
+        self.cache.clear();
+        self.node_count = 0;
+        self.dealt.clear();
+        self.dealt.extend(root.queue());
+        self.dealt.extend_from_slice(speculative);
+    }
+
+    /// Computes the worst case "h" value for `state`, which should have an empty queue.
+    fn h(
+        &mut self,
+        params: &Parameters,
+        heuristic: &Option<Arc<Heuristic>>,
+        h_cache: &mut HCache,
+        state: &State,
+    ) -> i64 {
+        self.worst_case(params, heuristic, h_cache, state, self.depth)
+    }
+
+    /// Returns the best "h" value that can be reached from `state` after `depth` more
+    /// pieces, if each piece is the one whose best placement is the worst. A piece that
+    /// can't be placed at all tops out, which rates as `i64::MAX`. Once the adversary has
+    /// looked beyond `node_limit` states, the rest are rated as if `depth` were 0.
+    fn worst_case(
+        &mut self,
+        params: &Parameters,
+        heuristic: &Option<Arc<Heuristic>>,
+        h_cache: &mut HCache,
+        state: &State,
+        depth: u8,
+    ) -> i64 {
+        if depth == 0 || state.reached_goal() || self.node_count >= self.node_limit {
+            return cached_h(self.shape_table, params, heuristic, h_cache, state);
+        }
+        let key = (state.matrix().clone(), self.dealt.clone());
+        if let Some(&h) = self.cache.get(&key) {
+            return h;
+        }
+        self.node_count += 1;
+        let mut worst = None;
+        for color in self.bag.next_pieces(&self.colors, &self.dealt) {
+            let next: State = Snapshot {
+                hold: None,
+                queue: vec![color],
+                matrix: state.matrix().clone(),
+            }
+            .into();
+            let succs = next
+                .placements(&mut self.pfind)
+                .map(|pl| {
+                    let mut succ = next.clone();
+                    succ.place(&pl);
+                    succ
+                })
+                .collect::<Vec<_>>();
+            let mut best = i64::MAX;
+            self.dealt.push(color);
+            for succ in succs.iter() {
+                let h = self.worst_case(params, heuristic, h_cache, succ, depth - 1);
+                best = best.min(h);
+            }
+            self.dealt.pop();
+            worst = std::cmp::max(worst, Some(best));
+        }
+        let h =
+            worst.unwrap_or_else(|| cached_h(self.shape_table, params, heuristic, h_cache, state));
+        self.cache.insert(key, h);
+        h
     }
 }
 
@@ -222,64 +895,127 @@ fn default_level() -> BinaryHeap<Node> {
 }
 
 impl MoveId {
+    /// Returns the stable id of the move starting with placement `pl`.
+    fn of(pl: &Place) -> Self {
+        let color = pl.shape.color().as_char() as u64 & 0xff;
+        Self((color << 48) | pl.normal().to_bits())
+    }
+
     #[cfg(test)]
     pub fn n(x: i32) -> Self {
-        Self(x as u8)
+        Self(x as u32 as u64)
     }
 }
 
 // Nodes
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
 struct Node {
     state: State,
     trace: Vec<u8>,
-    f: i64,
-    parent_f: i64,
+    // total path cost of the placements along the trace, and of all but the last one
+    g: i64,
+    parent_g: i64,
+    // heuristic value of the state minus the rewards, and the same for the parent node
+    h: i64,
+    parent_h: i64,
+    // total rewards earned by placements along the trace
+    reward: i64,
 }
 
 impl Node {
-    fn root(params: &Parameters, state: State) -> Self {
-        let h = eval(state.matrix()).score(params);
+    fn root(h: i64, state: State) -> Self {
         Self {
             state,
             trace: vec![],
-            f: h,
-            parent_f: h,
+            g: 0,
+            parent_g: 0,
+            h,
+            parent_h: h,
+            reward: 0,
         }
     }
 
-    /// Generates a successor node from this node, by placing `pl`. Uses `params` to
-    /// compute the new evaluation.
-    fn succ(&self, params: &Parameters, pl: &Place) -> Self {
+    /// Generates a successor node from this node, by placing `pl` and then appending the
+    /// pieces `speculative` to the queue. Uses `params` to compute the path cost and
+    /// rewards, and `h` to compute the new evaluation.
+    fn succ(
+        &self,
+        params: &Parameters,
+        pl: &Place,
+        speculative: &[Color],
+        h: impl FnOnce(&State) -> i64,
+    ) -> Self {
+        let mut reward = self.reward;
+        if params.reward_i_in_well != 0 && is_i_well_tetris(self.state.matrix(), pl) {
+            reward += params.reward_i_in_well;
+        }
+        if params.well_block_penalty != 0
+            && blocks_well(self.state.matrix(), pl, params.well_column)
+        {
+            // penalties are just negative rewards
+            reward -= params.well_block_penalty;
+        }
         let mut state = self.state.clone();
         state.place(pl);
+        if !speculative.is_empty() {
+            state.extend_queue(speculative);
+        }
         let mut trace = self.trace.clone();
         trace.push(pl.idx as u8);
-        let g = penalty(params, trace.len());
-        let h = eval(state.matrix()).score(params);
+        let g = self.g.saturating_add(penalty(params, pl.shape.color()));
+        let h = h(&state).saturating_sub(reward);
         Self {
             state,
             trace,
-            f: g + h,
-            parent_f: self.f,
+            g,
+            parent_g: self.g,
+            h,
+            parent_h: self.h,
+            reward,
         }
     }
 
+    /// Returns the overall evaluation of this node, i.e. `g + h`.
+    fn f(&self) -> i64 {
+        self.g.saturating_add(self.h)
+    }
+
     /// Returns `true` if this node is a terminal node (aka leaf node).
     fn is_terminal(&self) -> bool {
         self.state.is_terminal()
     }
 
     /// Returns the rating value for this node. Lower is always better.
-    fn rating(&self) -> i64 {
+    fn rating(&self, params: &Parameters) -> i64 {
         if self.state.reached_goal() {
-            // just use number-of-pieces as rating
-            self.trace.len() as i64
+            // just use number-of-pieces as rating, minus the bonus for perfect clears
+            let bonus = if self.state.is_empty() {
+                params.perfect_clear_bonus
+            } else {
+                0
+            };
+            self.trace.len() as i64 - bonus
         } else {
-            self.f.saturating_add(self.parent_f)
+            let parent_f = self.parent_g.saturating_add(self.parent_h);
+            self.f().saturating_add(parent_f)
         }
     }
 
+    /// Splits the rating of this node into `(g, h)`, where `g` is the path cost and `h` is
+    /// the rest, made up of the evaluations and rewards. The rating of a non-terminal node
+    /// counts the path cost of both this node and its parent. Nodes that reached the goal
+    /// are rated by their length alone, so all of their rating is path cost.
+    fn g_h(&self, params: &Parameters) -> (i64, i64) {
+        if self.state.reached_goal() {
+            return (self.rating(params), 0);
+        }
+        (
+            self.g.saturating_add(self.parent_g),
+            self.h.saturating_add(self.parent_h),
+        )
+    }
+
     fn trace<'a>(&'a self) -> impl Iterator<Item = usize> + 'a {
         self.trace.iter().map(|&i| i as usize)
     }
@@ -287,7 +1023,7 @@ impl Node {
 
 impl PartialEq for Node {
     fn eq(&self, rhs: &Self) -> bool {
-        self.f == rhs.f
+        self.f() == rhs.f()
     }
 }
 
@@ -301,6 +1037,462 @@ impl Eq for Node {}
 
 impl Ord for Node {
     fn cmp(&self, rhs: &Self) -> std::cmp::Ordering {
-        self.f.cmp(&rhs.f).reverse()
+        self.f().cmp(&rhs.f()).reverse()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{ai::Snapshot, basic_matrix, shape::srs, BasicMatrix, Color, MovementModel};
+
+    /// Places every piece in the queue, each time choosing the placement that leaves the
+    /// fewest rows, and returns the final node.
+    fn greedy_clear(params: &Parameters, queue: &str, matrix: BasicMatrix) -> Node {
+        let srs = srs();
+        let mut pfind = PlaceFinder::new(&srs, MovementModel::Full);
+        let state: State = Snapshot {
+            hold: None,
+            queue: queue.chars().map(Color::n).collect(),
+            matrix,
+        }
+        .into();
+        let h = |s: &State| eval(s.matrix()).score(params);
+        let mut node = Node::root(h(&state), state);
+        while node.state.queue_len() > 0 {
+            node = node
+                .state
+                .placements(&mut pfind)
+                .map(|pl| node.succ(params, &pl, &[], h))
+                .min_by_key(|n| n.state.matrix().rows())
+                .unwrap();
+        }
+        node
+    }
+
+    #[test]
+    fn test_trace_steps() {
+        use std::{cell::RefCell, rc::Rc};
+        let srs = srs();
+        let cfg = Config::default();
+        let root: State = Snapshot {
+            hold: None,
+            queue: "LTJ".chars().map(Color::n).collect(),
+            matrix: BasicMatrix::with_cols(10),
+        }
+        .into();
+        let events = Rc::new(RefCell::new(vec![]));
+        let mut search = Search::new(&srs, &cfg, None);
+        search.trace_steps({
+            let events = events.clone();
+            move |ev| events.borrow_mut().push(ev)
+        });
+        search.start(root);
+        for _ in 0..500 {
+            if search.step().is_err() {
+                break;
+            }
+        }
+        let events = events.borrow();
+        assert!(events.len() > 1);
+        assert_eq!(events[0].depth, 0);
+        assert_eq!(events[0].g, 0);
+        for (i, ev) in events.iter().enumerate() {
+            assert_eq!(ev.index, i);
+            assert_eq!(ev.f, ev.g + ev.h);
+            assert!(ev.depth < 3);
+        }
+        // expansion descends one level at a time during best-first iteration
+        for pair in events.windows(2) {
+            assert!(pair[1].depth <= pair[0].depth + 1);
+        }
+    }
+
+    #[test]
+    fn test_h_cache() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        let srs = srs();
+        let cfg = Config::default();
+        // O pieces placed side by side in either order give the same matrix
+        let root: State = Snapshot {
+            hold: None,
+            queue: "OOO".chars().map(Color::n).collect(),
+            matrix: BasicMatrix::with_cols(10),
+        }
+        .into();
+        let run = |clear_cache: bool| {
+            let calls = Arc::new(AtomicUsize::new(0));
+            let heuristic: Arc<Heuristic> = {
+                let calls = calls.clone();
+                let params = cfg.parameters.clone();
+                Arc::new(move |m: &BasicMatrix| {
+                    calls.fetch_add(1, Ordering::Relaxed);
+                    eval(m).score(&params)
+                })
+            };
+            let mut search = Search::new(&srs, &cfg, Some(heuristic));
+            search.start(root.clone());
+            while search.step().is_ok() {
+                if clear_cache {
+                    search.h_cache.clear();
+                }
+            }
+            (calls.load(Ordering::Relaxed), search.move_best)
+        };
+        let (uncached_calls, uncached_best) = run(true);
+        let (cached_calls, cached_best) = run(false);
+        assert!(
+            cached_calls < uncached_calls,
+            "{} >= {}",
+            cached_calls,
+            uncached_calls
+        );
+        assert_eq!(cached_best, uncached_best);
+    }
+
+    #[test]
+    fn test_adversary_top_out() {
+        let srs = srs();
+        let (xx, __) = (true, false);
+        // only placements clearing the row are allowed, and an O can't fill the gap
+        let cfg = Config {
+            require_clear: true,
+            future: FutureModel::Adversarial(1),
+            ..Config::default()
+        };
+        let state: State = Snapshot {
+            hold: None,
+            queue: vec![],
+            matrix: basic_matrix![[xx, xx, xx, xx, __, xx, xx, xx, xx, xx]],
+        }
+        .into();
+        let worst = |cfg: &Config| {
+            let mut h_cache = HCache::new(cfg.search_limit);
+            let mut adv = Adversary::new(&srs, cfg, 1);
+            adv.reset(&state, &[]);
+            adv.h(&cfg.parameters, &None, &mut h_cache, &state)
+        };
+        assert_eq!(worst(&cfg), i64::MAX);
+        // every other piece can clear the row
+        let cfg = Config {
+            bag: BagModel::Custom("IJLSTZ".chars().map(Color::n).collect()),
+            ..cfg
+        };
+        assert!(worst(&cfg) < i64::MAX);
+    }
+
+    #[test]
+    fn test_adversary_node_limit() {
+        let srs = srs();
+        let cfg = Config {
+            search_limit: 5,
+            future: FutureModel::Adversarial(2),
+            ..Config::default()
+        };
+        let state: State = Snapshot {
+            hold: None,
+            queue: vec![],
+            matrix: BasicMatrix::with_cols(10),
+        }
+        .into();
+        let mut h_cache = HCache::new(cfg.search_limit);
+        let mut adv = Adversary::new(&srs, &cfg, 2);
+        adv.reset(&state, &[]);
+        adv.h(&cfg.parameters, &None, &mut h_cache, &state);
+        assert_eq!(adv.node_count, 5);
+        assert!(adv.cache.len() <= 5);
+    }
+
+    #[test]
+    fn test_h_cache_cap() {
+        let srs = srs();
+        let cfg = Config {
+            search_limit: 20,
+            ..Config::default()
+        };
+        let root: State = Snapshot {
+            hold: None,
+            queue: "LTJSZ".chars().map(Color::n).collect(),
+            matrix: BasicMatrix::with_cols(10),
+        }
+        .into();
+        let mut search = Search::new(&srs, &cfg, None);
+        search.start(root);
+        let mut peak = 0;
+        while search.node_count() < 2_000 && search.step().is_ok() {
+            let len = search.h_cache.lvls.iter().map(|l| l.len()).sum::<usize>();
+            assert_eq!(len, search.h_cache.len);
+            peak = peak.max(len);
+        }
+        assert!(search.node_count() > 20);
+        assert!(peak <= 20, "{} > 20", peak);
+    }
+
+    #[test]
+    fn test_suspend_resume() {
+        let srs = srs();
+        let cfg = Config::default();
+        let root: State = Snapshot {
+            hold: None,
+            queue: "LTJSZ".chars().map(Color::n).collect(),
+            matrix: BasicMatrix::with_cols(10),
+        }
+        .into();
+        let run = |search: &mut Search, steps: usize| {
+            for _ in 0..steps {
+                search.step().unwrap();
+            }
+        };
+
+        let mut one_shot = Search::new(&srs, &cfg, None);
+        one_shot.start(root.clone());
+        run(&mut one_shot, 3_000);
+
+        let mut first = Search::new(&srs, &cfg, None);
+        first.start(root.clone());
+        run(&mut first, 1_234);
+        let json = serde_json::to_string(&first.suspend()).unwrap();
+        let frontier: Frontier = serde_json::from_str(&json).unwrap();
+        assert_eq!(*frontier.root(), root);
+        let mut resumed = Search::resume(&srs, &cfg, None, frontier);
+        run(&mut resumed, 3_000 - 1_234);
+
+        assert!(!one_shot.move_best.is_empty());
+        assert_eq!(resumed.move_best, one_shot.move_best);
+        assert_eq!(resumed.node_count(), one_shot.node_count());
+        let best = |s: &Search| {
+            s.move_best
+                .iter()
+                .map(|(&m, r)| (r.0, m))
+                .min()
+                .map(|(_, m)| m)
+        };
+        assert_eq!(best(&resumed), best(&one_shot));
+    }
+
+    #[test]
+    fn test_reward_i_in_well() {
+        let (xx, __) = (true, false);
+        let well = basic_matrix![
+            [xx, xx, xx, xx, xx, xx, xx, xx, xx, __],
+            [xx, xx, xx, xx, xx, xx, xx, xx, xx, __],
+            [xx, xx, xx, xx, xx, xx, xx, xx, xx, __],
+            [xx, xx, xx, xx, xx, xx, xx, xx, xx, __],
+        ];
+        let center_well = basic_matrix![
+            [xx, xx, xx, xx, __, xx, xx, xx, xx, xx],
+            [xx, xx, xx, xx, __, xx, xx, xx, xx, xx],
+            [xx, xx, xx, xx, __, xx, xx, xx, xx, xx],
+            [xx, xx, xx, xx, __, xx, xx, xx, xx, xx],
+        ];
+        let wide_well = basic_matrix![
+            [xx, xx, xx, xx, xx, xx, xx, xx, __, __],
+            [xx, xx, xx, xx, xx, xx, xx, xx, __, __],
+            [xx, xx, xx, xx, xx, xx, xx, xx, __, __],
+            [xx, xx, xx, xx, xx, xx, xx, xx, __, __],
+        ];
+        let params = Parameters {
+            reward_i_in_well: 100,
+            ..Parameters::default()
+        };
+        let i_tetris = greedy_clear(&params, "I", well.clone());
+        let mixed = greedy_clear(&params, "OO", wide_well);
+        assert_eq!(i_tetris.state.matrix().rows(), 0);
+        assert_eq!(mixed.state.matrix().rows(), 0);
+        assert!(i_tetris.f() < mixed.f());
+        assert_eq!(
+            i_tetris.f(),
+            greedy_clear(&Parameters::default(), "I", well).f() - 100
+        );
+        // only wells at the edge of the matrix are rewarded
+        assert_eq!(
+            greedy_clear(&params, "I", center_well.clone()).f(),
+            greedy_clear(&Parameters::default(), "I", center_well).f()
+        );
+    }
+
+    #[test]
+    fn test_hold_fit_bonus() {
+        let (xx, __) = (true, false);
+        let state: State = Snapshot {
+            hold: Some(Color::n('L')),
+            queue: vec![Color::n('O')],
+            matrix: basic_matrix![
+                [xx, xx, xx, xx, xx, __, xx, xx, xx, xx],
+                [__, xx, xx, xx, xx, xx, xx, xx, xx, xx],
+            ],
+        }
+        .into();
+        let srs = srs();
+        let mut pfind = PlaceFinder::new(&srs, MovementModel::Full);
+        // O placements covering the notch for the L in column 0, or leaving it open
+        let mut o_at = |col| {
+            state
+                .placements(&mut pfind)
+                .find(|pl| !pl.did_hold && pl.shape.cells(pl.tf).map(|(_, j)| j).min() == Some(col))
+                .map(|pl| (pl.shape, pl.tf))
+                .unwrap()
+        };
+        let (covers, keeps) = (o_at(0), o_at(4));
+        // f values of the successors for both placements
+        let f = |params: Parameters| {
+            let cfg = Config {
+                parameters: params,
+                ..Config::default()
+            };
+            let mut search = Search::new(&srs, &cfg, None);
+            let root = Node::root(search.h(&state), state.clone());
+            let mut f_of = |(shape, tf)| {
+                let pl = Place::new(shape, tf, false);
+                search.succ(&root, &pl).f()
+            };
+            (f_of(covers), f_of(keeps))
+        };
+        let (covers0, keeps0) = f(Parameters::default());
+        let (covers1, keeps1) = f(Parameters {
+            hold_fit_bonus: 1_000,
+            ..Parameters::default()
+        });
+        assert_eq!(covers1, covers0);
+        assert_eq!(keeps1, keeps0 - 1_000);
+        assert!(keeps1 < covers1);
+    }
+
+    #[test]
+    fn test_spin_setup_bonus() {
+        let (xx, __) = (true, false);
+        let state: State = Snapshot {
+            hold: None,
+            queue: vec![Color::n('T')],
+            matrix: basic_matrix![
+                [xx, xx, xx, __, __, xx, xx, xx, xx, xx],
+                [xx, xx, xx, xx, __, __, xx, xx, xx, xx],
+            ],
+        }
+        .into();
+        let srs = srs();
+        let h = |params: Parameters| {
+            let cfg = Config {
+                parameters: params,
+                ..Config::default()
+            };
+            let mut search = Search::new(&srs, &cfg, None);
+            let h = search.h(&state);
+            // the bonus only depends on the matrix, so it is cached along with the rest
+            assert_eq!(
+                search.h_cache.get(state.queue_len(), state.matrix()),
+                Some(h)
+            );
+            h
+        };
+        let params = Parameters {
+            spin_setup_bonus: 100,
+            ..Parameters::default()
+        };
+        assert_eq!(h(params), h(Parameters::default()) - 100);
+    }
+
+    #[test]
+    fn test_piece_costs() {
+        let srs = srs();
+        let mut pfind = PlaceFinder::new(&srs, MovementModel::Full);
+        // node after placing `color` anywhere on an empty matrix
+        let mut place_empty = |params: &Parameters, color| {
+            let state: State = Snapshot {
+                hold: None,
+                queue: vec![Color::n(color)],
+                matrix: BasicMatrix::with_cols(10),
+            }
+            .into();
+            let h = |s: &State| eval(s.matrix()).score(params);
+            let pl = state.placements(&mut pfind).next().unwrap();
+            Node::root(h(&state), state).succ(params, &pl, &[], h)
+        };
+        let params = Parameters {
+            piece_costs: vec![(Color::n('S'), 30), (Color::n('O'), 5)]
+                .into_iter()
+                .collect(),
+            ..Parameters::default()
+        };
+        let default = Parameters::default();
+        for (color, cost) in [('S', 30), ('O', 5), ('T', 0)] {
+            let with_cost = place_empty(&params, color);
+            let without = place_empty(&default, color);
+            // the cost is part of the path cost, not the rewards
+            assert_eq!(with_cost.g, default.piece_penalty + cost);
+            assert_eq!(with_cost.g, without.g + cost);
+            assert_eq!(with_cost.f(), without.f() + cost);
+            assert_eq!(with_cost.reward, without.reward);
+            assert_eq!(with_cost.g_h(&params).0, with_cost.g);
+        }
+    }
+
+    #[test]
+    fn test_well_block_penalty() {
+        let (xx, __) = (true, false);
+        let well = basic_matrix![
+            [xx, xx, xx, xx, xx, xx, xx, xx, xx, __],
+            [xx, xx, xx, xx, xx, xx, xx, xx, xx, __],
+        ];
+        let srs = srs();
+        let mut pfind = PlaceFinder::new(&srs, MovementModel::Full);
+        // f value after placing `color` at the bottom of the well
+        let mut f_in_well = |params: &Parameters, color| {
+            let state: State = Snapshot {
+                hold: None,
+                queue: vec![Color::n(color)],
+                matrix: well.clone(),
+            }
+            .into();
+            let h = |s: &State| eval(s.matrix()).score(params);
+            let pl = state
+                .placements(&mut pfind)
+                .find(|pl| pl.shape.cells(pl.tf).any(|cell| cell == (0, 9)))
+                .unwrap();
+            Node::root(h(&state), state).succ(params, &pl, &[], h).f()
+        };
+        let params = Parameters {
+            well_block_penalty: 50,
+            ..Parameters::default()
+        };
+        let default = Parameters::default();
+        assert_eq!(f_in_well(&params, 'L'), f_in_well(&default, 'L') + 50);
+        assert_eq!(f_in_well(&params, 'I'), f_in_well(&default, 'I'));
+        // the edge column isn't a well once the well is placed elsewhere
+        let middle = Parameters {
+            well_column: Some(4),
+            ..params.clone()
+        };
+        let default_middle = Parameters {
+            well_column: Some(4),
+            ..Parameters::default()
+        };
+        assert_eq!(f_in_well(&middle, 'L'), f_in_well(&default_middle, 'L'));
+    }
+
+    #[test]
+    fn test_well_column() {
+        let (xx, __) = (true, false);
+        let matrix = basic_matrix![
+            [xx, xx, xx, xx, __, xx, xx, xx, xx, xx],
+            [xx, xx, xx, xx, __, xx, xx, xx, xx, __],
+            [__, __, __, xx, __, __, __, __, __, __],
+        ];
+        let srs = srs();
+        let mut pfind = PlaceFinder::new(&srs, MovementModel::Full);
+        let state: State = Snapshot {
+            hold: None,
+            queue: vec![Color::n('J')],
+            matrix,
+        }
+        .into();
+        // a J standing in column 4, reaching down to the bottom of the well
+        let pl = state
+            .placements(&mut pfind)
+            .find(|pl| pl.shape.cells(pl.tf).any(|cell| cell == (0, 4)))
+            .unwrap();
+        assert!(blocks_well(state.matrix(), &pl, Some(4)));
+        assert!(!blocks_well(state.matrix(), &pl, None));
+        assert!(!blocks_well(state.matrix(), &pl, Some(9)));
     }
 }
@@ -1,14 +1,52 @@
-use crate::{config::Parameters, matrix::BasicMatrix};
+use crate::{
+    config::{Parameters, PlacementBias, ScoreMode},
+    matrix::BasicMatrix,
+    place::Place,
+    shape::{ShapeRef, ShapeTable},
+    Color, Orientation,
+};
 use red_union_find::UF;
-use std::ops::Range;
+use std::{cmp::Ordering, ops::Range};
 
 // Evaluations
 
+/// Extra penalty per row of the stack when `Parameters::keep_low` is set.
+pub const KEEP_LOW_ROW_FACTOR: i64 = 50;
+
 #[derive(Default, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Eval {
     pub rows: u16,
     pub piece_estimate: u16,
     pub i_dependencies: u16,
+    /// Column statistics, shared by the height based terms.
+    pub stats: BoardStats,
+}
+
+/// Lexicographically ordered summary of a matrix, used as its score under
+/// `ScoreMode::Lexicographic`. Fewer holes is always better, regardless of the other
+/// fields; then a flatter surface; then a lower stack.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LexScore {
+    /// Number of covered empty cells.
+    pub holes: u32,
+    /// Sum of the height differences between adjacent columns.
+    pub bumpiness: u32,
+    /// Number of occupied rows.
+    pub height: u16,
+}
+
+impl LexScore {
+    /// Encodes this score as an integer with the same ordering, so that it can be used as
+    /// a score by the search.
+    pub fn to_score(&self) -> i64 {
+        // each field fits into 20 bits, since there are at most 16 columns and `MAX_ROWS` rows
+        ((self.holes as i64) << 40) | ((self.bumpiness as i64) << 20) | (self.height as i64)
+    }
+}
+
+/// Compares two matrices under `ScoreMode::Lexicographic`. The `Less` matrix is better.
+pub fn cmp_lex(lhs: &BasicMatrix, rhs: &BasicMatrix) -> Ordering {
+    eval(lhs).lex_score().cmp(&eval(rhs).lex_score())
 }
 
 impl Eval {
@@ -16,35 +54,551 @@ impl Eval {
     ///
     /// Note: used by A* to compute "h" value (remaining cost heuristic).
     pub fn score(&self, params: &Parameters) -> i64 {
-        params.row_factor * (self.rows as i64)
-            + params.piece_estimate_factor * (self.piece_estimate as i64)
-            + params.i_dependency_factor * (self.i_dependencies as i64)
+        self.score_with_queue(params, usize::MAX)
+    }
+
+    /// Computes the "score" for a state that only has `queue_len` pieces left to place.
+    ///
+    /// If `params.queue_aware` is set, the piece estimate is capped at `queue_len`, since
+    /// a downstacking plan that needs more pieces than are left can't be carried out
+    /// anyways.
+    pub fn score_with_queue(&self, params: &Parameters, queue_len: usize) -> i64 {
+        if params.score_mode == ScoreMode::Lexicographic {
+            return self.lex_score().to_score();
+        }
+        let mut piece_estimate = self.piece_estimate as usize;
+        if params.queue_aware {
+            piece_estimate = std::cmp::min(piece_estimate, queue_len);
+        }
+        let keep_low_factor = if params.keep_low {
+            KEEP_LOW_ROW_FACTOR
+        } else {
+            0
+        };
+        // terms are only computed if their factor is nonzero, since most are disabled by
+        // default
+        let terms: [(i64, &dyn Fn() -> i64); 9] = [
+            (params.row_factor, &|| self.rows as i64),
+            (params.piece_estimate_factor, &|| piece_estimate as i64),
+            (params.i_dependency_factor, &|| self.i_dependencies as i64),
+            (params.height_variance_factor, &|| {
+                self.stats.height_variance() as i64
+            }),
+            (params.pc_parity_penalty, &|| self.pc_parity_locked() as i64),
+            (params.aggregate_height_factor, &|| {
+                self.aggregate_height(&params.column_weights)
+            }),
+            (params.bumpiness_factor, &|| {
+                self.bumpiness(&params.column_weights)
+            }),
+            (params.lopsidedness_factor, &|| self.lopsidedness()),
+            (keep_low_factor, &|| self.rows as i64),
+        ];
+        // saturate rather than overflow, so extreme parameters just clamp the score
+        terms.iter().filter(|(factor, _)| *factor != 0).fold(
+            self.bias_penalty(params.placement_bias),
+            |acc, (factor, x)| acc.saturating_add(factor.saturating_mul(x())),
+        )
+    }
+
+    /// Returns the summary of this evaluation used by `ScoreMode::Lexicographic`.
+    pub fn lex_score(&self) -> LexScore {
+        let bumpiness = self.bumpiness(&None) as u32;
+        LexScore {
+            holes: self.stats.covered_cells(),
+            bumpiness,
+            height: self.rows,
+        }
+    }
+
+    /// Computes the sum of the height differences between adjacent columns. Each
+    /// difference is multiplied by the smaller of the weights in `weights` of the two
+    /// columns (or `1` if there are no weights), so that a column with weight `0` is
+    /// ignored along with the differences next to it.
+    fn bumpiness(&self, weights: &Option<Vec<i64>>) -> i64 {
+        let weights = weights.as_deref().unwrap_or(&[]);
+        let weight = |j: usize| weights.get(j).cloned().unwrap_or(1);
+        self.stats
+            .heights()
+            .windows(2)
+            .enumerate()
+            .map(|(j, w)| {
+                let diff = (w[1] as i64 - w[0] as i64).abs();
+                std::cmp::min(weight(j), weight(j + 1)).saturating_mul(diff)
+            })
+            .fold(0, i64::saturating_add)
+    }
+
+    /// Computes the sum of the column heights, each multiplied by its weight in `weights`
+    /// (or `1` if there are no weights).
+    fn aggregate_height(&self, weights: &Option<Vec<i64>>) -> i64 {
+        let weights = match weights {
+            Some(weights) => weights,
+            None => return self.stats.aggregate as i64,
+        };
+        self.stats
+            .heights()
+            .iter()
+            .enumerate()
+            .map(|(j, &h)| {
+                weights
+                    .get(j)
+                    .cloned()
+                    .unwrap_or(1)
+                    .saturating_mul(h as i64)
+            })
+            .fold(0, i64::saturating_add)
+    }
+
+    /// Computes the absolute difference between the sum of the column heights on the left
+    /// and right halves of the matrix. The middle column of an odd-width matrix belongs to
+    /// neither half.
+    fn lopsidedness(&self) -> i64 {
+        let heights = self.stats.heights();
+        let half = heights.len() / 2;
+        let sum = |hs: &[u16]| hs.iter().map(|&h| h as i64).sum::<i64>();
+        let left = sum(&heights[..half]);
+        let right = sum(&heights[heights.len() - half..]);
+        (left - right).abs()
+    }
+
+    /// Returns `true` if the number of filled cells rules out a perfect clear, see
+    /// `pc_parity_locked`.
+    pub fn pc_parity_locked(&self) -> bool {
+        let cols = self.stats.cols;
+        parity_locked(self.rows, cols, self.stats.filled)
+    }
+
+    /// Returns the average distance of occupied cells from the center of the matrix, as a
+    /// percentage of the distance from the center to the leftmost or rightmost column.
+    pub fn spread(&self) -> u16 {
+        // `center_distance` is in half columns, as is `max_dist`
+        let max_dist = (self.stats.cols as u32).saturating_sub(1);
+        if self.stats.filled == 0 || max_dist == 0 {
+            0
+        } else {
+            (self.stats.center_distance * 100 / (self.stats.filled * max_dist)) as u16
+        }
+    }
+
+    /// Computes the tie-breaking term for `bias`. This is at most `1` so that it never
+    /// outweighs the other terms.
+    fn bias_penalty(&self, bias: PlacementBias) -> i64 {
+        match bias {
+            PlacementBias::None => 0,
+            PlacementBias::Center => (self.spread() > 50) as i64,
+            PlacementBias::Edges => (self.spread() < 50) as i64,
+        }
     }
 }
 
-/// Computes the "penalty" for placing the given number of pieces.
+/// Largest number of columns of a matrix, since each row is stored as a `u16`.
+const MAX_COLS: usize = 16;
+
+/// Column-based statistics about a matrix, computed together so that heuristics based on
+/// them don't each need to make their own pass over the matrix.
+#[derive(Default, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BoardStats {
+    /// Number of columns.
+    cols: u16,
+    /// Height of each column, counting only occupied cells. Only the first `cols` are
+    /// used; a fixed size array means evaluating a matrix doesn't have to allocate.
+    heights: [u16; MAX_COLS],
+    /// Height of the tallest column.
+    pub max_height: u16,
+    /// Sum of the heights of every column.
+    pub aggregate: u32,
+    /// Number of occupied cells.
+    pub filled: u32,
+    /// Sum of the distances of the occupied cells from the center of the matrix, in half
+    /// columns so that the center is a whole number even with an even number of columns.
+    pub center_distance: u32,
+    /// Number of distinct covered holes, as computed by `hole_count`.
+    pub hole_count: usize,
+}
+
+impl BoardStats {
+    /// Computes the statistics for `matrix`.
+    pub fn of(matrix: &BasicMatrix) -> Self {
+        Self::with_piece_estimate(matrix, &mut ResidueBuf::new()).0
+    }
+
+    /// Like `of`, but using `residue_buf` for bookkeeping. Also returns the piece
+    /// estimate, which is found by the same search for covered holes as `hole_count`.
+    fn with_piece_estimate(matrix: &BasicMatrix, residue_buf: &mut ResidueBuf) -> (Self, u16) {
+        let cols = matrix.cols();
+        let mut heights = [0; MAX_COLS];
+        let (mut filled, mut center_distance) = (0, 0);
+        let max_dist = matrix.cols().saturating_sub(1) as i32;
+        for i in 0..matrix.rows() {
+            for j in 0..matrix.cols() {
+                if matrix.get((i, j)) {
+                    heights[j as usize] = i + 1;
+                    filled += 1;
+                    center_distance += (2 * j as i32 - max_dist).unsigned_abs();
+                }
+            }
+        }
+        let (piece_estimate, hole_count) = residue_scan(matrix.clone(), residue_buf);
+        let stats = Self {
+            cols,
+            heights,
+            max_height: heights.iter().cloned().max().unwrap_or(0),
+            aggregate: heights.iter().map(|&h| h as u32).sum(),
+            filled,
+            center_distance,
+            hole_count,
+        };
+        (stats, piece_estimate)
+    }
+
+    /// Returns the height of each column, counting only occupied cells.
+    pub fn heights(&self) -> &[u16] {
+        &self.heights[..self.cols as usize]
+    }
+
+    /// Returns the variance of the column heights, rounded down.
+    pub fn height_variance(&self) -> u32 {
+        let n = self.cols as u32;
+        if n == 0 {
+            return 0;
+        }
+        let sum_sq = self
+            .heights()
+            .iter()
+            .map(|&h| h as u32 * h as u32)
+            .sum::<u32>();
+        // var = E[h^2] - E[h]^2, computed as (n * sum(h^2) - sum(h)^2) / n^2 to stay exact
+        // until the final division
+        (n * sum_sq - self.aggregate * self.aggregate) / (n * n)
+    }
+
+    /// Returns the number of empty cells below the top of their column, i.e. the total
+    /// of `column_holes`.
+    pub fn covered_cells(&self) -> u32 {
+        self.aggregate - self.filled
+    }
+}
+
+/// Computes the "penalty" for placing a piece of the given color, i.e.
+/// `params.piece_penalty` plus the extra cost of that piece, if any.
 ///
-/// Note: used in A* to compute "g" value (path cost).
-pub fn penalty(params: &Parameters, depth: usize) -> i64 {
-    (depth as i64) * params.piece_penalty
+/// Note: summed over the placements of a sequence in A* to compute "g" value (path cost).
+pub fn penalty(params: &Parameters, color: Color) -> i64 {
+    let cost = params.piece_costs.get(&color).copied().unwrap_or(0);
+    params.piece_penalty.saturating_add(cost)
 }
 
 /// Evaluates a matrix, returning the different heuristic values.
+///
+/// The matrix is evaluated as-is: no empty row is inserted below it, so holes in the
+/// bottom row are treated the same as holes anywhere else.
 pub fn eval(matrix: &BasicMatrix) -> Eval {
+    eval_with(matrix, &mut ResidueBuf::new())
+}
+
+/// Scores each matrix in `matrices`. The results are identical to calling
+/// `eval(matrix).score(params)` on each one, but internal buffers are shared across all
+/// of the evaluations.
+pub fn score_batch(params: &Parameters, matrices: &[BasicMatrix]) -> Vec<i64> {
+    let mut residue_buf = ResidueBuf::new();
+    matrices
+        .iter()
+        .map(|matrix| eval_with(matrix, &mut residue_buf).score(params))
+        .collect()
+}
+
+/// Fixed-point scale used by `score_normalized_fixed()`, i.e. the value that represents a
+/// normalized score of `1`.
+pub const NORMALIZED_SCALE: i64 = 1_000;
+
+/// Scores `matrix`, divided by the number of cells on the board so that scores are
+/// comparable across different board sizes. The board is considered to be `matrix.cols()`
+/// wide and at least one row tall.
+///
+/// Note: the division is rounded towards zero, so most scores on small boards collapse to
+/// just a few distinct values. Use `score_normalized_fixed()` to keep more precision.
+pub fn score_normalized(params: &Parameters, matrix: &BasicMatrix) -> i64 {
+    score_normalized_fixed(params, matrix) / NORMALIZED_SCALE
+}
+
+/// Like `score_normalized()`, but returns the result as a fixed-point number scaled by
+/// `NORMALIZED_SCALE`.
+pub fn score_normalized_fixed(params: &Parameters, matrix: &BasicMatrix) -> i64 {
+    let cells = (matrix.cols() as i64) * std::cmp::max(matrix.rows(), 1) as i64;
+    if cells == 0 {
+        return 0;
+    }
+    // scores saturate at the bounds of `i64`, so scale them up without overflowing
+    let fixed = eval(matrix).score(params) as i128 * NORMALIZED_SCALE as i128 / cells as i128;
+    fixed.clamp(i64::MIN as i128, i64::MAX as i128) as i64
+}
+
+/// Like `eval()`, but using `residue_buf` for bookkeeping.
+fn eval_with(matrix: &BasicMatrix, residue_buf: &mut ResidueBuf) -> Eval {
+    let (stats, piece_estimate) = BoardStats::with_piece_estimate(matrix, residue_buf);
     Eval {
         rows: matrix.rows(),
-        piece_estimate: piece_estimate(matrix.clone()),
-        i_dependencies: i_dependencies(&matrix, 0..matrix.rows()).count() as _,
+        piece_estimate,
+        i_dependencies: i_dependencies(matrix, 0..matrix.rows()).count() as _,
+        stats,
     }
 }
 
-/// Mystery's residue-based minimum piece estimate algorithm.
-fn piece_estimate(mut matrix: BasicMatrix) -> u16 {
+/// Returns the number of empty cells in `matrix` that have an occupied cell somewhere
+/// above them in the same column.
+fn covered_cells(matrix: &BasicMatrix) -> u32 {
+    column_holes(matrix).into_iter().map(u32::from).sum()
+}
+
+/// Returns the number of empty cells below the topmost occupied cell of each column of
+/// `matrix`. Unlike `hole_count`, every empty cell is counted, in the column it is in.
+pub fn column_holes(matrix: &BasicMatrix) -> Vec<u16> {
+    (0..matrix.cols())
+        .map(|j| {
+            let height = matrix.col_height(j);
+            (0..height).filter(|&i| !matrix.get((i, j))).count() as u16
+        })
+        .collect()
+}
+
+/// Returns `true` if no perfect clear is possible, no matter how many rows are cleared,
+/// since the empty cells to fill can't be divided into whole pieces. Clearing `h` rows
+/// requires filling `h * cols - filled` cells with pieces of 4 cells each, so this
+/// happens when the difference is never a multiple of 4 for any `h` above the stack.
+pub fn pc_parity_locked(matrix: &BasicMatrix) -> bool {
+    let filled = (0..matrix.rows()).map(|i| matrix.row_cells(i) as u32).sum();
+    parity_locked(matrix.rows(), matrix.cols(), filled)
+}
+
+/// Implements `pc_parity_locked` for a matrix with `filled` occupied cells.
+fn parity_locked(rows: u16, cols: u16, filled: u32) -> bool {
+    // `h * cols mod 4` repeats every 4 rows
+    let (rows, cols) = (rows as u32, cols as u32);
+    (rows..rows + 4).all(|h| (h * cols + 4 - filled % 4) % 4 != 0)
+}
+
+/// Returns an upper bound on the number of lines that can be cleared in `matrix` without
+/// placing any more pieces. Currently this is exactly the number of completely full rows.
+pub fn max_clearable_lines(matrix: &BasicMatrix) -> u16 {
+    (0..matrix.rows())
+        .filter(|&i| matrix.gaps(i).next().is_none())
+        .count() as u16
+}
+
+/// Returns the bumpiness of the top `window` rows of `matrix`: the sum of the height
+/// differences between adjacent columns, where columns are measured from the bottom of the
+/// window and columns that don't reach into the window count as height `0`. Unlike the
+/// whole-board bumpiness, anything below the window has no effect on the result.
+pub fn surface_roughness(matrix: &BasicMatrix, window: u16) -> u64 {
+    let base = matrix.rows().saturating_sub(window);
+    let heights = (0..matrix.cols())
+        .map(|j| matrix.col_height(j).saturating_sub(base) as i64)
+        .collect::<Vec<_>>();
+    heights
+        .windows(2)
+        .map(|w| (w[1] - w[0]).unsigned_abs())
+        .sum()
+}
+
+/// Returns the number of empty cells in the occupied rows of `matrix` that are reachable
+/// from above the stack, found by flood filling through empty cells (left, right, up and
+/// down) starting from the top row. Empty cells under an overhang are open if they connect
+/// to an open cell to the side; only cells sealed off entirely are excluded.
+pub fn open_cells(matrix: &BasicMatrix) -> u64 {
+    let (rows, cols) = (matrix.rows(), matrix.cols());
+    let mut seen = vec![false; rows as usize * cols as usize];
+    let mut queue = std::collections::VecDeque::new();
+    if rows > 0 {
+        queue.extend((0..cols).map(|j| (rows - 1, j)));
+    }
+    let mut count = 0;
+    while let Some((i, j)) = queue.pop_front() {
+        let idx = i as usize * cols as usize + j as usize;
+        if seen[idx] || matrix.get((i, j)) {
+            continue;
+        }
+        seen[idx] = true;
+        count += 1;
+        if i > 0 {
+            queue.push_back((i - 1, j));
+        }
+        if i + 1 < rows {
+            queue.push_back((i + 1, j));
+        }
+        if j > 0 {
+            queue.push_back((i, j - 1));
+        }
+        if j + 1 < cols {
+            queue.push_back((i, j + 1));
+        }
+    }
+    count
+}
+
+/// Returns the number of rows at the bottom of `matrix` that look like "cheese" garbage,
+/// i.e. rows that are full except for a single empty cell.
+pub fn cheese_rows(matrix: &BasicMatrix) -> u16 {
+    (0..matrix.rows())
+        .take_while(|&i| {
+            let mut gaps = matrix.gaps(i);
+            match (gaps.next(), gaps.next()) {
+                (Some(gap), None) => gap.end - gap.start == 1,
+                _ => false,
+            }
+        })
+        .count() as u16
+}
+
+/// Returns the number of distinct covered holes in `matrix`, as found by `covered_hole`.
+/// Holes covered by the same residue are counted once.
+pub fn hole_count(matrix: &BasicMatrix) -> usize {
+    residue_scan(matrix.clone(), &mut ResidueBuf::new()).1
+}
+
+/// Returns `true` if placing `pl` onto `matrix` is an I piece filling a well in the
+/// leftmost or rightmost column, clearing four lines.
+pub fn is_i_well_tetris(matrix: &BasicMatrix, pl: &Place) -> bool {
+    if pl.shape.color().as_char() != 'I' {
+        return false;
+    }
+    let well_cols = [0, matrix.cols() as i16 - 1];
+    pl.shape.cells(pl.tf).all(|(i, j)| {
+        // the row must be full, except for this cell of the I piece
+        well_cols.contains(&j)
+            && i >= 0
+            && i < matrix.rows() as i16
+            && matrix
+                .gaps(i as u16)
+                .eq(std::iter::once((j as u16)..(j as u16 + 1)))
+    })
+}
+
+/// Returns `true` if placing `pl` onto `matrix` puts a cell of a piece other than I into
+/// a well, i.e. below the top of the lower column next to the well. The well is in column
+/// `well_column`, or if `None`, either the leftmost or the rightmost column.
+pub fn blocks_well(matrix: &BasicMatrix, pl: &Place, well_column: Option<u16>) -> bool {
+    let cols = matrix.cols();
+    if pl.shape.color().as_char() == 'I' || cols < 2 {
+        return false;
+    }
+    let wells = match well_column {
+        Some(well) if well < cols => vec![well],
+        Some(_) => vec![],
+        None => vec![0, cols - 1],
+    };
+    // a well is as deep as the lower of the columns on either side of it
+    let depth = |well: u16| {
+        let left = well.checked_sub(1).map(|j| matrix.col_height(j));
+        let right = Some(well + 1)
+            .filter(|&j| j < cols)
+            .map(|j| matrix.col_height(j));
+        left.into_iter().chain(right).min().unwrap_or(0)
+    };
+    pl.shape.cells(pl.tf).any(|(i, j)| {
+        wells
+            .iter()
+            .any(|&well| j == well as i16 && i < depth(well) as i16)
+    })
+}
+
+/// Returns `true` if `shape` can be dropped straight down into a notch in `matrix`, i.e.
+/// in some orientation and column it lands without leaving any empty cells beneath it and
+/// without making the surface any bumpier. On a flat surface, every piece makes it
+/// bumpier, so only pieces filling a gap in the surface fit.
+pub fn fits_notch(matrix: &BasicMatrix, shape: ShapeRef) -> bool {
+    let bumpiness = |m: &BasicMatrix| surface_roughness(m, m.rows());
+    let (bumpiness0, covered0) = (bumpiness(matrix), covered_cells(matrix));
+    let orientations = [
+        Orientation::R0,
+        Orientation::R1,
+        Orientation::R2,
+        Orientation::R3,
+    ];
+    orientations
+        .iter()
+        .filter(|&&r| shape.canonical_orientation(r) == r)
+        .any(|&r| {
+            shape.valid_cols(r, matrix.cols()).any(|j| {
+                let tf = (shape.peak(matrix, j, r), j, r);
+                let mut matrix = matrix.clone();
+                shape.blit_to(&mut matrix, tf);
+                covered_cells(&matrix) == covered0 && bumpiness(&matrix) <= bumpiness0
+            })
+        })
+}
+
+/// Returns `true` if `pl` is a spin: a piece other than O or I placed onto `matrix` where it
+/// can't move left, right or up, so it could only have been rotated into place.
+pub fn is_spin(matrix: &BasicMatrix, pl: &Place) -> bool {
+    let color = pl.shape.color().as_char();
+    if color == 'O' || color == 'I' {
+        return false;
+    }
+    let (i, j, r) = pl.tf;
+    [(i, j - 1), (i, j + 1), (i + 1, j)]
+        .iter()
+        .all(|&(i, j)| pl.shape.intersects(matrix, (i, j, r)))
+}
+
+/// Returns `true` if any piece in `shtb` could rest somewhere in `matrix` as a spin, see
+/// `is_spin`. Whether the piece can actually be rotated into the slot isn't checked.
+pub fn has_spin_slot(matrix: &BasicMatrix, shtb: &ShapeTable) -> bool {
+    let orientations = [
+        Orientation::R0,
+        Orientation::R1,
+        Orientation::R2,
+        Orientation::R3,
+    ];
+    shtb.colors()
+        .into_iter()
+        .filter_map(|c| shtb.shape(c))
+        .any(|shape| {
+            orientations
+                .iter()
+                .filter(|&&r| shape.canonical_orientation(r) == r)
+                .any(|&r| {
+                    shape.valid_cols(r, matrix.cols()).any(|j| {
+                        (0..matrix.rows() as i16).any(|i| {
+                            !shape.intersects(matrix, (i, j, r))
+                                && shape.intersects(matrix, (i - 1, j, r))
+                                && is_spin(matrix, &Place::new(shape, (i, j, r), false))
+                        })
+                    })
+                })
+        })
+}
+
+/// Returns the center cell of every T-spin slot in `matrix`, from the bottom row up. A
+/// slot is room for a T pointing down, resting on the cell below its stem, with both of
+/// the T's lower corners occupied and at least one of its upper corners occupied, i.e.
+/// overhanging a wing. The walls and floor count as occupied.
+pub fn tspin_slots(matrix: &BasicMatrix) -> Vec<(u16, u16)> {
+    let occupied = |i: i16, j: i16| i < 0 || j < 0 || matrix.get((i as u16, j as u16));
+    let mut slots = vec![];
+    for i in 1..=(matrix.rows() as i16) {
+        for j in 0..(matrix.cols() as i16) {
+            let room = [(i, j - 1), (i, j), (i, j + 1), (i - 1, j)];
+            let is_slot = room.iter().all(|&(i, j)| !occupied(i, j))
+                && occupied(i - 2, j)
+                && occupied(i - 1, j - 1)
+                && occupied(i - 1, j + 1)
+                && (occupied(i + 1, j - 1) || occupied(i + 1, j + 1));
+            if is_slot {
+                slots.push((i as u16, j as u16));
+            }
+        }
+    }
+    slots
+}
+
+/// Mystery's residue-based minimum piece estimate algorithm. Returns the piece estimate
+/// along with the number of covered holes that were found (see `hole_count`).
+fn residue_scan(mut matrix: BasicMatrix, residue_buf: &mut ResidueBuf) -> (u16, usize) {
     let mut pieces = 0;
     let mut depth = 0;
 
-    let mut residue_buf = ResidueBuf::new();
-    while let Some((i, res)) = covered_hole(&matrix, &mut residue_buf) {
+    while let Some((i, res)) = covered_hole(&matrix, residue_buf) {
         let rows = (i + 1)..res.end;
         let pieces_to_fill: u16 = negative_spaces(&matrix, rows.clone())
             .map(|area| (area + 3) / 4)
@@ -57,7 +611,7 @@ fn piece_estimate(mut matrix: BasicMatrix) -> u16 {
         pieces += 1;
     }
 
-    pieces
+    (pieces, depth as usize)
 }
 
 /// Returns the area of each disjoint contiguous negative space in the given matrix.
@@ -496,6 +1050,703 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_bottom_row_holes() {
+        let (xx, rr, __) = (true, true, false);
+        // the same hole, in the bottom row and one row up
+        let low = basic_matrix![[__, xx, xx, xx, xx, xx], [rr, xx, xx, xx, xx, __]];
+        let high = basic_matrix![
+            [xx, xx, xx, xx, xx, xx],
+            [__, xx, xx, xx, xx, xx],
+            [rr, xx, xx, xx, xx, __],
+        ];
+        // no empty row is inserted below the matrix, so the hole in the bottom row is
+        // found underneath it
+        let mut rbuf = ResidueBuf::default();
+        assert_eq!(covered_hole(&low, &mut rbuf), Some((0, 1..2)));
+        assert_eq!(covered_hole(&high, &mut rbuf), Some((1, 2..3)));
+        assert_eq!(hole_count(&low), hole_count(&high));
+        assert_eq!(eval(&low).piece_estimate, eval(&high).piece_estimate);
+        // the hole adds as much to the score in either row
+        let mut low_filled = low.clone();
+        low_filled.set((0, 0));
+        let mut high_filled = high.clone();
+        high_filled.set((1, 0));
+        let params = Parameters::default();
+        let score = |m: &BasicMatrix| eval(m).score(&params);
+        assert!(score(&low) > score(&low_filled));
+        assert_eq!(
+            score(&low) - score(&low_filled),
+            score(&high) - score(&high_filled)
+        );
+    }
+
+    #[test]
+    fn test_lex_score() {
+        let (xx, __) = (true, false);
+        let holey = basic_matrix![[xx, __, xx, xx, xx], [xx, xx, xx, xx, __]];
+        let bumpy = basic_matrix![
+            [xx, __, xx, __, xx],
+            [xx, __, xx, __, xx],
+            [xx, __, xx, __, xx],
+            [xx, __, __, __, xx],
+        ];
+        assert_eq!(
+            eval(&holey).lex_score(),
+            LexScore {
+                holes: 1,
+                bumpiness: 1,
+                height: 2,
+            }
+        );
+        assert_eq!(
+            eval(&bumpy).lex_score(),
+            LexScore {
+                holes: 0,
+                bumpiness: 14,
+                height: 4,
+            }
+        );
+
+        // fewer holes wins no matter how bumpy, unlike under the default factors
+        assert_eq!(cmp_lex(&bumpy, &holey), Ordering::Less);
+        let weighted = Parameters::default();
+        assert!(eval(&bumpy).score(&weighted) > eval(&holey).score(&weighted));
+        let lex = Parameters {
+            score_mode: ScoreMode::Lexicographic,
+            ..Parameters::default()
+        };
+        assert!(eval(&bumpy).score(&lex) < eval(&holey).score(&lex));
+
+        // then flatter, then lower
+        let flat = basic_matrix![[xx, xx, xx, xx, __], [xx, xx, __, __, __]];
+        assert_eq!(cmp_lex(&flat, &bumpy), Ordering::Less);
+        let low = basic_matrix![[xx, xx, __, __, __]];
+        let high = basic_matrix![[xx, xx, xx, xx, xx], [xx, xx, __, __, __]];
+        assert_eq!(cmp_lex(&low, &high), Ordering::Less);
+        assert_eq!(cmp_lex(&low, &low), Ordering::Equal);
+    }
+
+    #[test]
+    fn test_open_cells() {
+        let (xx, __) = (true, false);
+        assert_eq!(open_cells(&BasicMatrix::with_cols(5)), 0);
+        let flat = basic_matrix![[xx, __, xx, xx, __], [__, __, xx, __, __]];
+        assert_eq!(open_cells(&flat), 6);
+        // the cells under the overhang in column 3 can be reached from column 4, but the
+        // ones in column 1 are sealed off
+        let overhang = basic_matrix![
+            [xx, __, xx, __, __],
+            [xx, __, xx, __, __],
+            [xx, xx, xx, xx, __],
+        ];
+        assert_eq!(open_cells(&overhang), 5);
+        // reaching a cell may require going back down after passing under an overhang
+        let winding = basic_matrix![
+            [__, __, xx, __, xx],
+            [xx, __, xx, __, xx],
+            [xx, __, __, __, xx],
+            [xx, xx, __, xx, xx],
+        ];
+        assert_eq!(open_cells(&winding), 9);
+    }
+
+    #[test]
+    fn test_cheese_rows() {
+        let (xx, __) = (true, false);
+        assert_eq!(cheese_rows(&BasicMatrix::with_cols(5)), 0);
+        assert_eq!(
+            cheese_rows(&basic_matrix![
+                [xx, xx, __, xx, xx],
+                [__, xx, xx, xx, xx],
+                [xx, xx, xx, __, xx],
+                [__, __, xx, xx, xx],
+                [xx, xx, xx, __, xx],
+            ]),
+            3
+        );
+        assert_eq!(cheese_rows(&basic_matrix![[xx, xx, xx, xx, xx]]), 0);
+        assert_eq!(cheese_rows(&basic_matrix![[xx, __, xx, __, xx]]), 0);
+    }
+
+    #[test]
+    fn test_column_holes() {
+        let (xx, __) = (true, false);
+        assert_eq!(column_holes(&BasicMatrix::with_cols(4)), vec![0, 0, 0, 0]);
+        let matrix = basic_matrix![
+            [xx, __, xx, xx, __],
+            [xx, __, xx, xx, __],
+            [xx, __, xx, xx, __],
+            [xx, xx, xx, __, __],
+        ];
+        assert_eq!(column_holes(&matrix), vec![0, 3, 0, 0, 0]);
+        assert_eq!(covered_cells(&matrix), 3);
+    }
+
+    #[test]
+    fn test_hole_count() {
+        let (xx, rr, __) = (true, true, false);
+        assert_eq!(hole_count(&BasicMatrix::with_cols(5)), 0, "zero");
+        assert_eq!(
+            hole_count(&basic_matrix![
+                [xx, xx, xx, xx, xx, xx],
+                [__, xx, xx, xx, xx, xx],
+                [rr, xx, xx, xx, xx, xx],
+            ]),
+            1,
+            "cheese h=1"
+        );
+        assert_eq!(
+            hole_count(&basic_matrix![
+                [__, xx, xx, xx, xx, xx],
+                [rr, xx, __, xx, xx, xx],
+                [rr, xx, rr, xx, __, xx],
+                [rr, xx, rr, xx, rr, xx],
+                [rr, xx, rr, xx, rr, xx],
+            ]),
+            3,
+            "cheese h=2"
+        );
+        assert_eq!(
+            hole_count(&basic_matrix![
+                [__, xx, xx, xx, xx, xx],
+                [rr, xx, xx, __, xx, xx],
+                [rr, xx, xx, __, xx, xx],
+                [rr, xx, xx, rr, xx, xx],
+                [rr, xx, xx, rr, xx, xx],
+            ]),
+            2,
+            "cheese d=2"
+        );
+        assert_eq!(
+            hole_count(&basic_matrix![
+                [xx, xx, xx, __, xx, xx],
+                [xx, xx, __, __, xx, xx],
+                [xx, __, __, __, __, xx],
+            ]),
+            0,
+            "funnel"
+        );
+        assert_eq!(
+            hole_count(&basic_matrix![
+                [xx, xx, xx, __, xx, xx],
+                [__, __, __, rr, __, __],
+                [__, __, rr, rr, __, __],
+            ]),
+            2,
+            "overhang"
+        );
+        assert_eq!(
+            hole_count(&basic_matrix![
+                [xx, xx, xx, xx, xx, xx],
+                [xx, __, xx, __, xx, xx],
+                [xx, rr, xx, rr, xx, xx],
+            ]),
+            1,
+            "double"
+        );
+    }
+
+    #[test]
+    fn test_is_spin() {
+        use crate::{place::PlaceFinder, MovementModel};
+        let (xx, __) = (true, false);
+        let srs = crate::shape::srs();
+        let s_spin = basic_matrix![
+            [xx, xx, xx, __, __, xx, xx, xx, xx, xx],
+            [xx, xx, xx, xx, __, __, xx, xx, xx, xx],
+        ];
+        let mut pfind = PlaceFinder::new(&srs, MovementModel::Full);
+        pfind.reset_matrix(&s_spin);
+        pfind.push_shape(crate::Color::n('S'), false);
+        let placements = pfind.collect::<Vec<_>>();
+        let at = |cells: &[(i16, i16)]| {
+            placements
+                .iter()
+                .find(|pl| {
+                    let mut pl_cells = pl.shape.cells(pl.tf).collect::<Vec<_>>();
+                    pl_cells.sort();
+                    pl_cells == cells
+                })
+                .unwrap()
+        };
+        // the S can only be spun into the slot, through the top right
+        assert!(is_spin(&s_spin, at(&[(0, 3), (0, 4), (1, 4), (1, 5)])));
+        assert!(!is_spin(&s_spin, at(&[(2, 0), (2, 1), (3, 1), (3, 2)])));
+        assert!(has_spin_slot(&s_spin, &srs));
+        assert!(!has_spin_slot(&BasicMatrix::with_cols(10), &srs));
+        let no_overhang = basic_matrix![[xx, xx, xx, __, __, xx, xx, xx, xx, xx]];
+        assert!(!has_spin_slot(&no_overhang, &srs));
+    }
+
+    #[test]
+    fn test_tspin_slots() {
+        let (xx, __) = (true, false);
+        let tsd = basic_matrix![
+            [xx, xx, xx, xx, __, xx, xx, xx, xx, xx],
+            [xx, xx, xx, __, __, __, xx, xx, xx, xx],
+            [xx, xx, xx, xx, __, __, __, __, __, __],
+        ];
+        assert_eq!(tspin_slots(&tsd), [(1, 4)]);
+        // without the overhang, a T could just be dropped in
+        let open = basic_matrix![
+            [xx, xx, xx, xx, __, xx, xx, xx, xx, xx],
+            [xx, xx, xx, __, __, __, xx, xx, xx, xx],
+        ];
+        assert!(tspin_slots(&open).is_empty());
+        assert!(tspin_slots(&BasicMatrix::with_cols(10)).is_empty());
+    }
+
+    #[test]
+    fn test_fits_notch() {
+        let (xx, __) = (true, false);
+        let srs = crate::shape::srs();
+        let shape = |c| srs.shape(crate::Color::n(c)).unwrap();
+        let notch = basic_matrix![
+            [xx, xx, xx, xx, xx, __, xx, xx, xx, xx],
+            [__, xx, xx, xx, xx, xx, xx, xx, xx, xx],
+        ];
+        assert!(fits_notch(&notch, shape('L')));
+        assert!(!fits_notch(&notch, shape('J')));
+        assert!(!fits_notch(&notch, shape('O')));
+        assert!(!fits_notch(&BasicMatrix::with_cols(10), shape('L')));
+    }
+
+    #[test]
+    fn test_surface_roughness() {
+        let (xx, __) = (true, false);
+        let mat = basic_matrix![
+            [xx, xx, xx, __, xx, xx],
+            [xx, __, xx, xx, xx, __],
+            [__, __, xx, xx, xx, __],
+            [__, __, __, xx, __, __],
+        ];
+        // heights [2, 1, 3, 4, 3, 1]
+        assert_eq!(surface_roughness(&mat, 4), 7);
+        // heights in window [0, 0, 1, 2, 1, 0]
+        assert_eq!(surface_roughness(&mat, 2), 4);
+        // heights in window [0, 0, 0, 1, 0, 0]
+        assert_eq!(surface_roughness(&mat, 1), 2);
+        assert_eq!(surface_roughness(&mat, 0), 0);
+        assert_eq!(surface_roughness(&mat, 100), 7);
+        assert_eq!(surface_roughness(&BasicMatrix::with_cols(6), 3), 0);
+
+        // rearranging the rows below the window doesn't matter
+        let buried = basic_matrix![
+            [__, xx, xx, xx, xx, xx],
+            [xx, xx, __, __, xx, xx],
+            [__, __, xx, xx, xx, __],
+            [__, __, __, xx, __, __],
+        ];
+        assert_eq!(surface_roughness(&buried, 2), surface_roughness(&mat, 2));
+        assert_ne!(surface_roughness(&buried, 4), surface_roughness(&mat, 4));
+    }
+
+    #[test]
+    fn test_board_stats() {
+        let (xx, __) = (true, false);
+        let mats = vec![
+            BasicMatrix::with_cols(4),
+            basic_matrix![
+                [xx, xx, xx, xx, __, xx],
+                [xx, xx, __, xx, xx, xx],
+                [xx, __, xx, xx, xx, __],
+                [__, __, xx, __, __, __],
+            ],
+            basic_matrix![
+                [xx, xx, xx, xx, xx, xx],
+                [xx, __, xx, __, xx, xx],
+                [xx, xx, xx, xx, xx, xx],
+            ],
+        ];
+        for mat in mats {
+            let stats = BoardStats::of(&mat);
+            let heights = (0..mat.cols())
+                .map(|j| mat.col_height(j))
+                .collect::<Vec<_>>();
+            assert_eq!(stats.heights(), heights);
+            assert_eq!(stats.max_height, heights.iter().cloned().max().unwrap_or(0));
+            assert_eq!(
+                stats.aggregate,
+                heights.iter().map(|&h| h as u32).sum::<u32>()
+            );
+            assert_eq!(stats.hole_count, hole_count(&mat));
+            assert_eq!(stats.covered_cells(), covered_cells(&mat));
+            assert_eq!(eval(&mat).stats, stats);
+        }
+    }
+
+    #[test]
+    fn test_placement_bias() {
+        let (xx, __) = (true, false);
+        let edges = eval(&basic_matrix![
+            [xx, xx, __, __, __, __, __, __, xx, xx],
+            [xx, __, __, __, __, __, __, __, __, xx],
+        ]);
+        let center = eval(&basic_matrix![
+            [__, __, __, xx, xx, xx, xx, __, __, __],
+            [__, __, __, __, xx, xx, __, __, __, __],
+        ]);
+        assert!(edges.spread() > 50);
+        assert!(center.spread() < 50);
+
+        let params = Parameters::default();
+        assert_eq!(edges.score(&params), center.score(&params));
+        let params = Parameters {
+            placement_bias: PlacementBias::Edges,
+            ..Parameters::default()
+        };
+        assert_eq!(edges.score(&params) + 1, center.score(&params));
+        let params = Parameters {
+            placement_bias: PlacementBias::Center,
+            ..Parameters::default()
+        };
+        assert_eq!(edges.score(&params), center.score(&params) + 1);
+    }
+
+    #[test]
+    fn test_height_variance() {
+        let (xx, __) = (true, false);
+        let flat = eval(&basic_matrix![
+            [xx, xx, xx, xx, __, xx, xx, xx],
+            [xx, xx, xx, __, xx, xx, xx, xx],
+            [xx, xx, __, xx, xx, xx, xx, xx],
+            [xx, __, xx, xx, xx, xx, xx, xx],
+        ]);
+        let spike = eval(&basic_matrix![
+            [xx, xx, xx, xx, __, xx, xx, xx],
+            [__, __, __, __, xx, __, __, __],
+            [__, __, __, __, xx, __, __, __],
+            [__, __, __, __, xx, __, __, __],
+            [__, __, __, __, xx, __, __, __],
+            [__, __, __, __, xx, __, __, __],
+            [__, __, __, __, xx, __, __, __],
+            [__, __, __, __, xx, __, __, __],
+            [__, __, __, __, xx, __, __, __],
+        ]);
+        assert_eq!(flat.stats.height_variance(), 0);
+        // heights [1, 1, 1, 1, 9, 1, 1, 1]: mean 2, var = (8 * 88 - 16 * 16) / 64
+        assert_eq!(spike.stats.height_variance(), 7);
+        let stats = BoardStats::of(&basic_matrix![[__, __], [xx, __]]);
+        assert_eq!(stats.height_variance(), 1);
+
+        let params = Parameters::default();
+        let base = spike.score(&params) - flat.score(&params);
+        let params = Parameters {
+            height_variance_factor: 100,
+            ..Parameters::default()
+        };
+        assert_eq!(
+            spike.score(&params) - flat.score(&params),
+            base + 100 * spike.stats.height_variance() as i64
+        );
+    }
+
+    #[test]
+    fn test_pc_parity() {
+        let (xx, __) = (true, false);
+        // 10 columns: only an even number of filled cells can be cleared
+        let residue = |n: usize| {
+            let mut mat = BasicMatrix::with_cols(10);
+            for k in 0..n {
+                mat.set(((k / 9) as u16, (k % 9) as u16));
+            }
+            pc_parity_locked(&mat)
+        };
+        assert!(!residue(0));
+        assert!(residue(1));
+        assert!(!residue(2));
+        assert!(residue(3));
+        assert!(!residue(4));
+        assert!(!residue(6));
+        assert!(residue(9));
+        // 9 columns: any number of filled cells can be cleared with enough rows
+        assert!(!pc_parity_locked(&basic_matrix![[
+            xx, __, __, __, __, __, __, __, __
+        ]]));
+        // 4 columns: only multiples of 4
+        assert!(!pc_parity_locked(&basic_matrix![[xx, xx, xx, xx]]));
+        assert!(pc_parity_locked(&basic_matrix![[xx, xx, __, __]]));
+        assert!(pc_parity_locked(&basic_matrix![[xx, xx, xx, __]]));
+
+        let mut odd = BasicMatrix::with_cols(10);
+        odd.set((0, 0));
+        let mut even = odd.clone();
+        even.set((0, 9));
+        let params = Parameters {
+            pc_parity_penalty: 50,
+            ..Parameters::default()
+        };
+        assert!(eval(&odd).pc_parity_locked());
+        assert!(!eval(&even).pc_parity_locked());
+        assert_eq!(
+            eval(&odd).score(&params) - eval(&odd).score(&Parameters::default()),
+            50
+        );
+        assert_eq!(
+            eval(&even).score(&params),
+            eval(&even).score(&Parameters::default())
+        );
+    }
+
+    #[test]
+    fn test_keep_low() {
+        let (xx, __) = (true, false);
+        let tall_smooth = eval(&basic_matrix![
+            [xx, xx, xx, xx, xx, xx, xx, xx, __, __],
+            [xx, xx, xx, xx, xx, xx, xx, xx, __, __],
+            [xx, xx, xx, xx, xx, xx, xx, xx, __, __],
+            [xx, xx, xx, xx, xx, xx, xx, xx, __, __],
+        ]);
+        // two wells that need I pieces
+        let low_rough = eval(&basic_matrix![
+            [xx, __, xx, xx, xx, xx, __, xx, __, __],
+            [xx, __, xx, xx, xx, xx, __, xx, __, __],
+            [xx, __, xx, xx, xx, xx, __, xx, __, __],
+        ]);
+        let params = Parameters::default();
+        assert!(low_rough.score(&params) > tall_smooth.score(&params));
+        let params = Parameters {
+            keep_low: true,
+            ..params
+        };
+        assert!(low_rough.score(&params) < tall_smooth.score(&params));
+        assert_eq!(
+            tall_smooth.score(&params) - tall_smooth.score(&Parameters::default()),
+            4 * KEEP_LOW_ROW_FACTOR
+        );
+    }
+
+    #[test]
+    fn test_lopsidedness() {
+        let (xx, __) = (true, false);
+        let balanced = eval(&basic_matrix![
+            [xx, xx, __, __, __, __, __, __, xx, xx],
+            [xx, __, __, __, __, __, __, __, __, xx],
+        ]);
+        let left_heavy = eval(&basic_matrix![
+            [xx, xx, xx, xx, __, __, __, __, __, __],
+            [xx, xx, __, __, __, __, __, __, __, __],
+        ]);
+        assert_eq!(balanced.stats.aggregate, left_heavy.stats.aggregate);
+        assert_eq!(balanced.lopsidedness(), 0);
+        assert_eq!(left_heavy.lopsidedness(), 6);
+
+        let params = Parameters {
+            lopsidedness_factor: 3,
+            ..Parameters::default()
+        };
+        let base = Parameters::default();
+        let lopsided = |ev: &Eval| ev.score(&params) - ev.score(&base);
+        assert_eq!(lopsided(&balanced), 0);
+        assert_eq!(lopsided(&left_heavy), 3 * 6);
+
+        // the middle column of an odd-width matrix is ignored
+        let centered = eval(&basic_matrix![[__, __, xx, __, __]]);
+        assert_eq!(centered.lopsidedness(), 0);
+    }
+
+    #[test]
+    fn test_column_weights() {
+        let (xx, __) = (true, false);
+        let deep_well = eval(&basic_matrix![
+            [xx, xx, xx, __, xx],
+            [xx, xx, xx, __, xx],
+            [xx, xx, xx, __, xx],
+        ]);
+        let shallow_well = eval(&basic_matrix![
+            [xx, xx, xx, xx, xx],
+            [xx, xx, xx, xx, xx],
+            [xx, xx, xx, __, xx],
+        ]);
+        assert_eq!(deep_well.stats.heights(), [3, 3, 3, 0, 3]);
+        assert_eq!(shallow_well.stats.heights(), [3, 3, 3, 2, 3]);
+
+        let params = Parameters {
+            aggregate_height_factor: 2,
+            ..Parameters::default()
+        };
+        let base = Parameters::default();
+        let aggregate = |ev: &Eval, params: &Parameters| ev.score(params) - ev.score(&base);
+        assert_eq!(aggregate(&deep_well, &params), 2 * 12);
+        assert_eq!(aggregate(&shallow_well, &params), 2 * 14);
+
+        // weighting the well column by 0 ignores how deep the well is
+        let params = Parameters {
+            column_weights: Some(vec![1, 1, 1, 0, 1]),
+            ..params
+        };
+        assert_eq!(aggregate(&deep_well, &params), 2 * 12);
+        assert_eq!(aggregate(&shallow_well, &params), 2 * 12);
+
+        // missing weights count as 1
+        let params = Parameters {
+            column_weights: Some(vec![2]),
+            ..params
+        };
+        assert_eq!(aggregate(&deep_well, &params), 2 * 15);
+
+        // the bumpiness next to a column with weight 0 is ignored too
+        let params = Parameters {
+            bumpiness_factor: 1,
+            ..Parameters::default()
+        };
+        assert_eq!(aggregate(&deep_well, &params), 6);
+        assert_eq!(aggregate(&shallow_well, &params), 2);
+        let params = Parameters {
+            column_weights: Some(vec![1, 1, 1, 0, 1]),
+            ..params
+        };
+        assert_eq!(aggregate(&deep_well, &params), 0);
+        assert_eq!(aggregate(&shallow_well, &params), 0);
+        let params = Parameters {
+            column_weights: Some(vec![1, 1, 1, 2, 2]),
+            ..params
+        };
+        assert_eq!(aggregate(&deep_well, &params), 3 + 2 * 3);
+    }
+
+    #[test]
+    fn test_score_batch() {
+        // simple LCG, so that the corpus is the same on every run
+        let mut seed = 0x2545_f491u32;
+        let mut rand = move || {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            seed >> 16
+        };
+        let matrices = (0..200)
+            .map(|_| {
+                let mut mat = BasicMatrix::with_cols(10);
+                let rows = rand() % 12;
+                for _ in 0..rows {
+                    let hole = rand() % 10;
+                    let density = rand() % 4;
+                    mat.push_row((0..10).map(|j| j != hole && rand() % 4 >= density));
+                }
+                mat
+            })
+            .collect::<Vec<_>>();
+        let params = Parameters {
+            height_variance_factor: 3,
+            ..Parameters::default()
+        };
+        let individual = matrices
+            .iter()
+            .map(|mat| eval(mat).score(&params))
+            .collect::<Vec<_>>();
+        assert_eq!(score_batch(&params, &matrices), individual);
+        assert_eq!(score_batch(&params, &[]), Vec::<i64>::new());
+    }
+
+    #[test]
+    fn test_score_normalized() {
+        let params = Parameters::default();
+        let scaled = |factor: u16| {
+            let mut mat = BasicMatrix::with_cols(5 * factor);
+            for i in 0..4 * factor {
+                let hole = (i / factor) * 3 % 5;
+                mat.push_row((0..5 * factor).map(|j| j / factor != hole));
+            }
+            mat
+        };
+        let small = scaled(1);
+        let norm = score_normalized_fixed(&params, &small);
+        assert_eq!(score_normalized(&params, &small), norm / NORMALIZED_SCALE);
+        assert!(norm > 0);
+        for factor in 2..=3 {
+            let large = scaled(factor);
+            // raw scores grow with the board, normalized scores don't
+            assert!(eval(&large).score(&params) > eval(&small).score(&params));
+            let large_norm = score_normalized_fixed(&params, &large);
+            assert!(large_norm <= norm, "{} > {}", large_norm, norm);
+            assert!(large_norm * 4 >= norm, "{} < {} / 4", large_norm, norm);
+        }
+        assert_eq!(
+            score_normalized_fixed(&params, &BasicMatrix::with_cols(10)),
+            0
+        );
+        assert_eq!(
+            score_normalized_fixed(&params, &BasicMatrix::with_cols(0)),
+            0
+        );
+    }
+
+    #[test]
+    fn test_score_queue_aware() {
+        let (xx, __) = (true, false);
+        let mat = basic_matrix![
+            [xx, xx, xx, xx, __, xx],
+            [xx, xx, __, xx, xx, xx],
+            [xx, __, xx, xx, xx, xx],
+            [__, xx, xx, xx, xx, xx],
+        ];
+        let ev = eval(&mat);
+        assert_eq!(ev.piece_estimate, 3);
+
+        let params = Parameters::default();
+        assert_eq!(ev.score_with_queue(&params, 1), ev.score(&params));
+
+        let params = Parameters {
+            queue_aware: true,
+            ..Parameters::default()
+        };
+        let long = ev.score_with_queue(&params, 10);
+        let short = ev.score_with_queue(&params, 1);
+        assert_eq!(long, ev.score(&params));
+        assert_eq!(long - short, params.piece_estimate_factor * 2);
+    }
+
+    #[test]
+    fn test_score_saturates() {
+        let (xx, __) = (true, false);
+        let mat = basic_matrix![[xx, xx, __, xx, xx, xx], [xx, __, xx, xx, xx, xx]];
+        let ev = eval(&mat);
+        let params = Parameters {
+            row_factor: std::i64::MAX - 1,
+            aggregate_height_factor: std::i64::MAX / 2,
+            ..Parameters::default()
+        };
+        assert_eq!(ev.score(&params), std::i64::MAX);
+        assert_eq!(score_normalized_fixed(&params, &mat), i64::MAX);
+        let params = Parameters {
+            row_factor: std::i64::MIN + 1,
+            aggregate_height_factor: 1,
+            column_weights: Some(vec![std::i64::MIN; 6]),
+            ..Parameters::default()
+        };
+        assert_eq!(ev.score(&params), std::i64::MIN);
+        assert_eq!(score_normalized_fixed(&params, &mat), i64::MIN);
+        assert_eq!(score_normalized(&params, &mat), i64::MIN / NORMALIZED_SCALE);
+    }
+
+    #[test]
+    fn test_max_clearable_lines() {
+        let (xx, __) = (true, false);
+        assert_eq!(max_clearable_lines(&BasicMatrix::with_cols(4)), 0);
+        assert_eq!(
+            max_clearable_lines(&basic_matrix![[xx, xx, __, xx], [__, xx, xx, xx]]),
+            0
+        );
+        assert_eq!(
+            max_clearable_lines(&basic_matrix![
+                [xx, xx, __, xx],
+                [xx, xx, xx, xx],
+                [__, xx, xx, __],
+            ]),
+            1
+        );
+        assert_eq!(
+            max_clearable_lines(&basic_matrix![
+                [xx, xx, xx, xx],
+                [xx, xx, xx, xx],
+                [__, xx, xx, xx],
+                [xx, xx, xx, xx],
+                [xx, xx, xx, xx],
+            ]),
+            4
+        );
+    }
+
     #[test]
     fn test_i_deps_1() {
         let (xx, __) = (true, false);
@@ -1,7 +1,8 @@
 use crate::{
-    config::Config,
+    config::{BagModel, ColumnWeightsError, Config, MovementModel, Parameters},
+    place::{Place, PlaceFinder},
     shape::{srs, ShapeTable},
-    BasicMatrix, Color, Input,
+    BasicMatrix, Color, Input, Orientation, MAX_ROWS,
 };
 
 mod analysis;
@@ -28,6 +29,100 @@ pub struct Suggestion {
     pub rating: i64,
 }
 
+impl Suggestion {
+    /// Returns `true` if this suggestion and `other` follow the same plan, meaning that
+    /// the inputs of the shorter suggestion are a prefix of the inputs of the longer one.
+    /// For instance, two suggestions for the same move requested with different lengths
+    /// are the same plan. Ratings are not compared.
+    pub fn same_plan(&self, other: &Suggestion) -> bool {
+        let len = std::cmp::min(self.inputs.len(), other.inputs.len());
+        self.inputs[..len] == other.inputs[..len]
+    }
+}
+
+/// A single placement from a suggested sequence.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Placement {
+    /// Index of this placement among the placements available at its point in the
+    /// sequence.
+    pub idx: usize,
+    /// The color of the piece being placed.
+    pub color: Color,
+    /// The final orientation of the piece.
+    pub orientation: Orientation,
+    /// The final row of the piece.
+    pub row: i16,
+    /// The final column of the piece.
+    pub col: i16,
+    /// `true` if hold was required for this placement.
+    pub did_hold: bool,
+}
+
+impl<'s> From<&Place<'s>> for Placement {
+    fn from(pl: &Place<'s>) -> Self {
+        let (row, col, orientation) = pl.tf;
+        Placement {
+            idx: pl.idx,
+            color: pl.shape.color(),
+            orientation,
+            row,
+            col,
+            did_hold: pl.did_hold,
+        }
+    }
+}
+
+impl Placement {
+    /// Returns a placement whose column is given relative to the center of a matrix with
+    /// `cols` columns, as by `column_from_center`. `idx` is zero and `did_hold` is `false`.
+    pub fn from_center(
+        color: Color,
+        orientation: Orientation,
+        row: i16,
+        col_from_center: i16,
+        cols: u16,
+    ) -> Self {
+        Placement {
+            idx: 0,
+            color,
+            orientation,
+            row,
+            col: col_from_center + (cols / 2) as i16,
+            did_hold: false,
+        }
+    }
+
+    /// Returns the column of this placement relative to the center column of a matrix
+    /// with `cols` columns (i.e. column `cols / 2`), so columns left of center are
+    /// negative.
+    pub fn column_from_center(&self, cols: u16) -> i16 {
+        self.col - (cols / 2) as i16
+    }
+
+    /// Returns the absolute (row, col) coordinates of the cells filled by this placement,
+    /// before any lines are cleared. Yields nothing if `shtb` has no shape for the color.
+    pub fn cells<'a>(&self, shtb: &'a ShapeTable) -> impl Iterator<Item = (u16, u16)> + 'a {
+        // the position may be negative due to the shape's offset, but the casts round-trip
+        // since the coordinates of the cells themselves are never negative
+        shtb.cells(
+            self.color,
+            self.orientation,
+            self.row as u16,
+            self.col as u16,
+        )
+    }
+}
+
+/// Whether or not to hold the current piece, see `hold_decision`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct HoldDecision {
+    /// `true` if holding leads to the better placement.
+    pub hold: bool,
+    /// How much lower the score of the better option's best placement is than the other
+    /// option's. `None` if only one of the options has any placements.
+    pub score_gap: Option<i64>,
+}
+
 /// Statistics about the analysis after it has finished.
 #[derive(Clone, Debug, Eq, PartialEq, Default)]
 pub struct Stats {
@@ -35,23 +130,432 @@ pub struct Stats {
     pub iterations: usize,
     /// Number of nodes generated.
     pub nodes: usize,
+    /// Number of nodes discarded without being expanded, e.g. by `Config::prune_margin`.
+    pub pruned: usize,
     /// Total time taken to do the analysis.
     pub time_taken: std::time::Duration,
+    /// The best move and its rating as of each iteration, as `(iteration, move_id,
+    /// rating)`. Only recorded if `Config::record_best_per_iteration` is set.
+    pub best_per_iteration: Vec<(usize, MoveId, i64)>,
+    /// Number of placements in the longest sequence found.
+    pub max_depth: usize,
+}
+
+impl Stats {
+    /// Returns the effective branching factor of the search, approximated as the `b` for
+    /// which a tree with `max_depth` levels of `b` children per node has `nodes` leaves,
+    /// i.e. `nodes^(1/max_depth)`. Lower values indicate a more focused search. Returns
+    /// `0` if no sequences were found.
+    pub fn effective_branching_factor(&self) -> f64 {
+        if self.max_depth == 0 {
+            return 0.0;
+        }
+        (self.nodes as f64).powf(1.0 / self.max_depth as f64)
+    }
 }
 
 // Evaluation function interface
 
-pub use eval::Eval;
+pub use eval::{
+    cheese_rows, cmp_lex, column_holes, hole_count, max_clearable_lines, open_cells,
+    pc_parity_locked, score_batch, score_normalized, score_normalized_fixed, surface_roughness,
+    tspin_slots, BoardStats, Eval, LexScore, KEEP_LOW_ROW_FACTOR, NORMALIZED_SCALE,
+};
+
+/// A custom heuristic function, which rates a matrix (lower is better). May be used in
+/// place of the built-in evaluation function via `AI::set_heuristic`.
+pub type Heuristic = dyn Fn(&BasicMatrix) -> i64 + Send + Sync;
 
 /// Performs the static analysis function on a snapshot.
 pub fn static_eval(snapshot: &Snapshot) -> Eval {
     eval::eval(&snapshot.matrix)
 }
 
+/// Returns the heuristic score of `state`'s matrix as is, before any placements are made
+/// (lower is better), i.e. `eval(matrix).score(params)`. The queue and hold piece are not
+/// taken into account.
+pub fn board_rating(params: &Parameters, state: &State) -> i64 {
+    eval::eval(state.matrix()).score(params)
+}
+
+/// Returns the placement for `state` whose resulting matrix has the lowest score, without
+/// searching any further ahead. Returns `None` if there are no placements.
+pub fn best_placement(shtb: &ShapeTable, params: &Parameters, state: &State) -> Option<Placement> {
+    best_placement_where(shtb, params, state, |_| true).map(|best| best.placement)
+}
+
+/// Like `best_placement`, but only considers placements that leave column `well_col`
+/// untouched, e.g. to keep a well open for later.
+pub fn best_placement_keeping_well(
+    shtb: &ShapeTable,
+    params: &Parameters,
+    state: &State,
+    well_col: u16,
+) -> Option<Placement> {
+    best_placement_where(shtb, params, state, |pl| {
+        pl.shape.cells(pl.tf).all(|(_, j)| j != well_col as i16)
+    })
+    .map(|best| best.placement)
+}
+
+/// Decides whether to hold the current piece of `state`, by comparing the best placement
+/// of the current piece with the best placement of the piece from hold (as found by
+/// `best_placement`).
+pub fn hold_decision(shtb: &ShapeTable, params: &Parameters, state: &State) -> HoldDecision {
+    let keep = best_placement_where(shtb, params, state, |pl| !pl.did_hold);
+    let hold = best_placement_where(shtb, params, state, |pl| pl.did_hold);
+    match (keep, hold) {
+        (Some(keep), Some(hold)) => HoldDecision {
+            hold: hold.score < keep.score,
+            score_gap: Some((keep.score - hold.score).abs()),
+        },
+        (keep, hold) => HoldDecision {
+            hold: keep.is_none() && hold.is_some(),
+            score_gap: None,
+        },
+    }
+}
+
+/// The placement found by `best_placement_where`.
+struct BestPlacement {
+    placement: Placement,
+    /// Score of the matrix after the placement.
+    score: i64,
+    /// The state after the placement.
+    succ: State,
+    /// Number of lines cleared by the placement.
+    lines: usize,
+}
+
+/// Returns the placement with the lowest score among those accepted by `filter`, along
+/// with that score and the state it leads to.
+fn best_placement_where(
+    shtb: &ShapeTable,
+    params: &Parameters,
+    state: &State,
+    filter: impl Fn(&Place) -> bool,
+) -> Option<BestPlacement> {
+    let mut pfind = PlaceFinder::new(shtb, MovementModel::Full);
+    state
+        .placements(&mut pfind)
+        .filter(|pl| filter(pl))
+        .map(|pl| {
+            let mut succ = state.clone();
+            succ.place(&pl);
+            let score = eval::eval(succ.matrix()).score(params);
+            (pl, score, succ)
+        })
+        .min_by_key(|&(_, score, _)| score)
+        .map(|(pl, score, succ)| BestPlacement {
+            placement: Placement::from(&pl),
+            score,
+            succ,
+            lines: state.rows_cleared_by(&pl).len(),
+        })
+}
+
+/// Computes a plan for digging through the "cheese" garbage at the bottom of `state`'s
+/// matrix (see `cheese_rows`), using the main search with parameters `params`. Clearing
+/// the bottom row ends a sequence and rates it by its length, so the plan is the shortest
+/// sequence found that clears all of the garbage, or the best sequence for the queue if
+/// none can. Returns an empty plan if there is no cheese to dig through.
+pub fn cheese_plan(
+    shtb: std::sync::Arc<ShapeTable>,
+    params: &Parameters,
+    state: &State,
+) -> Vec<Placement> {
+    if eval::cheese_rows(state.matrix()) == 0 {
+        return vec![];
+    }
+    let cfg = Config {
+        parameters: params.clone(),
+        ..Config::default()
+    };
+    let mut analysis = analysis::spawn(shtb, cfg, state.clone());
+    analysis.wait();
+    analysis
+        .all_moves()
+        .min_by(|&m, &n| analysis.cmp(m, n))
+        .map_or(vec![], |best| analysis.placement_trace(best, usize::MAX))
+}
+
+/// Searches for a sequence of at most `max_pieces` placements from `state` that leaves
+/// the matrix completely empty, i.e. a perfect clear. Placements are generated the same
+/// way as in the main search under `cfg`. Returns `None` if there is no such sequence
+/// within the window, or with the pieces available in the queue. If the matrix is already
+/// empty, the sequence is empty.
+pub fn find_perfect_clear(
+    shtb: &ShapeTable,
+    cfg: &Config,
+    state: &State,
+    max_pieces: usize,
+) -> Option<Vec<Placement>> {
+    if state.is_empty() {
+        return Some(vec![]);
+    }
+    let mut pfind = PlaceFinder::new(shtb, cfg.movement)
+        .with_order(cfg.placement_order)
+        .with_kick_system(cfg.kick_system)
+        .with_max_soft_drop(cfg.max_soft_drop)
+        .with_require_clear(cfg.require_clear);
+    let mut visited = std::collections::HashSet::new();
+    let mut plan = vec![];
+    if perfect_clear_dfs(&mut pfind, &mut visited, state, max_pieces, &mut plan) {
+        Some(plan)
+    } else {
+        None
+    }
+}
+
+/// Depth-first search for `find_perfect_clear`. On success, returns `true` with the
+/// placements leading to the perfect clear appended to `plan`.
+fn perfect_clear_dfs(
+    pfind: &mut PlaceFinder,
+    visited: &mut std::collections::HashSet<State>,
+    state: &State,
+    pieces_left: usize,
+    plan: &mut Vec<Placement>,
+) -> bool {
+    // every row with blocks in it has to be filled to be cleared, so give up once there
+    // are more empty cells in those rows than the remaining pieces can fill.
+    let matrix = state.matrix();
+    let empty_cells: usize = (0..matrix.rows())
+        .map(|i| (0..matrix.cols()).filter(|&j| matrix.get((i, j))).count())
+        .filter(|&filled| filled > 0)
+        .map(|filled| matrix.cols() as usize - filled)
+        .sum();
+    if pieces_left == 0 || empty_cells > pieces_left * 4 {
+        return false;
+    }
+    if !visited.insert(state.clone()) {
+        return false;
+    }
+    let placements = state.placements(pfind).collect::<Vec<_>>();
+    for pl in placements {
+        let mut succ = state.clone();
+        succ.place(&pl);
+        plan.push(Placement::from(&pl));
+        if succ.is_empty() || perfect_clear_dfs(pfind, visited, &succ, pieces_left - 1, plan) {
+            return true;
+        }
+        plan.pop();
+    }
+    false
+}
+
+/// Number of placements of each piece considered by `optimal_order`, which keeps the
+/// search bounded.
+const OPTIMAL_ORDER_WIDTH: usize = 4;
+
+/// Finds the order in which to place `pieces` onto `board` that clears the most lines,
+/// e.g. to check how well a bag could have been used. Every order of the pieces is tried,
+/// but only the `OPTIMAL_ORDER_WIDTH` placements of each piece that clear the most lines
+/// (then score the lowest under `params`) are searched, so this is only feasible for a
+/// handful of pieces. Ties in lines cleared are broken by the score of the final matrix.
+/// Hold is never used. Returns an empty plan if not all pieces can be placed.
+pub fn optimal_order(
+    shtb: &ShapeTable,
+    params: &Parameters,
+    board: BasicMatrix,
+    pieces: &[Color],
+) -> Vec<Placement> {
+    let mut pfind = PlaceFinder::new(shtb, MovementModel::Full);
+    let mut pieces = pieces.to_vec();
+    pieces.sort();
+    optimal_order_dfs(&mut pfind, params, &board, &pieces).map_or(vec![], |(_, _, mut plan)| {
+        plan.reverse();
+        plan
+    })
+}
+
+/// Depth-first search for `optimal_order`, placing the (sorted) `pieces` onto `matrix`.
+/// Returns the number of lines cleared, the final score and the placements in reverse
+/// order of the best plan found.
+fn optimal_order_dfs(
+    pfind: &mut PlaceFinder,
+    params: &Parameters,
+    matrix: &BasicMatrix,
+    pieces: &[Color],
+) -> Option<(usize, i64, Vec<Placement>)> {
+    if pieces.is_empty() {
+        return Some((0, eval::eval(matrix).score(params), vec![]));
+    }
+    let mut best: Option<(usize, i64, Vec<Placement>)> = None;
+    for k in 0..pieces.len() {
+        // the same plans would be found again for a repeated piece
+        if k > 0 && pieces[k] == pieces[k - 1] {
+            continue;
+        }
+        let state: State = Snapshot {
+            hold: None,
+            queue: vec![pieces[k]],
+            matrix: matrix.clone(),
+        }
+        .into();
+        let mut candidates = state
+            .placements(pfind)
+            .filter(|pl| !pl.did_hold)
+            .map(|pl| {
+                let lines = state.rows_cleared_by(&pl).len();
+                let mut succ = state.clone();
+                succ.place(&pl);
+                let score = eval::eval(succ.matrix()).score(params);
+                (lines, score, pl, succ)
+            })
+            .collect::<Vec<_>>();
+        candidates.sort_by_key(|&(lines, score, _, _)| (std::cmp::Reverse(lines), score));
+        candidates.truncate(OPTIMAL_ORDER_WIDTH);
+        let rest = [&pieces[..k], &pieces[k + 1..]].concat();
+        for (lines, _, pl, succ) in candidates {
+            let (rest_lines, score, mut plan) =
+                match optimal_order_dfs(pfind, params, succ.matrix(), &rest) {
+                    Some(sub) => sub,
+                    None => continue,
+                };
+            let total = lines + rest_lines;
+            if best.as_ref().map_or(true, |&(best_lines, best_score, _)| {
+                (std::cmp::Reverse(total), score) < (std::cmp::Reverse(best_lines), best_score)
+            }) {
+                plan.push(Placement::from(&pl));
+                best = Some((total, score, plan));
+            }
+        }
+    }
+    best
+}
+
+/// Returns the name of the opener in `library` whose matrix is the same as `matrix`, or
+/// the same as its mirror image, e.g. to recognize a board built from one of a few known
+/// openers. Returns `None` if no opener matches.
+pub fn match_opener<'a>(
+    matrix: &BasicMatrix,
+    library: &'a [(String, BasicMatrix)],
+) -> Option<&'a str> {
+    let mirrored = matrix.mirrored();
+    library
+        .iter()
+        .find(|(_, opener)| *opener == *matrix || *opener == mirrored)
+        .map(|(name, _)| name.as_str())
+}
+
+/// Outcome of a game played by `play_out`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct GameResult {
+    /// Number of pieces placed.
+    pub pieces: usize,
+    /// Total number of lines cleared.
+    pub lines: usize,
+    /// `true` if the game ended because the stack grew taller than the playfield, or
+    /// because no placement was possible for the pieces left.
+    pub topped_out: bool,
+    /// Matrix at the end of the game.
+    pub matrix: BasicMatrix,
+}
+
+/// Plays a game on an empty 10 column playfield, 20 rows tall, by repeatedly making the
+/// `best_placement` under `params`, e.g. for benchmarking parameters. See `play_out_on`.
+pub fn play_out(
+    shtb: &ShapeTable,
+    params: &Parameters,
+    initial_queue: Vec<Color>,
+    bag: BagModel,
+    seed: u64,
+    max_pieces: usize,
+) -> GameResult {
+    play_out_on(shtb, params, initial_queue, bag, seed, (20, 10), max_pieces)
+}
+
+/// Like `play_out`, but on a playfield `rows` tall (but no taller than `MAX_ROWS`) and
+/// `cols` wide, given as `(rows, cols)`. Pieces are dealt from `initial_queue`, followed
+/// by pieces generated from `bag`: each bag is shuffled, or under `BagModel::Random` each
+/// piece is drawn, using a random number generator seeded with `seed`, so the game is the
+/// same for the same seed. The game ends after `max_pieces` placements, once `bag` can't
+/// generate any more pieces (i.e. an empty `BagModel::Custom`), or when the stack grows
+/// taller than `rows`.
+pub fn play_out_on(
+    shtb: &ShapeTable,
+    params: &Parameters,
+    initial_queue: Vec<Color>,
+    bag: BagModel,
+    seed: u64,
+    (rows, cols): (u16, u16),
+    max_pieces: usize,
+) -> GameResult {
+    let rows = std::cmp::min(rows, MAX_ROWS);
+    let colors = shtb.colors();
+    let mut rng = seed;
+    // pieces dealt so far in the current bag
+    let mut dealt = initial_queue.clone();
+    let mut state: State = Snapshot {
+        hold: None,
+        queue: initial_queue,
+        matrix: BasicMatrix::with_cols(cols),
+    }
+    .into();
+    let (mut pieces, mut lines, mut topped_out) = (0, 0, false);
+    while pieces < max_pieces {
+        // keep a piece in the next queue after the current one, so that hold is usable
+        if state.queue_len() < 2 {
+            let next = deal(&bag, &colors, &dealt, &mut rng);
+            state.extend_queue(&next);
+            // the rest of the bag was dealt, so the next bag starts fresh
+            dealt.clear();
+        }
+        let best = match best_placement_where(shtb, params, &state, |_| true) {
+            Some(best) => best,
+            None => {
+                topped_out = state.queue_len() > 0;
+                break;
+            }
+        };
+        lines += best.lines;
+        state = best.succ;
+        pieces += 1;
+        if state.matrix().rows() > rows {
+            topped_out = true;
+            break;
+        }
+    }
+    GameResult {
+        pieces,
+        lines,
+        topped_out,
+        matrix: state.matrix().clone(),
+    }
+}
+
+/// Generates the next pieces for `play_out_on` after the pieces `dealt` from the current
+/// bag: the rest of the bag in a random order, or a single random piece under
+/// `BagModel::Random`. `colors` is the set of every piece.
+fn deal(bag: &BagModel, colors: &[Color], dealt: &[Color], rng: &mut u64) -> Vec<Color> {
+    let mut next = match bag {
+        BagModel::Random => vec![colors[next_random(rng, colors.len())]],
+        _ => bag.rest_of_bag(colors, dealt),
+    };
+    // Fisher-Yates shuffle
+    for i in (1..next.len()).rev() {
+        next.swap(i, next_random(rng, i + 1));
+    }
+    next
+}
+
+/// Returns a number in `0..n` from the random number generator with state `rng` (a
+/// SplitMix64 generator), advancing it.
+fn next_random(rng: &mut u64, n: usize) -> usize {
+    *rng = rng.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *rng;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    ((z ^ (z >> 31)) % n as u64) as usize
+}
+
 // AI interface
 
 // Re-export
-pub use analysis::{Analysis, AnalysisDone, MoveId};
+pub use analysis::{
+    analyze_deterministic, verify_trace, Analysis, AnalysisDone, Frontier, MoveId, ReconstructError,
+};
+pub use state::State;
 
 /// An instance of the Blockfish AI. Holds engine configuration and can be used to spawn
 /// an analysis.
@@ -63,6 +567,7 @@ pub struct AI {
     config: Config,
     shape_table: std::sync::Arc<ShapeTable>,
     all_tx: Option<std::sync::mpsc::Sender<Suggestion>>,
+    heuristic: Option<std::sync::Arc<Heuristic>>,
 }
 
 impl AI {
@@ -72,6 +577,7 @@ impl AI {
             config,
             shape_table: std::sync::Arc::new(srs()),
             all_tx: None,
+            heuristic: None,
         }
     }
 
@@ -85,14 +591,62 @@ impl AI {
 
     /// Begins a new analysis of `snapshot`, returning a handle to it.
     pub fn analyze(&mut self, snapshot: Snapshot) -> Analysis {
-        analysis::spawn(
+        let opts = analysis::SpawnOptions {
+            all_suggestions_tx: self.all_tx.take(),
+            heuristic: self.heuristic.clone(),
+            resume: None,
+        };
+        analysis::spawn_with(
             self.shape_table.clone(),
             self.config.clone(),
             snapshot.into(),
-            self.all_tx.take(),
+            opts,
         )
     }
 
+    /// Like `analyze`, but first checks that the score parameters fit the matrix of
+    /// `snapshot` (see `Parameters::check_column_weights`), returning the error instead
+    /// of searching with weights that don't line up with the columns.
+    pub fn try_analyze(&mut self, snapshot: Snapshot) -> Result<Analysis, ColumnWeightsError> {
+        self.config
+            .parameters
+            .check_column_weights(snapshot.matrix.cols())?;
+        Ok(self.analyze(snapshot))
+    }
+
+    /// Begins a new analysis of `snapshot`, continuing the search from `frontier` (taken
+    /// from a previous analysis via `Analysis::take_frontier()`) if it was suspended at
+    /// the same state. Otherwise, the search starts over. The search continues until
+    /// `Config::search_limit` nodes are in the frontier, so the limit should be raised
+    /// for the search to make progress.
+    pub fn resume(&mut self, snapshot: Snapshot, frontier: Frontier) -> Analysis {
+        let opts = analysis::SpawnOptions {
+            all_suggestions_tx: self.all_tx.take(),
+            heuristic: self.heuristic.clone(),
+            resume: Some(frontier),
+        };
+        analysis::spawn_with(
+            self.shape_table.clone(),
+            self.config.clone(),
+            snapshot.into(),
+            opts,
+        )
+    }
+
+    /// Configures all subsequent analyses to use `heuristic` to rate matrices during the
+    /// search, instead of the built-in evaluation function.
+    pub fn set_heuristic(
+        &mut self,
+        heuristic: impl Fn(&BasicMatrix) -> i64 + Send + Sync + 'static,
+    ) {
+        self.heuristic = Some(std::sync::Arc::new(heuristic));
+    }
+
+    /// Configures all subsequent analyses to use the built-in evaluation function again.
+    pub fn reset_heuristic(&mut self) {
+        self.heuristic = None;
+    }
+
     /// Configures the next analysis (via `analyze()`) to send every suggestion it
     /// encounters to a non-blocking channel. Returns the rx end of that channel.
     ///
@@ -104,3 +658,416 @@ impl AI {
         rx
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::basic_matrix;
+
+    #[test]
+    fn test_suggestion_same_plan() {
+        use Input::*;
+        let sugg = |inputs: &[Input]| Suggestion {
+            inputs: inputs.to_vec(),
+            rating: 0,
+        };
+        let full = sugg(&[Left, HD, Hold, CW, HD, Right, HD]);
+        let short = sugg(&[Left, HD, Hold, CW, HD]);
+        assert!(full.same_plan(&full));
+        assert!(full.same_plan(&short));
+        assert!(short.same_plan(&full));
+        assert!(full.same_plan(&sugg(&[])));
+        // different first placement
+        assert!(!full.same_plan(&sugg(&[Right, HD])));
+        // same first placement, different second placement
+        assert!(!full.same_plan(&sugg(&[Left, HD, Hold, CCW, HD])));
+        assert!(!short.same_plan(&sugg(&[Left, HD, CW, HD])));
+    }
+
+    #[test]
+    fn test_stats_effective_branching_factor() {
+        let stats = |max_depth| Stats {
+            nodes: 4096,
+            max_depth,
+            ..Stats::default()
+        };
+        assert_eq!(stats(0).effective_branching_factor(), 0.0);
+        assert!((stats(2).effective_branching_factor() - 64.0).abs() < 1e-9);
+        assert!((stats(3).effective_branching_factor() - 16.0).abs() < 1e-9);
+        // the same number of nodes spread over a deeper search branches less
+        assert!(stats(6).effective_branching_factor() < stats(3).effective_branching_factor());
+    }
+
+    #[test]
+    fn test_placement_cells() {
+        let (xx, __) = (true, false);
+        let state: State = Snapshot {
+            hold: None,
+            queue: vec![Color::n('O')],
+            matrix: basic_matrix![[xx, xx, xx, __, __, __]],
+        }
+        .into();
+        let srs = srs();
+        let mut pfind = crate::place::PlaceFinder::new(&srs, crate::MovementModel::Full);
+        let mut cells_seen = vec![];
+        for (matrix, pls) in state.distinct_outcomes(&mut pfind) {
+            for pl in pls {
+                let mut cells = pl.cells(&srs).collect::<Vec<_>>();
+                assert_eq!(cells.len(), 4);
+                // no lines are cleared, so every cell is filled in the resulting matrix
+                assert!(cells.iter().all(|&(i, j)| matrix.get((i, j))));
+                assert!(cells.iter().all(|&(i, j)| !state.matrix().get((i, j))));
+                cells.sort();
+                cells_seen.push(cells);
+            }
+        }
+        assert!(cells_seen.contains(&vec![(0, 3), (0, 4), (1, 3), (1, 4)]));
+        assert!(cells_seen.contains(&vec![(1, 0), (1, 1), (2, 0), (2, 1)]));
+    }
+
+    #[test]
+    fn test_placement_column_from_center() {
+        let pl = |col| Placement {
+            idx: 0,
+            color: Color::n('I'),
+            orientation: Orientation::R0,
+            row: 0,
+            col,
+            did_hold: false,
+        };
+        assert_eq!(pl(0).column_from_center(10), -5);
+        assert_eq!(pl(5).column_from_center(10), 0);
+        assert_eq!(pl(9).column_from_center(10), 4);
+        assert_eq!(pl(3).column_from_center(7), 0);
+        // shape offsets can put the column left of the matrix
+        assert_eq!(pl(-1).column_from_center(10), -6);
+        for &(col, cols) in [(0, 10), (5, 10), (9, 10), (-1, 10), (0, 7), (6, 7)].iter() {
+            let from_center = pl(col).column_from_center(cols);
+            assert_eq!(
+                Placement::from_center(Color::n('I'), Orientation::R0, 0, from_center, cols),
+                pl(col),
+            );
+        }
+    }
+
+    #[test]
+    fn test_best_placement_keeping_well() {
+        let (xx, __) = (true, false);
+        let state: State = Snapshot {
+            hold: None,
+            queue: "IO".chars().map(Color::n).collect(),
+            matrix: basic_matrix![
+                [xx, xx, xx, xx, xx, __, xx, xx],
+                [xx, xx, xx, xx, xx, __, xx, xx],
+                [xx, xx, xx, xx, xx, __, xx, xx],
+                [xx, xx, xx, xx, xx, __, xx, xx],
+            ],
+        }
+        .into();
+        let srs = srs();
+        let params = Parameters::default();
+        // the I fills the well and clears four lines
+        let best = best_placement(&srs, &params, &state).unwrap();
+        assert!(best.cells(&srs).any(|(_, j)| j == 5));
+
+        for well_col in 0..8 {
+            let pl = best_placement_keeping_well(&srs, &params, &state, well_col).unwrap();
+            assert!(pl.cells(&srs).all(|(_, j)| j != well_col), "{:?}", pl);
+        }
+        let empty: State = Snapshot {
+            hold: None,
+            queue: vec![],
+            matrix: BasicMatrix::with_cols(8),
+        }
+        .into();
+        assert_eq!(best_placement_keeping_well(&srs, &params, &empty, 0), None);
+    }
+
+    #[test]
+    fn test_hold_decision() {
+        let (xx, __) = (true, false);
+        let well = basic_matrix![
+            [xx, xx, xx, xx, xx, xx, xx, xx, xx, __],
+            [xx, xx, xx, xx, xx, xx, xx, xx, xx, __],
+            [xx, xx, xx, xx, xx, xx, xx, xx, xx, __],
+            [xx, xx, xx, xx, xx, xx, xx, xx, xx, __],
+        ];
+        let state = |hold: Option<char>, queue: &str| -> State {
+            Snapshot {
+                hold: hold.map(Color::n),
+                queue: queue.chars().map(Color::n).collect(),
+                matrix: well.clone(),
+            }
+            .into()
+        };
+        let srs = srs();
+        let params = Parameters::default();
+
+        // holding the S gets the I into the well
+        let decision = hold_decision(&srs, &params, &state(None, "SI"));
+        assert!(decision.hold);
+        assert!(decision.score_gap.unwrap() > 0);
+        let decision = hold_decision(&srs, &params, &state(Some('S'), "I"));
+        assert!(!decision.hold);
+        assert!(decision.score_gap.unwrap() > 0);
+        // nothing to hold
+        let decision = hold_decision(&srs, &params, &state(None, "S"));
+        assert_eq!(
+            decision,
+            HoldDecision {
+                hold: false,
+                score_gap: None
+            }
+        );
+    }
+
+    #[test]
+    fn test_cheese_plan() {
+        let (xx, __) = (true, false);
+        let state: State = Snapshot {
+            hold: None,
+            queue: "TIOLJSZTIOLJSZ".chars().map(Color::n).collect(),
+            matrix: basic_matrix![
+                [xx, xx, xx, __, xx, xx, xx, xx, xx, xx],
+                [xx, xx, xx, xx, xx, xx, xx, __, xx, xx],
+                [xx, __, xx, xx, xx, xx, xx, xx, xx, xx],
+                [xx, xx, xx, xx, xx, __, xx, xx, xx, xx],
+                [xx, xx, xx, xx, xx, xx, xx, xx, __, xx],
+                [xx, xx, __, xx, xx, xx, xx, xx, xx, xx],
+            ],
+        }
+        .into();
+        let srs = std::sync::Arc::new(srs());
+        let plan = cheese_plan(srs.clone(), &Parameters::default(), &state);
+        assert!(!plan.is_empty());
+
+        let mut matrix = state.matrix().clone();
+        let mut cleared_bottom = false;
+        for pl in plan.iter() {
+            assert!(!cleared_bottom, "plan continues after clearing all garbage");
+            for cell in pl.cells(&srs) {
+                matrix.set(cell);
+            }
+            cleared_bottom |= matrix.sift_rows();
+        }
+        assert!(cleared_bottom, "{:?}", plan);
+        assert_eq!(cheese_rows(&matrix), 0);
+
+        let flat: State = Snapshot {
+            hold: None,
+            queue: "TIO".chars().map(Color::n).collect(),
+            matrix: basic_matrix![[xx, xx, __, __]],
+        }
+        .into();
+        assert!(cheese_plan(srs, &Parameters::default(), &flat).is_empty());
+    }
+
+    #[test]
+    fn test_find_perfect_clear() {
+        let (xx, __) = (true, false);
+        // the perfect clear opener after the first bag, with its T in hold and the second
+        // bag in the queue
+        let state: State = Snapshot {
+            hold: Some(Color::n('T')),
+            queue: "ITOSZJL".chars().map(Color::n).collect(),
+            matrix: basic_matrix![
+                [xx, xx, xx, xx, __, __, __, xx, xx, xx],
+                [xx, xx, xx, __, __, __, xx, xx, xx, xx],
+                [xx, xx, xx, __, __, __, __, __, xx, xx],
+                [xx, xx, __, __, __, __, __, xx, xx, xx],
+            ],
+        }
+        .into();
+        let shtb = srs();
+        let cfg = Config::default();
+        // the 16 empty cells take four pieces
+        assert_eq!(find_perfect_clear(&shtb, &cfg, &state, 3), None);
+        let plan = find_perfect_clear(&shtb, &cfg, &state, 4).unwrap();
+        assert_eq!(plan.len(), 4);
+        // the placements come from the config, and the opener can't be built with hard
+        // drops alone
+        let hard_drop_only = Config {
+            movement: MovementModel::HardDropOnly,
+            ..Config::default()
+        };
+        assert_eq!(find_perfect_clear(&shtb, &hard_drop_only, &state, 4), None);
+        let mut pfind = PlaceFinder::new(&shtb, cfg.movement);
+        let mut state = state;
+        for placement in plan.iter() {
+            let pl = state
+                .placements(&mut pfind)
+                .find(|pl| Placement::from(pl) == *placement)
+                .unwrap();
+            state.place(&pl);
+        }
+        assert!(state.is_empty());
+        // nothing needs to be placed once the matrix is empty
+        assert_eq!(find_perfect_clear(&shtb, &cfg, &state, 0), Some(vec![]));
+    }
+
+    #[test]
+    fn test_match_opener() {
+        let (xx, __) = (true, false);
+        let pco = basic_matrix![
+            [xx, xx, xx, xx, __, __, xx, xx, xx, xx],
+            [xx, xx, xx, __, __, __, xx, xx, xx, xx],
+            [xx, xx, xx, xx, __, __, __, xx, xx, xx],
+            [xx, xx, xx, xx, __, __, __, __, __, __],
+        ];
+        let library = vec![
+            ("PCO".to_string(), pco.clone()),
+            (
+                "flat".to_string(),
+                basic_matrix![[xx, xx, xx, xx, __, __, __, __, __, __]],
+            ),
+        ];
+        assert_eq!(match_opener(&pco, &library), Some("PCO"));
+        assert_eq!(match_opener(&pco.mirrored(), &library), Some("PCO"));
+        let unrelated = basic_matrix![
+            [xx, xx, __, xx, xx, xx, xx, xx, xx, xx],
+            [xx, xx, __, __, __, __, __, __, __, __],
+        ];
+        assert_eq!(match_opener(&unrelated, &library), None);
+    }
+
+    #[test]
+    fn test_optimal_order() {
+        let (xx, __) = (true, false);
+        let board = basic_matrix![
+            [xx, xx, xx, xx, xx, xx, xx, xx, xx, __],
+            [xx, xx, xx, xx, xx, xx, xx, xx, xx, __],
+            [xx, xx, xx, xx, xx, xx, xx, xx, xx, __],
+            [xx, xx, xx, xx, xx, xx, xx, xx, xx, __],
+            [xx, xx, xx, xx, xx, xx, xx, xx, xx, __],
+            [xx, xx, xx, xx, xx, xx, xx, xx, xx, __],
+        ];
+        let shtb = srs();
+        let params = Parameters::default();
+        let pieces = "OLJI".chars().map(Color::n).collect::<Vec<_>>();
+        let mut pfind = PlaceFinder::new(&shtb, MovementModel::Full);
+        // places `pieces` in order, using `choose` to pick each placement
+        let mut play = |pieces: &[Color], choose: &mut dyn FnMut(&State) -> Placement| {
+            let mut lines = 0;
+            let mut matrix = board.clone();
+            for &color in pieces.iter() {
+                let mut state: State = Snapshot {
+                    hold: None,
+                    queue: vec![color],
+                    matrix,
+                }
+                .into();
+                let placement = choose(&state);
+                let pl = state
+                    .placements(&mut pfind)
+                    .find(|pl| Placement::from(pl) == placement)
+                    .unwrap();
+                lines += state.rows_cleared_by(&pl).len();
+                state.place(&pl);
+                matrix = state.matrix().clone();
+            }
+            lines
+        };
+        // placing the pieces greedily in the given order clears fewer lines
+        let greedy = play(&pieces, &mut |state| {
+            best_placement(&shtb, &params, state).unwrap()
+        });
+        assert_eq!(greedy, 4);
+        let plan = optimal_order(&shtb, &params, board.clone(), &pieces);
+        let order = plan.iter().map(|pl| pl.color).collect::<Vec<_>>();
+        let mut sorted = order.clone();
+        sorted.sort();
+        assert_eq!(sorted, "IJLO".chars().map(Color::n).collect::<Vec<_>>());
+        let mut plan = plan.into_iter();
+        assert_eq!(play(&order, &mut |_| plan.next().unwrap()), 6);
+    }
+
+    #[test]
+    fn test_play_out() {
+        let shtb = srs();
+        let params = Parameters::default();
+        let queue = "LJSZOIT".chars().map(Color::n).collect::<Vec<_>>();
+        let game = play_out(&shtb, &params, queue.clone(), BagModel::SevenBag, 1, 70);
+        assert_eq!(game.pieces, 70);
+        assert!(!game.topped_out);
+        assert_eq!(game.lines, 27);
+        assert_eq!(game.matrix.cols(), 10);
+        // every cell placed was either cleared or is still in the matrix
+        let m = &game.matrix;
+        let cells = (0..m.rows())
+            .flat_map(|i| (0..m.cols()).map(move |j| (i, j)))
+            .filter(|&ij| m.get(ij))
+            .count();
+        assert_eq!(game.pieces * 4, game.lines * 10 + cells);
+        // the game only depends on the seed
+        let replay = play_out(&shtb, &params, queue.clone(), BagModel::SevenBag, 1, 70);
+        assert_eq!(replay, game);
+        let other = play_out(&shtb, &params, queue.clone(), BagModel::SevenBag, 2, 70);
+        assert_ne!(other.matrix, game.matrix);
+        // pieces keep coming under a random bag
+        let random = play_out(&shtb, &params, queue[..5].to_vec(), BagModel::Random, 1, 70);
+        assert_eq!((random.pieces, random.topped_out), (70, false));
+        // O pieces alone can't fill a row 3 columns wide, so the stack tops out
+        let o_bag = BagModel::Custom(vec![Color::n('O')]);
+        let narrow = play_out_on(&shtb, &params, vec![], o_bag.clone(), 1, (6, 3), 70);
+        assert!(narrow.topped_out);
+        assert_eq!(narrow.lines, 0);
+        assert_eq!(narrow.matrix.cols(), 3);
+        assert!(narrow.pieces < 70);
+        // the playfield can't be taller than `MAX_ROWS`
+        let tall = play_out_on(&shtb, &params, vec![], o_bag, 1, (u16::MAX, 3), usize::MAX);
+        assert!(tall.topped_out);
+        assert_eq!(tall.matrix.rows(), MAX_ROWS);
+        // the game ends once an empty bag runs out
+        let empty = BagModel::Custom(vec![]);
+        let short = play_out(&shtb, &params, queue[..5].to_vec(), empty, 1, 70);
+        assert_eq!((short.pieces, short.topped_out), (5, false));
+    }
+
+    #[test]
+    fn test_board_rating() {
+        let (xx, __) = (true, false);
+        let matrix = basic_matrix![
+            [xx, xx, xx, __, xx, xx],
+            [xx, __, xx, xx, xx, xx],
+            [__, xx, xx, xx, xx, __],
+        ];
+        let state: State = Snapshot {
+            hold: None,
+            queue: vec![Color::n('T')],
+            matrix: matrix.clone(),
+        }
+        .into();
+        let params = Parameters::default();
+        assert_eq!(
+            board_rating(&params, &state),
+            eval::eval(&matrix).score(&params)
+        );
+        // the queue is ignored even if the score would be capped by its length
+        let params = Parameters {
+            queue_aware: true,
+            ..Parameters::default()
+        };
+        let ev = eval::eval(&matrix);
+        assert_ne!(
+            ev.score(&params),
+            ev.score_with_queue(&params, state.queue_len())
+        );
+        assert_eq!(board_rating(&params, &state), ev.score(&params));
+    }
+
+    #[test]
+    fn test_ai_try_analyze() {
+        let mut ai = AI::new(Config::default());
+        ai.config_mut().parameters.column_weights = Some(vec![1; 4]);
+        let snapshot = |cols| Snapshot {
+            hold: None,
+            queue: "TI".chars().map(Color::n).collect(),
+            matrix: BasicMatrix::with_cols(cols),
+        };
+        let err = ai.try_analyze(snapshot(10)).err().unwrap();
+        assert_eq!((err.expected, err.got), (10, 4));
+        let mut analysis = ai.try_analyze(snapshot(4)).unwrap();
+        analysis.wait();
+        assert!(analysis.all_moves().next().is_some());
+    }
+}
@@ -1,14 +1,23 @@
-use super::Snapshot;
+use super::{
+    eval::{eval, hole_count, tspin_slots},
+    Placement, Snapshot,
+};
 use crate::{
     common::Color,
+    config::Parameters,
+    finesse::FinesseFinder,
     matrix::BasicMatrix,
     place::{Place, PlaceFinder},
 };
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 /// A game state: matrix plus queue/hold.
 ///
 /// Represention should prioritize efficiently taking pieces from the queue/hold slot.
-#[derive(Clone)]
+/// The hold piece, if any, is stored on top of the reversed queue and `has_held` is set.
+/// While hold is empty, holding consumes the next piece of the queue instead.
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub struct State {
     matrix: BasicMatrix,
     queue_rev: Vec<Color>,
@@ -25,10 +34,52 @@ impl State {
         self.reached_goal
     }
 
+    /// Returns `true` if there are no occupied cells in the matrix.
+    pub fn is_empty(&self) -> bool {
+        let cols = self.matrix.cols();
+        (0..self.matrix.rows()).all(|i| (0..cols).all(|j| !self.matrix.get((i, j))))
+    }
+
     pub fn is_terminal(&self) -> bool {
         self.reached_goal || self.queue_rev.is_empty()
     }
 
+    /// Returns the number of pieces left to be placed, including the hold piece.
+    pub fn queue_len(&self) -> usize {
+        self.queue_rev.len()
+    }
+
+    /// Returns the pieces in the next queue in order, not including the hold piece.
+    pub fn queue<'a>(&'a self) -> impl Iterator<Item = Color> + 'a {
+        // the hold slot may have been used up by the last placement
+        let len = self.queue_rev.len().saturating_sub(self.has_held as usize);
+        self.queue_rev[..len].iter().rev().cloned()
+    }
+
+    /// Returns the current piece, i.e. the one that will be placed without holding, or
+    /// `None` if the next queue is empty.
+    pub fn current(&self) -> Option<Color> {
+        self.next().0
+    }
+
+    /// Appends `pieces` to the end of the next queue.
+    pub fn extend_queue(&mut self, pieces: &[Color]) {
+        if self.queue_rev.is_empty() {
+            // the hold piece was already placed
+            self.has_held = false;
+        }
+        self.queue_rev.splice(0..0, pieces.iter().rev().cloned());
+    }
+
+    /// Returns the piece currently in the hold slot, or `None` if hold is empty.
+    pub fn hold(&self) -> Option<Color> {
+        if self.has_held {
+            self.queue_rev.last().cloned()
+        } else {
+            None
+        }
+    }
+
     /// Returns the next available piece colors, as pair `(next_piece, hold_piece)`.
     /// Either may be `None` if not available (e.g. the next queue is empty).
     ///
@@ -69,6 +120,176 @@ impl State {
         pfind
     }
 
+    /// Like `placements`, but only produces placements for the shape described by
+    /// `color`. Produces nothing if `color` is neither the next piece nor the hold piece.
+    pub fn placements_of<'p, 's>(
+        &self,
+        pfind: &'p mut PlaceFinder<'s>,
+        color: Color,
+    ) -> &'p mut PlaceFinder<'s> {
+        pfind.reset_matrix(&self.matrix);
+        let (color_nh, color_h) = self.next();
+        if color_nh == Some(color) {
+            pfind.push_shape(color, false);
+        } else if color_h == Some(color) {
+            pfind.push_shape(color, true);
+        }
+        pfind
+    }
+
+    /// Like `placements`, but filters out any placement that `ffind` cannot find an input
+    /// sequence for, i.e. those that are unreachable from the spawn location.
+    pub fn reachable_placements<'a, 's: 'a>(
+        &'a self,
+        pfind: &'a mut PlaceFinder<'s>,
+        ffind: &'a mut FinesseFinder,
+    ) -> impl Iterator<Item = Place<'s>> + 'a {
+        let matrix = &self.matrix;
+        self.placements(pfind)
+            .filter(move |pl| ffind.find(matrix, pl.shape, pl.normal()).is_ok())
+    }
+
+    /// Like `placements`, but only produces placements of the current piece (without
+    /// hold) that don't create any new covered holes, as counted by `hole_count`.
+    pub fn clean_placements<'a, 's: 'a>(
+        &'a self,
+        pfind: &'a mut PlaceFinder<'s>,
+    ) -> impl Iterator<Item = Place<'s>> + 'a {
+        let matrix = &self.matrix;
+        let holes = hole_count(matrix);
+        self.placements(pfind)
+            .filter(|pl| !pl.did_hold)
+            .filter(move |pl| {
+                let mut matrix = matrix.clone();
+                pl.shape.blit_to(&mut matrix, pl.tf);
+                matrix.sift_rows();
+                hole_count(&matrix) <= holes
+            })
+    }
+
+    /// Returns `true` if the current piece can be placed without creating any new holes,
+    /// i.e. `clean_placements` produces anything. If not, the player is probably forced
+    /// to bury something, or to use hold.
+    pub fn has_clean_spot(&self, pfind: &mut PlaceFinder) -> bool {
+        self.clean_placements(pfind).next().is_some()
+    }
+
+    /// Returns each placement for this state (see `placements`) along with the score of
+    /// the resulting matrix, sorted from best to worst score. Placements with equal scores
+    /// are kept in the order they were generated.
+    pub fn scored_placements(
+        &self,
+        pfind: &mut PlaceFinder,
+        params: &Parameters,
+    ) -> Vec<(Placement, i64)> {
+        let mut scored = self
+            .placements(pfind)
+            .map(|pl| {
+                let mut succ = self.clone();
+                succ.place(&pl);
+                (Placement::from(&pl), eval(succ.matrix()).score(params))
+            })
+            .collect::<Vec<_>>();
+        scored.sort_by_key(|&(_, score)| score);
+        scored
+    }
+
+    /// Returns the placements for this state (see `placements`) that set up a T-spin, i.e.
+    /// after which there are more T-spin slots in the matrix than before, as found by
+    /// `tspin_slots`.
+    pub fn tspin_setups(&self, pfind: &mut PlaceFinder) -> Vec<Placement> {
+        let slots = tspin_slots(&self.matrix).len();
+        self.placements(pfind)
+            .filter(|pl| {
+                let mut succ = self.clone();
+                succ.place(pl);
+                tspin_slots(succ.matrix()).len() > slots
+            })
+            .map(|pl| Placement::from(&pl))
+            .collect()
+    }
+
+    /// Groups the placements for this state (see `placements`) by the matrix that results
+    /// from each one, after clearing lines. Many placements lead to identical boards, e.g.
+    /// different orientations of symmetrical pieces, so this gives the set of distinct
+    /// outcomes. Groups are returned in the order their first placement was found.
+    pub fn distinct_outcomes(&self, pfind: &mut PlaceFinder) -> Vec<(BasicMatrix, Vec<Placement>)> {
+        let mut outcomes: Vec<(BasicMatrix, Vec<Placement>)> = vec![];
+        let mut index: HashMap<BasicMatrix, usize> = HashMap::new();
+        for pl in self.placements(pfind) {
+            let mut matrix = self.matrix.clone();
+            pl.shape.blit_to(&mut matrix, pl.tf);
+            matrix.sift_rows();
+            let placement = Placement::from(&pl);
+            match index.get(&matrix) {
+                Some(&i) => outcomes[i].1.push(placement),
+                None => {
+                    index.insert(matrix.clone(), outcomes.len());
+                    outcomes.push((matrix, vec![placement]));
+                }
+            }
+        }
+        outcomes
+    }
+
+    /// Returns the horizontal mirror image of this state: the matrix is flipped and every
+    /// piece is replaced by its mirror counterpart.
+    pub fn mirror(&self) -> State {
+        State {
+            matrix: self.matrix.mirrored(),
+            queue_rev: self.queue_rev.iter().map(Color::mirror).collect(),
+            has_held: self.has_held,
+            reached_goal: self.reached_goal,
+        }
+    }
+
+    /// Returns a key identifying this state, which is equal for states that are mirror
+    /// images of each other. Useful for deduplicating states, e.g. in an opening book.
+    pub fn canonical_key(&self) -> Vec<u8> {
+        std::cmp::min(self.key(), self.mirror().key())
+    }
+
+    /// Encodes this state as bytes.
+    fn key(&self) -> Vec<u8> {
+        let mut key = Vec::with_capacity(8 + self.matrix.rows() as usize * 2);
+        key.extend_from_slice(&self.matrix.cols().to_le_bytes());
+        key.extend_from_slice(&self.matrix.rows().to_le_bytes());
+        key.push(self.has_held as u8);
+        key.push(self.reached_goal as u8);
+        key.extend(self.queue_rev.iter().map(|c| c.as_char() as u8));
+        key.push(0);
+        for i in 0..self.matrix.rows() {
+            let mut row_bits = 0u16;
+            for j in 0..self.matrix.cols() {
+                row_bits |= (self.matrix.get((i, j)) as u16) << j;
+            }
+            key.extend_from_slice(&row_bits.to_le_bytes());
+        }
+        key
+    }
+
+    /// Serializes this state to a JSON string.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("BUG: state is not serializable")
+    }
+
+    /// Deserializes a state from a JSON string produced by `to_json`.
+    pub fn from_json(s: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(s)
+    }
+
+    /// Returns the indices of the rows that would become full if the given placement were
+    /// applied to this state, in increasing order. Indices are rows of the current matrix,
+    /// before any lines are cleared.
+    pub fn rows_cleared_by(&self, pl: &Place) -> Vec<u16> {
+        let mut matrix = self.matrix.clone();
+        pl.shape.blit_to(&mut matrix, pl.tf);
+        let cols = matrix.cols();
+        (0..matrix.rows())
+            .filter(|&i| (0..cols).all(|j| matrix.get((i, j))))
+            .collect()
+    }
+
     /// Applies the given placement to this state, modifying the queue and matrix.
     pub fn place(&mut self, pl: &Place) {
         pl.shape.blit_to(&mut self.matrix, pl.tf);
@@ -114,7 +335,7 @@ impl From<Snapshot> for State {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::{shape::srs, Orientation::*};
+    use crate::{basic_matrix, shape::srs, MovementModel, Orientation::*};
 
     #[test]
     fn test_state_operations() {
@@ -140,6 +361,24 @@ mod test {
         assert!(s.is_terminal());
         assert_eq!(s.matrix.rows(), 7);
         assert_eq!(s.next(), (None, None));
+        assert_eq!(s.queue().count(), 0);
+
+        // the last piece is placed from hold
+        let mut s: State = Snapshot {
+            hold: Some(Color::n('S')),
+            queue: vec![Color::n('L')],
+            matrix: BasicMatrix::with_cols(10),
+        }
+        .into();
+        let place = |s: &mut State, color, j, hold| {
+            let shape = srs.shape(Color::n(color)).unwrap();
+            s.place(&Place::new(shape, (0, j, R0), hold));
+        };
+        place(&mut s, 'L', 0, false);
+        place(&mut s, 'S', 4, true);
+        assert!(s.is_terminal());
+        assert_eq!(s.next(), (None, None));
+        assert_eq!(s.queue().count(), 0);
     }
 
     #[test]
@@ -152,8 +391,14 @@ mod test {
         }
         .into();
         assert_eq!(s.next(), (Some(Color::n('L')), Some(Color::n('S'))));
+        assert_eq!(s.queue().map(|c| c.as_char()).collect::<String>(), "LTJI");
+        assert_eq!(s.current(), Some(Color::n('L')));
+        assert_eq!(s.hold(), Some(Color::n('S')));
         s.pop(true);
         assert_eq!(s.next(), (Some(Color::n('T')), Some(Color::n('L'))));
+        assert_eq!(s.current(), Some(Color::n('T')));
+        assert_eq!(s.hold(), Some(Color::n('L')));
+        assert_eq!(s.queue().map(|c| c.as_char()).collect::<String>(), "TJI");
         s.pop(false);
         assert_eq!(s.next(), (Some(Color::n('J')), Some(Color::n('L'))));
         // nothing previously in hold
@@ -164,8 +409,323 @@ mod test {
         }
         .into();
         assert_eq!(s.next(), (Some(Color::n('L')), Some(Color::n('T'))));
+        assert_eq!(s.queue().map(|c| c.as_char()).collect::<String>(), "LTJI");
+        assert_eq!(s.current(), Some(Color::n('L')));
+        assert_eq!(s.hold(), None);
         s.pop(true);
         assert_eq!(s.next(), (Some(Color::n('J')), Some(Color::n('L'))));
+        assert_eq!(s.current(), Some(Color::n('J')));
+        assert_eq!(s.hold(), Some(Color::n('L')));
+        s.extend_queue(&[Color::n('O')]);
+        assert_eq!(s.queue().map(|c| c.as_char()).collect::<String>(), "JIO");
+        assert_eq!(s.hold(), Some(Color::n('L')));
+    }
+
+    #[test]
+    fn test_state_empty_vs_filled_hold() {
+        let srs = srs();
+        let mut pfind = PlaceFinder::new(&srs, MovementModel::Full);
+        let mut count_of = |c| {
+            let s: State = Snapshot {
+                hold: None,
+                queue: vec![Color::n(c)],
+                matrix: BasicMatrix::with_cols(10),
+            }
+            .into();
+            s.placements(&mut pfind).count()
+        };
+        let (o_count, i_count, t_count) = (count_of('O'), count_of('I'), count_of('T'));
+
+        let snapshot = |hold, queue: &str| Snapshot {
+            hold,
+            queue: queue.chars().map(Color::n).collect(),
+            matrix: BasicMatrix::with_cols(10),
+        };
+
+        // holding with an empty hold slot consumes the 2nd piece of the queue
+        let empty: State = snapshot(None, "OI").into();
+        assert_eq!(empty.hold(), None);
+        assert_eq!(empty.placements(&mut pfind).count(), o_count + i_count);
+        let filled: State = snapshot(Some(Color::n('T')), "OI").into();
+        assert_eq!(filled.hold(), Some(Color::n('T')));
+        assert_eq!(filled.placements(&mut pfind).count(), o_count + t_count);
+
+        // with an empty hold slot and a single piece, there is nothing to hold
+        let empty: State = snapshot(None, "O").into();
+        assert_eq!(empty.placements(&mut pfind).count(), o_count);
+        assert!(empty.placements(&mut pfind).all(|pl| !pl.did_hold));
+        let filled: State = snapshot(Some(Color::n('T')), "O").into();
+        assert_eq!(filled.placements(&mut pfind).count(), o_count + t_count);
+
+        // holding from an empty slot fills it with the current piece
+        let mut s: State = snapshot(None, "OI").into();
+        s.pop(true);
+        assert_eq!(s.hold(), Some(Color::n('O')));
+        assert_eq!(s.next(), (None, Some(Color::n('O'))));
+    }
+
+    #[test]
+    fn test_state_rows_cleared_by() {
+        let (xx, __) = (true, false);
+        let s: State = Snapshot {
+            hold: None,
+            queue: vec![Color::n('O')],
+            matrix: basic_matrix![
+                [xx, xx, xx, __, xx, xx, xx, xx, xx, xx],
+                [xx, xx, xx, xx, xx, xx, xx, xx, __, __],
+                [xx, xx, xx, xx, xx, xx, xx, xx, __, __],
+                [xx, xx, xx, xx, xx, xx, xx, __, __, __],
+            ],
+        }
+        .into();
+        let srs = srs();
+        let mut pfind = PlaceFinder::new(&srs, MovementModel::Full);
+        let cleared = |s: &State, pfind: &mut PlaceFinder, col| {
+            let pl = s
+                .placements(pfind)
+                .find(|pl| pl.shape.cells(pl.tf).map(|(_, j)| j).min() == Some(col))
+                .unwrap();
+            s.rows_cleared_by(&pl)
+        };
+        // the O fills the gap in the middle two rows
+        assert_eq!(cleared(&s, &mut pfind, 8), vec![1, 2]);
+        assert_eq!(cleared(&s, &mut pfind, 0), Vec::<u16>::new());
+    }
+
+    #[test]
+    fn test_state_tspin_setups() {
+        let (xx, __) = (true, false);
+        let s: State = Snapshot {
+            hold: None,
+            queue: "JO".chars().map(Color::n).collect(),
+            matrix: basic_matrix![
+                [xx, xx, xx, xx, __, xx, xx, xx, xx, xx],
+                [xx, xx, xx, __, __, __, xx, xx, xx, xx],
+            ],
+        }
+        .into();
+        let srs = srs();
+        let mut pfind = PlaceFinder::new(&srs, MovementModel::Full);
+        let setups = s.tspin_setups(&mut pfind);
+        let find = |pfind: &mut PlaceFinder, cells: &[(i16, i16)]| {
+            let pl = s
+                .placements(pfind)
+                .find(|pl| pl.shape.cells(pl.tf).eq(cells.iter().cloned()))
+                .unwrap();
+            Placement::from(&pl)
+        };
+        // the J hangs over the left end of the slot
+        let overhang = find(&mut pfind, &[(2, 1), (2, 2), (2, 3), (3, 1)]);
+        assert!(setups.contains(&overhang));
+        let flat = find(&mut pfind, &[(2, 7), (2, 8), (3, 7), (3, 8)]);
+        assert!(!setups.contains(&flat));
+    }
+
+    #[test]
+    fn test_state_placements_of() {
+        let s: State = Snapshot {
+            hold: Some(Color::n('O')),
+            queue: "TI".chars().map(Color::n).collect(),
+            matrix: BasicMatrix::with_cols(10),
+        }
+        .into();
+        let srs = srs();
+        let mut pfind = PlaceFinder::new(&srs, MovementModel::Full);
+        let o_places = s
+            .placements_of(&mut pfind, Color::n('O'))
+            .collect::<Vec<_>>();
+        assert_eq!(o_places.len(), 9);
+        assert!(o_places.iter().all(|pl| pl.did_hold));
+        assert!(o_places.iter().all(|pl| pl.shape.color() == Color::n('O')));
+        let t_places = s
+            .placements_of(&mut pfind, Color::n('T'))
+            .collect::<Vec<_>>();
+        assert!(!t_places.is_empty());
+        assert!(t_places.iter().all(|pl| !pl.did_hold));
+        assert_eq!(s.placements_of(&mut pfind, Color::n('I')).count(), 0);
+    }
+
+    #[test]
+    fn test_state_reachable_placements() {
+        let (xx, __) = (true, false);
+        let s: State = Snapshot {
+            hold: None,
+            queue: vec![Color::n('O')],
+            matrix: basic_matrix![
+                [xx, xx, xx, xx, __, __],
+                [xx, __, __, xx, __, __],
+                [xx, __, __, xx, __, __],
+                [xx, xx, xx, xx, __, __],
+            ],
+        }
+        .into();
+        let srs = srs();
+        let o = srs.shape(Color::n('O')).unwrap();
+        // fits in the enclosed hole, but can't get there
+        let buried_tf = (0, 0, R0);
+        assert!(!o.intersects(s.matrix(), buried_tf));
+        let mut ffind = FinesseFinder::new(MovementModel::Full);
+        assert!(ffind.find(s.matrix(), o, o.normalize(buried_tf)).is_err());
+
+        let mut pfind = PlaceFinder::new(&srs, MovementModel::Full);
+        let all_count = s.placements(&mut pfind).count();
+        let reachable = s
+            .reachable_placements(&mut pfind, &mut ffind)
+            .collect::<Vec<_>>();
+        assert_eq!(reachable.len(), all_count);
+        assert!(reachable
+            .iter()
+            .all(|pl| pl.normal() != o.normalize(buried_tf)));
+
+        // the placement finder finds the tuck under the overhang, but it can't be reached
+        // with hard drops alone
+        let s: State = Snapshot {
+            hold: None,
+            queue: vec![Color::n('O')],
+            matrix: basic_matrix![
+                [__, __, __, __, __, __],
+                [__, __, __, __, __, __],
+                [__, __, __, __, xx, xx],
+            ],
+        }
+        .into();
+        let is_tuck = |pl: &Place| pl.shape.cells(pl.tf).any(|ij| ij == (0, 4));
+        let all = s.placements(&mut pfind).collect::<Vec<_>>();
+        assert!(all.iter().any(is_tuck));
+        let mut ffind = FinesseFinder::new(MovementModel::HardDropOnly);
+        let reachable = s
+            .reachable_placements(&mut pfind, &mut ffind)
+            .collect::<Vec<_>>();
+        assert!(!reachable.is_empty());
+        assert!(reachable.len() < all.len());
+        assert!(!reachable.iter().any(is_tuck));
+    }
+
+    #[test]
+    fn test_state_has_clean_spot() {
+        let (xx, __) = (true, false);
+        let srs = srs();
+        let mut pfind = PlaceFinder::new(&srs, MovementModel::Full);
+        let state = |queue: &str, matrix| -> State {
+            Snapshot {
+                hold: None,
+                queue: queue.chars().map(Color::n).collect(),
+                matrix,
+            }
+            .into()
+        };
+
+        let flat = state("OI", basic_matrix![[xx, xx, __, __]]);
+        assert!(flat.has_clean_spot(&mut pfind));
+        let clean = flat.clean_placements(&mut pfind).collect::<Vec<_>>();
+        assert!(clean.iter().all(|pl| !pl.did_hold));
+        assert!(clean.len() < flat.placements(&mut pfind).count());
+
+        // every spot for the O leaves a hole, even though the I in hold fits cleanly
+        let bumpy = state("OI", basic_matrix![[__, xx, __, xx]]);
+        assert!(!bumpy.has_clean_spot(&mut pfind));
+        assert_eq!(bumpy.clean_placements(&mut pfind).count(), 0);
+        let bumpy = state("IO", basic_matrix![[__, xx, __, xx]]);
+        assert!(bumpy.has_clean_spot(&mut pfind));
+
+        assert!(!state("", basic_matrix![[xx, xx, __, __]]).has_clean_spot(&mut pfind));
+    }
+
+    #[test]
+    fn test_state_scored_placements() {
+        let (xx, __) = (true, false);
+        let s: State = Snapshot {
+            hold: None,
+            queue: "TI".chars().map(Color::n).collect(),
+            matrix: basic_matrix![
+                [xx, xx, xx, __, xx, xx, xx, __],
+                [xx, __, xx, xx, xx, __, __, __],
+            ],
+        }
+        .into();
+        let srs = srs();
+        let mut pfind = PlaceFinder::new(&srs, MovementModel::Full);
+        let params = Parameters::default();
+        let scored = s.scored_placements(&mut pfind, &params);
+        assert_eq!(scored.len(), s.placements(&mut pfind).count());
+        assert!(scored.windows(2).all(|w| w[0].1 <= w[1].1));
+        assert_eq!(
+            Some(&scored[0].0),
+            crate::ai::best_placement(&srs, &params, &s).as_ref()
+        );
+    }
+
+    #[test]
+    fn test_state_distinct_outcomes() {
+        let (xx, __) = (true, false);
+        let s: State = Snapshot {
+            hold: None,
+            queue: "OZ".chars().map(Color::n).collect(),
+            matrix: basic_matrix![
+                [__, xx, xx, xx, xx],
+                [__, __, xx, xx, xx],
+                [__, __, xx, xx, xx],
+            ],
+        }
+        .into();
+        let srs = srs();
+        let mut pfind = PlaceFinder::new(&srs, MovementModel::Full);
+        let outcomes = s.distinct_outcomes(&mut pfind);
+        assert_eq!(
+            outcomes.iter().map(|(_, pls)| pls.len()).sum::<usize>(),
+            s.placements(&mut pfind).count()
+        );
+        for (i, (mat, _)) in outcomes.iter().enumerate() {
+            assert!(outcomes[..i].iter().all(|(other, _)| other != mat));
+        }
+        // O on top of the bottom row clears the two rows above it. a vertical Z fills the
+        // bottom row and one of the rows above, leaving the same cell empty
+        let (_, grouped) = outcomes
+            .iter()
+            .find(|(mat, _)| *mat == basic_matrix![[__, xx, xx, xx, xx]])
+            .unwrap();
+        assert_eq!(grouped.len(), 2);
+        let find = |c| grouped.iter().find(|pl| pl.color == Color::n(c)).unwrap();
+        let (o, z) = (find('O'), find('Z'));
+        assert_eq!((o.color, o.did_hold), (Color::n('O'), false));
+        assert_eq!((z.color, z.did_hold), (Color::n('Z'), true));
+        assert_ne!(o.col, z.col);
+    }
+
+    #[test]
+    fn test_state_json_round_trip() {
+        let (xx, __) = (true, false);
+        let mut s: State = Snapshot {
+            hold: Some(Color::n('S')),
+            queue: "LTJI".chars().map(Color::n).collect(),
+            matrix: basic_matrix![[xx, xx, __, xx], [__, xx, __, __]],
+        }
+        .into();
+        s.pop(true);
+        let json = s.to_json();
+        assert_eq!(State::from_json(&json).unwrap(), s);
+        assert!(State::from_json("{}").is_err());
+    }
+
+    #[test]
+    fn test_state_mirror() {
+        let (xx, __) = (true, false);
+        let s: State = Snapshot {
+            hold: Some(Color::n('S')),
+            queue: "LTZI".chars().map(Color::n).collect(),
+            matrix: basic_matrix![[xx, xx, __, xx], [__, xx, __, __]],
+        }
+        .into();
+        let m = s.mirror();
+        assert_eq!(m.mirror(), s);
+        assert_eq!(m.next(), (Some(Color::n('J')), Some(Color::n('Z'))));
+        assert_eq!(m.matrix, basic_matrix![[xx, __, xx, xx], [__, __, xx, __]]);
+        assert_ne!(m, s);
+        assert_eq!(m.canonical_key(), s.canonical_key());
+
+        let mut t = s.clone();
+        t.pop(false);
+        assert_ne!(t.canonical_key(), s.canonical_key());
     }
 
     #[test]
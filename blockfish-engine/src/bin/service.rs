@@ -162,16 +162,24 @@ impl Service {
         msg: protos::Request_Analyze,
     ) -> Result<impl (FnOnce() -> protos::Response) + Send> {
         let id = msg.id;
-        let ss = from_snapshot_proto(msg.get_snapshot());
         let count = self.max_results;
         let len = self.max_placements;
-        let mut handle = self.ai.analyze(ss);
+        // reject snapshots the engine can't represent by finishing without any suggestions
+        let handle = match from_snapshot_proto(msg.get_snapshot()) {
+            Ok(ss) => Some(self.ai.analyze(ss)),
+            Err(err) => {
+                log::error!("rejecting analysis {}: {}", id, err);
+                None
+            }
+        };
         Ok(move || {
-            handle.wait();
             let mut res = protos::Response::new();
             let mut finished = res.mut_finished();
             finished.id = id;
-            to_analysis_proto(handle, count, len, &mut finished);
+            if let Some(mut handle) = handle {
+                handle.wait();
+                to_analysis_proto(handle, count, len, &mut finished);
+            }
             res
         })
     }
@@ -187,19 +195,23 @@ fn set_if_nonzero(y: &mut usize, x: usize) {
     }
 }
 
-fn from_snapshot_proto(ss: &protos::Snapshot) -> blockfish::ai::Snapshot {
-    blockfish::ai::Snapshot {
+fn from_snapshot_proto(
+    ss: &protos::Snapshot,
+) -> std::result::Result<blockfish::ai::Snapshot, blockfish::RowLimitError> {
+    Ok(blockfish::ai::Snapshot {
         hold: ss.hold.chars().next().and_then(color),
         queue: ss.queue.chars().filter_map(color).collect(),
-        matrix: matrix(ss.rows.iter().map(|s| s.as_str())),
-    }
+        matrix: matrix(ss.rows.iter().map(|s| s.as_str()))?,
+    })
 }
 
 fn color(ch: char) -> Option<blockfish::Color> {
     blockfish::Color::try_from(ch).ok()
 }
 
-fn matrix<'a>(row_strs: impl Iterator<Item = &'a str>) -> blockfish::BasicMatrix {
+fn matrix<'a>(
+    row_strs: impl Iterator<Item = &'a str>,
+) -> std::result::Result<blockfish::BasicMatrix, blockfish::RowLimitError> {
     let mut mat = None;
     for (i, row_str) in row_strs.enumerate() {
         let mat = mat.get_or_insert_with(|| {
@@ -208,11 +220,12 @@ fn matrix<'a>(row_strs: impl Iterator<Item = &'a str>) -> blockfish::BasicMatrix
         });
         for (j, ch) in row_str.chars().enumerate() {
             if blockfish::Color::try_from(ch).is_ok() {
-                mat.set((i as u16, j as u16));
+                let i = u16::try_from(i).unwrap_or(u16::MAX);
+                mat.try_set((i, j as u16))?;
             }
         }
     }
-    mat.unwrap_or_else(|| blockfish::BasicMatrix::with_cols(10))
+    Ok(mat.unwrap_or_else(|| blockfish::BasicMatrix::with_cols(10)))
 }
 
 fn to_analysis_proto(
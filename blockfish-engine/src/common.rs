@@ -21,6 +21,20 @@ impl Color {
     pub fn as_char(&self) -> char {
         self.0.get() as char
     }
+
+    /// Returns the color of the shape that is the horizontal mirror image of this one,
+    /// i.e. swaps S with Z and L with J. Other colors are unchanged.
+    pub fn mirror(&self) -> Self {
+        match self.as_char() {
+            'S' => 'Z',
+            'Z' => 'S',
+            'L' => 'J',
+            'J' => 'L',
+            _ => return *self,
+        }
+        .try_into()
+        .unwrap()
+    }
 }
 
 impl TryFrom<char> for Color {
@@ -145,6 +159,12 @@ mod test {
         assert_eq!(Color::try_from('\0').ok(), None);
     }
 
+    #[test]
+    fn test_mirror_color() {
+        let mirror = |c| Color::n(c).mirror().as_char();
+        assert_eq!("SZLJTIO".chars().map(mirror).collect::<String>(), "ZSJLTIO");
+    }
+
     #[test]
     fn test_sizeof() {
         use std::mem::size_of;
@@ -1,11 +1,63 @@
-use std::convert::{TryFrom, TryInto};
+use crate::{ai::Placement, Color, Input};
+use std::{
+    collections::HashMap,
+    convert::{TryFrom, TryInto},
+    sync::Arc,
+};
 use thiserror::Error;
 
 /// AI configuration.
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Config {
+    /// Number of nodes the search generates before the analysis finishes. Also bounds the
+    /// number of heuristic values the search keeps cached.
     pub search_limit: usize,
     pub parameters: Parameters,
+    pub movement: MovementModel,
+    pub placement_order: PlacementOrder,
+    pub finesse: FinesseConfig,
+    pub kick_system: KickSystem,
+    pub future: FutureModel,
+    /// Which pieces may come after the known queue, when the future model looks beyond
+    /// it. The known queue (not including the hold piece) is assumed to start at the
+    /// beginning of a bag.
+    pub bag: BagModel,
+    /// What to do once every piece in the known queue has been placed.
+    pub on_queue_empty: QueueEmptyPolicy,
+    /// If `Some`, pieces may only be soft dropped this many rows after being moved or
+    /// rotated, which excludes placements requiring deeper tucks.
+    pub max_soft_drop: Option<u16>,
+    /// If `true`, only placements that clear at least one line are considered, e.g. for
+    /// combo play. If there are no such placements, the analysis finds no moves.
+    pub require_clear: bool,
+    /// If `Some`, the search discards nodes whose `f` value exceeds the best at the same
+    /// depth by more than this margin. Speeds up the search, but may miss good moves.
+    pub prune_margin: Option<i64>,
+    /// If `Some`, sequences are cut off at this many placements, and the final node is
+    /// rated by the heuristic as if the queue ended there.
+    pub max_depth: Option<usize>,
+    /// If `true`, the search is suspended rather than discarded once the analysis
+    /// finishes, so that it can be resumed later. See `Analysis::take_frontier()`.
+    pub keep_frontier: bool,
+    /// If `Some`, the first placement of every sequence must be this placement, e.g. to
+    /// explore the best continuations after a particular opening move. Only the color,
+    /// orientation, row and column are taken into account.
+    pub forced_first: Option<Placement>,
+    /// If `true`, the best move after each iteration is recorded in the analysis
+    /// statistics. Useful for debugging how quickly the search converges.
+    pub record_best_per_iteration: bool,
+    /// If `true`, move ids are derived from the first placement of each move rather than
+    /// the order the placements were generated in, so that the same move gets the same
+    /// id every time the same root is analyzed, even with a different placement order.
+    /// See `MoveId` for details.
+    pub stable_move_ids: bool,
+    /// Minimum amount a move's rating must improve by before the change is reported to
+    /// the analysis handle, unless the move becomes the new best move. Changes held back
+    /// this way are reported once the analysis finishes.
+    pub min_rating_delta: i64,
+    /// If `Some`, the analysis sends its diagnostic messages to this hook instead of the
+    /// global logger, so that the trace of a particular analysis can be captured.
+    pub trace_hook: Option<TraceHook>,
 }
 
 impl Default for Config {
@@ -13,17 +65,325 @@ impl Default for Config {
         Self {
             search_limit: 50_000,
             parameters: Parameters::default(),
+            movement: MovementModel::default(),
+            placement_order: PlacementOrder::default(),
+            finesse: FinesseConfig::default(),
+            kick_system: KickSystem::default(),
+            future: FutureModel::default(),
+            bag: BagModel::default(),
+            on_queue_empty: QueueEmptyPolicy::default(),
+            max_soft_drop: None,
+            require_clear: false,
+            prune_margin: None,
+            max_depth: None,
+            keep_frontier: false,
+            forced_first: None,
+            record_best_per_iteration: false,
+            stable_move_ids: false,
+            min_rating_delta: 0,
+            trace_hook: None,
+        }
+    }
+}
+
+/// Determines which kinds of movement the AI is allowed to use to place pieces.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Default)]
+pub enum MovementModel {
+    /// Any movement, including soft drops and tucks/spins.
+    #[default]
+    Full,
+    /// Only shifting and rotating at the top of the matrix followed by hard drop.
+    HardDropOnly,
+}
+
+/// Determines the order in which the placements for a state are generated. This affects
+/// which move is preferred when two moves are rated equally.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Default)]
+pub enum PlacementOrder {
+    /// The order in which placements are discovered by the placement finder's depth-first
+    /// search. Deterministic, but depends on the details of the search.
+    #[default]
+    Discovery,
+    /// Placements not requiring hold first, then by orientation, then by column, then by
+    /// row.
+    Sorted,
+}
+
+/// Determines how rotations that collide with the matrix are handled.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Default)]
+pub enum KickSystem {
+    /// Try each of the offsets in the shape table's kick table, as in SRS.
+    #[default]
+    Srs,
+    /// Rotations that collide are not allowed, as in classic games without kicks.
+    None,
+}
+
+/// Determines how the pieces after the end of the known queue are accounted for.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Default)]
+pub enum FutureModel {
+    /// Only the known queue is searched, and the final matrix is rated by the heuristic.
+    #[default]
+    Known,
+    /// Assume the worst possible piece comes next, for this many pieces after the known
+    /// queue. The final matrix is rated by the best placements of the worst pieces, i.e.
+    /// the maximum over the possible next pieces of the minimum over their placements.
+    /// This avoids relying on a particular piece, but is much slower.
+    Adversarial(u8),
+}
+
+/// Determines which pieces are dealt, used to narrow down the pieces that may come after
+/// the known queue.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Default)]
+pub enum BagModel {
+    /// Pieces are dealt in bags containing one of each piece (the 7-bag randomizer).
+    SevenBag,
+    /// Any piece may come next.
+    #[default]
+    Random,
+    /// Pieces are dealt in bags with the given contents.
+    Custom(Vec<Color>),
+}
+
+impl BagModel {
+    /// Returns the pieces that may come right after the pieces `dealt`, in sorted order,
+    /// where `colors` is the set of every piece. `dealt` should start at the beginning of
+    /// a bag. If `dealt` doesn't fit into consecutive bags, any piece in the bag may come
+    /// next.
+    pub fn next_pieces(&self, colors: &[Color], dealt: &[Color]) -> Vec<Color> {
+        let mut next = match self {
+            BagModel::SevenBag => next_in_bag(colors, dealt),
+            BagModel::Random => colors.to_vec(),
+            BagModel::Custom(bag) => next_in_bag(bag, dealt),
+        };
+        next.sort();
+        next.dedup();
+        next
+    }
+
+    /// Returns the pieces left in the bag after the pieces `dealt`, in sorted order, or a
+    /// whole bag if `dealt` ends at the end of a bag. Under `BagModel::Random` nothing is
+    /// known about the next pieces, so this is empty. See `next_pieces` for the meaning of
+    /// `colors` and `dealt`.
+    pub fn rest_of_bag(&self, colors: &[Color], dealt: &[Color]) -> Vec<Color> {
+        let bag_len = match self {
+            BagModel::SevenBag => colors.len(),
+            BagModel::Random => 0,
+            BagModel::Custom(bag) => bag.len(),
+        };
+        if bag_len == 0 {
+            return vec![];
+        }
+        let mut dealt = dealt.to_vec();
+        let start = dealt.len();
+        for _ in 0..bag_len - start % bag_len {
+            match self.next_pieces(colors, &dealt).first() {
+                Some(&color) => dealt.push(color),
+                None => break,
+            }
+        }
+        dealt.split_off(start)
+    }
+}
+
+/// Determines what the search does once it has placed every piece in the known queue.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Default)]
+pub enum QueueEmptyPolicy {
+    /// Sequences end with the last piece of the queue.
+    #[default]
+    Stop,
+    /// Once the known queue is used up, it is extended with the rest of the bag it ends
+    /// in under `Config::bag` (see `BagModel::rest_of_bag`), and these speculative pieces
+    /// are searched as if they were part of the queue. Since their actual order is
+    /// unknown, they are dealt in sorted order. Suggestions only include placements of
+    /// the known pieces. Under `BagModel::Random` there is nothing to speculate on, so this
+    /// is the same as `Stop`.
+    ContinueWithBag,
+}
+
+/// Returns the pieces that may come after `dealt` if pieces are dealt in copies of `bag`,
+/// see `BagModel::next_pieces`. The result may contain duplicates.
+fn next_in_bag(bag: &[Color], dealt: &[Color]) -> Vec<Color> {
+    if bag.is_empty() {
+        return vec![];
+    }
+    let consistent = dealt
+        .chunks(bag.len())
+        .all(|chunk| take_from_bag(bag, chunk).is_some());
+    let current = &dealt[dealt.len() - dealt.len() % bag.len()..];
+    match take_from_bag(bag, current) {
+        Some(left) if consistent => left,
+        _ => bag.to_vec(),
+    }
+}
+
+/// Returns the pieces left in `bag` after taking out `pieces`, or `None` if `pieces` are
+/// not all contained in the bag.
+fn take_from_bag(bag: &[Color], pieces: &[Color]) -> Option<Vec<Color>> {
+    let mut left = bag.to_vec();
+    for piece in pieces {
+        let i = left.iter().position(|c| c == piece)?;
+        left.remove(i);
+    }
+    Some(left)
+}
+
+/// Callback that receives diagnostic messages from an analysis, see `Config::trace_hook`.
+/// Hooks are compared by identity, so two configs are only equal if they share the same
+/// hook.
+#[derive(Clone)]
+pub struct TraceHook(Arc<dyn Fn(&str) + Send + Sync>);
+
+impl TraceHook {
+    pub fn new(hook: impl Fn(&str) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(hook))
+    }
+
+    /// Sends `msg` to the hook.
+    pub fn call(&self, msg: &str) {
+        (self.0)(msg)
+    }
+
+    fn addr(&self) -> *const () {
+        Arc::as_ptr(&self.0) as *const ()
+    }
+}
+
+impl std::fmt::Debug for TraceHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "TraceHook({:p})", self.addr())
+    }
+}
+
+impl PartialEq for TraceHook {
+    fn eq(&self, other: &Self) -> bool {
+        self.addr() == other.addr()
+    }
+}
+
+impl Eq for TraceHook {}
+
+impl std::hash::Hash for TraceHook {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.addr().hash(state)
+    }
+}
+
+/// Costs of each kind of input, used when finding the cheapest input sequence for a
+/// placement. By default every input costs the same, so the fewest total keys are used.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct FinesseConfig {
+    pub sd_cost: u32,
+    pub move_cost: u32,
+    pub rotate_cost: u32,
+}
+
+impl FinesseConfig {
+    /// Returns the cost of pressing `input`.
+    pub fn cost(&self, input: Input) -> u32 {
+        match input {
+            Input::Left | Input::Right => self.move_cost,
+            Input::CW | Input::CCW => self.rotate_cost,
+            Input::SD => self.sd_cost,
+            Input::Hold | Input::HD => 0,
+        }
+    }
+}
+
+impl Default for FinesseConfig {
+    fn default() -> Self {
+        Self {
+            sd_cost: 1,
+            move_cost: 1,
+            rotate_cost: 1,
         }
     }
 }
 
 /// Evaluation scoring parameters.
-#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Parameters {
     pub row_factor: i64,
     pub piece_estimate_factor: i64,
     pub i_dependency_factor: i64,
     pub piece_penalty: i64,
+    /// If `true`, the piece estimate is capped by the number of pieces remaining in the
+    /// queue.
+    pub queue_aware: bool,
+    /// Reward for clearing four lines by placing an I piece into a well in the leftmost
+    /// or rightmost column.
+    pub reward_i_in_well: i64,
+    /// Penalty for placing a piece other than I into the well (see `well_column`), below
+    /// the top of the columns next to it.
+    pub well_block_penalty: i64,
+    /// Column of the well that `well_block_penalty` keeps clear for I pieces. If `None`,
+    /// both the leftmost and the rightmost column count as wells.
+    pub well_column: Option<u16>,
+    /// Bonus for boards with a notch that the held piece can be dropped into without
+    /// leaving holes, so that other pieces don't fill the spot that the held piece fits.
+    pub hold_fit_bonus: i64,
+    /// Bonus for boards with a slot that an S, Z, L, J or T piece can only be spun into,
+    /// since it can't move left, right or up once there.
+    pub spin_setup_bonus: i64,
+    /// Which side of the matrix to prefer when scores are otherwise tied.
+    pub placement_bias: PlacementBias,
+    /// Penalty per unit of variance in the column heights. Penalizes a few very tall
+    /// columns even if the surface is otherwise smooth.
+    pub height_variance_factor: i64,
+    /// Penalty for boards that can no longer be perfect cleared because of the number of
+    /// filled cells. Only useful for bots going for perfect clears.
+    pub pc_parity_penalty: i64,
+    /// Penalty per unit of the (weighted) sum of the column heights.
+    pub aggregate_height_factor: i64,
+    /// Penalty per unit of the (weighted) sum of the height differences between adjacent
+    /// columns.
+    pub bumpiness_factor: i64,
+    /// Penalty per unit of difference between the sum of the column heights on the left
+    /// half of the matrix and on the right half. Discourages piling up on one side.
+    pub lopsidedness_factor: i64,
+    /// If set, adds a large extra penalty per row of the stack on top of `row_factor`
+    /// (see `KEEP_LOW_ROW_FACTOR`), so that keeping the stack low takes priority over
+    /// keeping the surface smooth. A preset for endurance modes.
+    pub keep_low: bool,
+    /// How the evaluation is turned into a score. If not `ScoreMode::Weighted`, the
+    /// factors above are ignored.
+    pub score_mode: ScoreMode,
+    /// Bonus subtracted from the rating of sequences that leave the matrix empty (a
+    /// perfect clear). Large values prefer a perfect clear over a shorter sequence that
+    /// leaves some cells behind.
+    pub perfect_clear_bonus: i64,
+    /// If `Some`, the height of each column is multiplied by the corresponding weight in
+    /// the aggregate height and bumpiness terms, e.g. to ignore the well column. There
+    /// should be one weight per column (see `check_column_weights`, and
+    /// `AI::try_analyze`); columns without a weight have weight `1`.
+    pub column_weights: Option<Vec<i64>>,
+    /// Extra path cost for placing each kind of piece, on top of `piece_penalty`, e.g. to
+    /// discourage pieces that are harder to place well. Pieces without an entry have no
+    /// extra cost.
+    pub piece_costs: HashMap<Color, i64>,
+}
+
+/// Tie-breaking preference for where the occupied cells of the matrix should be.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Default)]
+pub enum PlacementBias {
+    /// No preference.
+    #[default]
+    None,
+    /// Prefer cells toward the center columns.
+    Center,
+    /// Prefer cells toward the leftmost and rightmost columns.
+    Edges,
+}
+
+/// Determines how the terms of a matrix's evaluation are combined into its score.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Default)]
+pub enum ScoreMode {
+    /// Sum of the terms, each multiplied by its factor in `Parameters`.
+    #[default]
+    Weighted,
+    /// Fewest holes first, then the flattest surface, then the lowest stack; each term
+    /// only matters if the previous ones are tied. See `LexScore`.
+    Lexicographic,
 }
 
 impl Default for Parameters {
@@ -33,10 +393,82 @@ impl Default for Parameters {
             piece_estimate_factor: 10,
             i_dependency_factor: 10,
             piece_penalty: 10,
+            queue_aware: false,
+            reward_i_in_well: 0,
+            well_block_penalty: 0,
+            well_column: None,
+            hold_fit_bonus: 0,
+            spin_setup_bonus: 0,
+            placement_bias: PlacementBias::default(),
+            height_variance_factor: 0,
+            pc_parity_penalty: 0,
+            aggregate_height_factor: 0,
+            bumpiness_factor: 0,
+            lopsidedness_factor: 0,
+            keep_low: false,
+            score_mode: ScoreMode::default(),
+            perfect_clear_bonus: 0,
+            column_weights: None,
+            piece_costs: HashMap::new(),
         }
     }
 }
 
+impl std::hash::Hash for Parameters {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        let Self {
+            row_factor,
+            piece_estimate_factor,
+            i_dependency_factor,
+            piece_penalty,
+            queue_aware,
+            reward_i_in_well,
+            well_block_penalty,
+            well_column,
+            hold_fit_bonus,
+            spin_setup_bonus,
+            placement_bias,
+            height_variance_factor,
+            pc_parity_penalty,
+            aggregate_height_factor,
+            bumpiness_factor,
+            lopsidedness_factor,
+            keep_low,
+            score_mode,
+            perfect_clear_bonus,
+            column_weights,
+            piece_costs,
+        } = self;
+        (
+            row_factor,
+            piece_estimate_factor,
+            i_dependency_factor,
+            piece_penalty,
+        )
+            .hash(state);
+        (
+            queue_aware,
+            reward_i_in_well,
+            well_block_penalty,
+            well_column,
+        )
+            .hash(state);
+        (hold_fit_bonus, spin_setup_bonus, placement_bias).hash(state);
+        (
+            height_variance_factor,
+            pc_parity_penalty,
+            aggregate_height_factor,
+        )
+            .hash(state);
+        (bumpiness_factor, lopsidedness_factor, keep_low, score_mode).hash(state);
+        (perfect_clear_bonus, column_weights).hash(state);
+        // hash maps have no order, so the entries are hashed sorted by piece
+        let mut costs = piece_costs.iter().collect::<Vec<_>>();
+        costs.sort_unstable();
+        costs.hash(state);
+    }
+}
+
 // Parsing / printing
 
 #[derive(Debug, Error)]
@@ -70,6 +502,7 @@ impl std::str::FromStr for Config {
             Ok(Config {
                 search_limit,
                 parameters,
+                ..Config::default()
             })
         }
     }
@@ -93,6 +526,13 @@ impl std::fmt::Display for Config {
 #[error("expected exactly 4 values")]
 pub struct ParseParametersError;
 
+#[derive(Debug, Error)]
+#[error("expected {expected} column weights, got {got}")]
+pub struct ColumnWeightsError {
+    pub expected: u16,
+    pub got: usize,
+}
+
 impl<'a> TryFrom<&'a [i64]> for Parameters {
     type Error = ParseParametersError;
     fn try_from(vs: &'a [i64]) -> Result<Self, ParseParametersError> {
@@ -102,6 +542,7 @@ impl<'a> TryFrom<&'a [i64]> for Parameters {
                 piece_estimate_factor: *v2,
                 i_dependency_factor: *v3,
                 piece_penalty: *v4,
+                ..Parameters::default()
             }),
             _ => Err(ParseParametersError),
         }
@@ -109,6 +550,18 @@ impl<'a> TryFrom<&'a [i64]> for Parameters {
 }
 
 impl Parameters {
+    /// Checks that `column_weights`, if set, has exactly one weight for each of `cols`
+    /// columns.
+    pub fn check_column_weights(&self, cols: u16) -> Result<(), ColumnWeightsError> {
+        match &self.column_weights {
+            Some(weights) if weights.len() != cols as usize => Err(ColumnWeightsError {
+                expected: cols,
+                got: weights.len(),
+            }),
+            _ => Ok(()),
+        }
+    }
+
     fn to_array(&self) -> [i64; 4] {
         [
             self.row_factor,
@@ -125,13 +578,47 @@ impl Parameters {
 mod test {
     use super::*;
 
+    #[test]
+    fn test_bag_model_next_pieces() {
+        let colors = |s: &str| s.chars().map(Color::n).collect::<Vec<_>>();
+        let all = colors("IJLOSTZ");
+        let next = |bag: BagModel, dealt| bag.next_pieces(&all, &colors(dealt));
+        assert_eq!(next(BagModel::SevenBag, ""), colors("IJLOSTZ"));
+        assert_eq!(next(BagModel::SevenBag, "TIZ"), colors("JLOS"));
+        assert_eq!(next(BagModel::SevenBag, "TIZJLS"), colors("O"));
+        // after a full bag, a new one begins
+        assert_eq!(next(BagModel::SevenBag, "TIZJLSO"), colors("IJLOSTZ"));
+        assert_eq!(next(BagModel::SevenBag, "TIZJLSOIT"), colors("JLOSZ"));
+        // not dealt from a bag
+        assert_eq!(next(BagModel::SevenBag, "TT"), colors("IJLOSTZ"));
+        assert_eq!(next(BagModel::Random, "TIZJLS"), colors("IJLOSTZ"));
+        let custom = || BagModel::Custom(colors("TTI"));
+        assert_eq!(next(custom(), "T"), colors("IT"));
+        assert_eq!(next(custom(), "TT"), colors("I"));
+        assert_eq!(next(custom(), "TTI"), colors("IT"));
+    }
+
+    #[test]
+    fn test_bag_model_rest_of_bag() {
+        let colors = |s: &str| s.chars().map(Color::n).collect::<Vec<_>>();
+        let all = colors("IJLOSTZ");
+        let rest = |bag: BagModel, dealt| bag.rest_of_bag(&all, &colors(dealt));
+        assert_eq!(rest(BagModel::SevenBag, "TIZ"), colors("JLOS"));
+        assert_eq!(rest(BagModel::SevenBag, "TIZJLSO"), colors("IJLOSTZ"));
+        assert_eq!(rest(BagModel::Random, "TIZ"), colors(""));
+        assert_eq!(rest(BagModel::Custom(colors("TTI")), ""), colors("ITT"));
+        assert_eq!(rest(BagModel::Custom(colors("TTI")), "T"), colors("IT"));
+        assert_eq!(rest(BagModel::Custom(vec![]), "T"), colors(""));
+    }
+
     #[test]
     fn test_parse_config() {
         assert_eq!(
             "15".parse::<Config>().unwrap(),
             Config {
                 search_limit: 15_000,
-                parameters: Parameters::default()
+                parameters: Parameters::default(),
+                ..Config::default()
             }
         );
         assert_eq!(
@@ -143,7 +630,9 @@ mod test {
                     piece_estimate_factor: 2,
                     i_dependency_factor: 3,
                     piece_penalty: 4,
+                    ..Parameters::default()
                 },
+                ..Config::default()
             }
         );
     }
@@ -160,13 +649,25 @@ mod test {
                         piece_estimate_factor: 2,
                         i_dependency_factor: 3,
                         piece_penalty: 4,
+                        ..Parameters::default()
                     },
+                    ..Config::default()
                 }
             ),
             "15/1,2,3,4"
         );
     }
 
+    #[test]
+    fn test_check_column_weights() {
+        let mut params = Parameters::default();
+        assert!(params.check_column_weights(10).is_ok());
+        params.column_weights = Some(vec![1; 10]);
+        assert!(params.check_column_weights(10).is_ok());
+        let err = params.check_column_weights(8).unwrap_err();
+        assert_eq!((err.expected, err.got), (8, 10));
+    }
+
     #[test]
     fn test_parse_params() {
         let params = Parameters {
@@ -174,6 +675,7 @@ mod test {
             piece_estimate_factor: 2,
             i_dependency_factor: 3,
             piece_penalty: 4,
+            ..Parameters::default()
         };
         let values = params.to_array();
         assert_eq!(Parameters::try_from(&values[..]).unwrap(), params);
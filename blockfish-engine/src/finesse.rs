@@ -3,6 +3,7 @@ use thiserror::Error;
 
 use crate::{
     common::{Input, Orientation},
+    config::{FinesseConfig, KickSystem, MovementModel},
     matrix::BasicMatrix,
     shape::{NormalizedShapeTransform, ShapeRef, Transform},
 };
@@ -18,21 +19,43 @@ pub struct FinesseNotFoundError;
 /// Current implementation uses Djikstra's algorithm to ensure a shortest path is found.
 /// It's perhaps not as efficient as A* but it does the job simply.
 pub struct FinesseFinder {
+    movement: MovementModel,
+    costs: FinesseConfig,
+    kick_system: KickSystem,
     frontier: BinaryHeap<FinesseNode>,
     explored: HashSet<Transform>,
-    back_links: HashMap<Transform, (Transform, Input)>,
+    // back link to the previous transform and input, along with the cost of the path
+    back_links: HashMap<Transform, (Transform, Input, u32)>,
 }
 
 impl FinesseFinder {
-    /// Constructs a new `FinesseFinder`.
-    pub fn new() -> Self {
+    /// Constructs a new `FinesseFinder`, which only finds input sequences allowed under
+    /// movement model `movement`.
+    pub fn new(movement: MovementModel) -> Self {
         Self {
+            movement,
+            costs: FinesseConfig::default(),
+            kick_system: KickSystem::default(),
             frontier: BinaryHeap::new(),
             explored: HashSet::new(),
             back_links: HashMap::new(),
         }
     }
 
+    /// Configures the cost of each input, which determines the path that is chosen when
+    /// several input sequences reach the same placement.
+    pub fn with_costs(self, costs: FinesseConfig) -> Self {
+        Self { costs, ..self }
+    }
+
+    /// Configures how rotations are kicked.
+    pub fn with_kick_system(self, kick_system: KickSystem) -> Self {
+        Self {
+            kick_system,
+            ..self
+        }
+    }
+
     /// Runs the finesse finder algorithm to find a path for `shape` to get from its spawn
     /// location to `target`.
     pub fn find(
@@ -66,7 +89,7 @@ impl FinesseFinder {
         shape: ShapeRef,
         target: NormalizedShapeTransform,
     ) -> Result<Option<Transform>, FinesseNotFoundError> {
-        // pop next node in priority queue with least KPP
+        // pop next node in priority queue with least cost
         let node = self.frontier.pop().ok_or(FinesseNotFoundError)?;
         let tf0 = node.tf;
         self.explored.insert(tf0);
@@ -78,24 +101,34 @@ impl FinesseFinder {
             return Ok(Some(tf0));
         }
 
-        // try moving in every direction (including sonic-drop)
-        let neighbors = [Input::CW, Input::CCW, Input::Left, Input::Right, Input::SD]
-            .iter()
-            .filter_map(|&input| {
-                match input {
-                    Input::SD => Some(sd_tf),
-                    _ => shape.try_input(matrix, tf0, input),
-                }
-                .map(|tf| (tf, input))
-            });
+        // try moving in every direction (including sonic-drop, if allowed)
+        let inputs: &[Input] = match self.movement {
+            MovementModel::Full => &[Input::CW, Input::CCW, Input::Left, Input::Right, Input::SD],
+            MovementModel::HardDropOnly => &[Input::CW, Input::CCW, Input::Left, Input::Right],
+        };
+        let kick_system = self.kick_system;
+        let neighbors = inputs.iter().filter_map(|&input| {
+            match input {
+                Input::SD => Some(sd_tf),
+                _ => shape.try_input(matrix, tf0, input, kick_system),
+            }
+            .map(|tf| (tf, input))
+        });
 
         for (tf, input) in neighbors {
             // push if placement is newly discovered
             if self.explored.contains(&tf) {
                 continue;
             }
-            self.frontier.push(node.succ(tf, input));
-            self.back_links.insert(tf, (tf0, input));
+            // don't replace a cheaper path that was already found, since inputs may have
+            // different costs
+            let succ = node.succ(tf, input, &self.costs);
+            match self.back_links.get(&tf) {
+                Some(&(_, _, cost)) if cost < succ.cost => continue,
+                _ => {}
+            }
+            self.back_links.insert(tf, (tf0, input, succ.cost));
+            self.frontier.push(succ);
         }
 
         Ok(None)
@@ -105,7 +138,7 @@ impl FinesseFinder {
     /// links created during Djikstra's algorithm.
     fn reconstruct_inputs(&self, mut tf: Transform) -> Vec<Input> {
         let mut inputs = vec![];
-        while let Some(&(prev_tf, input)) = self.back_links.get(&tf) {
+        while let Some(&(prev_tf, input, _)) = self.back_links.get(&tf) {
             tf = prev_tf;
             inputs.push(input);
         }
@@ -115,23 +148,23 @@ impl FinesseFinder {
 }
 
 /// Wrapper around Transforms for use in Djikstra's algorithm. `FinesseNode`s are
-/// `Ord`ered by most KPP, so that the binary (max-)heap in `FinesseFinder` orders them by
-/// least-KPP first.
+/// `Ord`ered by most cost, so that the binary (max-)heap in `FinesseFinder` orders them by
+/// least-cost first.
 #[derive(Debug)]
 struct FinesseNode {
     tf: Transform,
-    kpp: usize,
+    cost: u32,
 }
 
 impl FinesseNode {
     fn new(tf: Transform) -> Self {
-        Self { tf, kpp: 0 }
+        Self { tf, cost: 0 }
     }
 
-    fn succ(&self, new_tf: Transform, _: Input) -> Self {
+    fn succ(&self, new_tf: Transform, input: Input, costs: &FinesseConfig) -> Self {
         Self {
             tf: new_tf,
-            kpp: self.kpp + 1,
+            cost: self.cost + costs.cost(input),
         }
     }
 }
@@ -140,7 +173,7 @@ impl Eq for FinesseNode {}
 
 impl PartialEq for FinesseNode {
     fn eq(&self, rhs: &Self) -> bool {
-        rhs.kpp == self.kpp
+        rhs.cost == self.cost
     }
 }
 
@@ -152,7 +185,7 @@ impl PartialOrd for FinesseNode {
 
 impl Ord for FinesseNode {
     fn cmp(&self, rhs: &Self) -> std::cmp::Ordering {
-        rhs.kpp.cmp(&self.kpp)
+        rhs.cost.cmp(&self.cost)
     }
 }
 
@@ -167,10 +200,11 @@ mod test {
     #[test]
     fn test_ord_kpp() {
         let tf = (0, 0, R0);
+        let c = FinesseConfig::default();
         let n0 = FinesseNode::new(tf);
-        let n1 = n0.succ(tf, CW); // kpp = 1
-        let n2 = n0.succ(tf, Left).succ(tf, Right); // kpp = 2
-        let n2_alt = n0.succ(tf, Left).succ(tf, CW); // kpp = 2
+        let n1 = n0.succ(tf, CW, &c); // kpp = 1
+        let n2 = n0.succ(tf, Left, &c).succ(tf, Right, &c); // kpp = 2
+        let n2_alt = n0.succ(tf, Left, &c).succ(tf, CW, &c); // kpp = 2
         assert!(n1 > n2); // order reversed to create a min-heap in FinesseFinder
         assert!(n0 > n1);
         assert!(n0 == n0);
@@ -183,7 +217,7 @@ mod test {
         let color = Color::n(color);
         let matrix = BasicMatrix::with_cols(10);
         let shape = srs.shape(color).unwrap();
-        let mut ffind = FinesseFinder::new();
+        let mut ffind = FinesseFinder::new(MovementModel::Full);
         shape
             .valid_cols(r, matrix.cols())
             .map(|j| {
@@ -255,4 +289,77 @@ mod test {
         assert_eq!(all_finesse('S', -1, R0), all_finesse('S', 0, R2));
         assert_eq!(all_finesse('S', 0, R1), all_finesse('S', 0, R3));
     }
+
+    #[test]
+    fn test_sd_cost() {
+        use crate::basic_matrix;
+        let (xx, __) = (true, false);
+        let srs = srs();
+        let shape = srs.shape(Color::n('J')).unwrap();
+        let matrix = basic_matrix![
+            [xx, xx, xx, xx, __, __, __, __, __, __],
+            [xx, xx, __, __, __, __, __, __, __, __],
+            [xx, xx, xx, xx, xx, __, __, __, __, __],
+        ];
+        let tgt = shape.normalize((3, 2, R1));
+        let cheap_sd = FinesseConfig {
+            sd_cost: 0,
+            ..FinesseConfig::default()
+        };
+        let costly_sd = FinesseConfig {
+            sd_cost: 10,
+            ..FinesseConfig::default()
+        };
+        let mut ffind = FinesseFinder::new(MovementModel::Full).with_costs(cheap_sd);
+        assert_eq!(ffind.find(&matrix, shape, tgt).unwrap(), vec![SD, CW]);
+        let mut ffind = FinesseFinder::new(MovementModel::Full).with_costs(costly_sd);
+        assert_eq!(ffind.find(&matrix, shape, tgt).unwrap(), vec![Left, CW]);
+    }
+
+    #[test]
+    fn test_tsd_finesse() {
+        use crate::{basic_matrix, place::PlaceFinder};
+        let (xx, __) = (true, false);
+        let srs = srs();
+        let shape = srs.shape(Color::n('T')).unwrap();
+        // the T can only get into the slot by kicking off the surrounding stack
+        let matrix = basic_matrix![
+            [xx, xx, xx, __, xx, xx, xx, xx, xx, xx],
+            [xx, xx, __, __, __, xx, xx, xx, xx, xx],
+            [xx, __, __, __, xx, __, __, __, __, __],
+            [xx, xx, __, __, __, __, __, __, __, __],
+            [xx, __, __, xx, __, __, __, __, __, __],
+        ];
+        let mut pfind = PlaceFinder::new(&srs, MovementModel::Full);
+        pfind.reset_matrix(&matrix);
+        pfind.push_shape(Color::n('T'), false);
+        let slot = [(0, 3), (1, 2), (1, 3), (1, 4)];
+        let tsd = pfind
+            .find(|pl| {
+                let mut cells = pl.shape.cells(pl.tf).collect::<Vec<_>>();
+                cells.sort();
+                cells == slot
+            })
+            .expect("no placement in the slot");
+
+        let mut ffind = FinesseFinder::new(MovementModel::Full);
+        let inputs = ffind.find(&matrix, shape, tsd.normal()).unwrap();
+        assert!([CW, CCW].contains(inputs.last().unwrap()), "{:?}", inputs);
+        // replay the inputs from the spawn location, followed by a hard drop
+        let mut tf = (matrix.rows() as i16, shape.spawn_col(), R0);
+        for &input in inputs.iter() {
+            tf = match input {
+                SD => shape.sonic_drop(&matrix, tf),
+                _ => shape
+                    .try_input(&matrix, tf, input, KickSystem::Srs)
+                    .expect("input blocked"),
+            };
+        }
+        let tf = shape.sonic_drop(&matrix, tf);
+        assert_eq!(shape.normalize(tf), tsd.normal());
+
+        // unreachable without kicks
+        let mut ffind = FinesseFinder::new(MovementModel::Full).with_kick_system(KickSystem::None);
+        assert!(ffind.find(&matrix, shape, tsd.normal()).is_err());
+    }
 }
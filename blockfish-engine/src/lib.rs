@@ -8,8 +8,11 @@ mod shape;
 pub mod ai;
 
 pub use common::{Color, Input, Orientation};
-pub use config::{Config, Parameters};
-pub use matrix::BasicMatrix;
+pub use config::{
+    BagModel, ColumnWeightsError, Config, FinesseConfig, FutureModel, KickSystem, MovementModel,
+    Parameters, PlacementBias, PlacementOrder, QueueEmptyPolicy, ScoreMode, TraceHook,
+};
+pub use matrix::{BasicMatrix, ParseGridError, RowLimitError, MAX_ROWS};
 
 #[cfg(feature = "block-stacker")]
 mod stacker_util;
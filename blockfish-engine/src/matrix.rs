@@ -5,13 +5,20 @@ use std::{
 };
 use thiserror::Error;
 
+/// Maximum number of rows supported by `BasicMatrix`. Pieces are positioned using signed
+/// 16-bit coordinates above the top of the stack, so much taller matrices can't be
+/// represented reliably. The checked constructors refuse matrices taller than this, and
+/// the unchecked methods that add rows panic rather than grow past it.
+pub const MAX_ROWS: u16 = 1024;
+
 #[derive(Clone, Eq, PartialEq, Hash)]
 pub struct BasicMatrix {
     /// Number of columns.
     cols: u16,
     /// Row data. Each row is represented by a bit mask of the occupied cells. All high
     /// bits after the first `cols` bits are set as well. This way e.g. a completely full
-    /// row is represented by `std::u16::MAX`.
+    /// row is represented by `u16::MAX`. Rows therefore have at most 16 columns, in which
+    /// case an empty row is `0`.
     data: Vec<u16>,
 }
 
@@ -38,7 +45,8 @@ macro_rules! basic_matrix {
 
 #[inline(always)]
 fn empty_row_bits(cols: u16) -> u16 {
-    std::u16::MAX.overflowing_shl(cols.into()).0
+    // with all 16 columns in use there are no high bits to set
+    u16::MAX.checked_shl(cols.into()).unwrap_or(0)
 }
 
 #[inline(always)]
@@ -52,6 +60,15 @@ impl BasicMatrix {
         BasicMatrix { cols, data: vec![] }
     }
 
+    /// Constructs an empty matrix with the given number of columns, preallocating space
+    /// for `rows` rows, but no more than `MAX_ROWS`. Otherwise identical to `with_cols`.
+    pub fn with_capacity(cols: u16, rows: u16) -> Self {
+        BasicMatrix {
+            cols,
+            data: Vec::with_capacity(std::cmp::min(rows, MAX_ROWS) as usize),
+        }
+    }
+
     /// Returns the number of columns.
     #[inline(always)]
     pub fn cols(&self) -> u16 {
@@ -65,10 +82,23 @@ impl BasicMatrix {
     }
 
     /// Ensure that row `i` is present by appending empty rows to the top of the matrix.
+    ///
+    /// Panics if `i` is not below `MAX_ROWS`; see `try_ensure_row`.
     fn ensure_row(&mut self, i: u16) {
+        self.try_ensure_row(i)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Like `ensure_row`, but returns an error instead of growing the matrix past
+    /// `MAX_ROWS`.
+    fn try_ensure_row(&mut self, i: u16) -> Result<(), RowLimitError> {
+        if i >= MAX_ROWS {
+            return Err(RowLimitError(i));
+        }
         let min_len = (i as usize) + 1;
         let len = std::cmp::max(self.data.len(), min_len);
         self.data.resize(len, empty_row_bits(self.cols));
+        Ok(())
     }
 
     /// Returns true if the coordinate `coord` is occupied. Out of bounds coordinates are
@@ -78,30 +108,56 @@ impl BasicMatrix {
     pub fn get(&self, (i, j): (u16, u16)) -> bool {
         self.data
             .get(i as usize)
-            .map(|row_bits| row_bits & (1 << j) != 0)
+            .map(|row_bits| j >= self.cols || row_bits & (1 << j) != 0)
             .unwrap_or_else(|| j >= self.cols)
     }
 
-    /// Set the given coordinate to become occupied. If the coordinate is out of bounds,
-    /// does nothing.
+    /// Set the given coordinate to become occupied. If the column is out of bounds, does
+    /// nothing.
+    ///
+    /// Panics if the row is not below `MAX_ROWS`; see `try_set`.
     pub fn set(&mut self, (i, j): (u16, u16)) {
+        self.try_set((i, j)).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Like `set`, but returns an error instead of growing the matrix past `MAX_ROWS`.
+    pub fn try_set(&mut self, (i, j): (u16, u16)) -> Result<(), RowLimitError> {
         if j < self.cols {
-            self.ensure_row(i);
+            self.try_ensure_row(i)?;
             self.data[i as usize] |= 1 << j;
         }
+        Ok(())
     }
 
     /// Blit matrix `mat` onto this matrix with origin `(i0, j0)`, setting all corresponing
     /// occupied cells.
+    ///
+    /// Panics if this would grow the matrix past `MAX_ROWS`; see `try_blit`.
     pub fn blit(&mut self, mat: &BasicMatrix, (i0, j0): (u16, u16)) {
+        self.try_blit(mat, (i0, j0))
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Like `blit`, but returns an error, leaving this matrix unchanged, instead of growing
+    /// the matrix past `MAX_ROWS`.
+    pub fn try_blit(
+        &mut self,
+        mat: &BasicMatrix,
+        (i0, j0): (u16, u16),
+    ) -> Result<(), RowLimitError> {
         if mat.rows() == 0 {
-            return;
+            return Ok(());
+        }
+        let top = i0 as u32 + mat.rows() as u32 - 1;
+        if top >= MAX_ROWS as u32 {
+            return Err(RowLimitError(top.min(u16::MAX as u32) as u16));
         }
         let mask = (1 << mat.cols()) - 1;
         self.ensure_row(i0 + mat.rows() - 1);
         for i in 0..mat.rows() {
             self.data[(i0 + i) as usize] |= (mat.data[i as usize] & mask) << j0;
         }
+        Ok(())
     }
 
     /// Returns true if `mat` overlaps with this matrix, when offset by `(i0, j0)`.
@@ -152,6 +208,47 @@ impl BasicMatrix {
             .unwrap_or(0)
     }
 
+    /// Returns the row that a flat-bottomed piece `width` columns wide, with its leftmost
+    /// column at `col`, comes to rest on when hard dropped. Holes under an overhang don't
+    /// count, since the piece stops on top of the overhang. Columns past the right edge of
+    /// the matrix are ignored.
+    pub fn landing_height(&self, col: u16, width: u16) -> u16 {
+        let end = std::cmp::min(col.saturating_add(width), self.cols);
+        (col..end).map(|j| self.col_height(j)).max().unwrap_or(0)
+    }
+
+    /// Returns the difference in height between each pair of adjacent columns, i.e. entry
+    /// `j` is the height of column `j + 1` minus the height of column `j`. The result
+    /// describes the shape of the surface regardless of its absolute height.
+    pub fn surface_profile(&self) -> Vec<i16> {
+        let heights = (0..self.cols())
+            .map(|j| self.col_height(j) as i16)
+            .collect::<Vec<_>>();
+        heights.windows(2).map(|w| w[1] - w[0]).collect()
+    }
+
+    /// Returns the column heights as `(height, run_length)` pairs, from left to right,
+    /// where each run is a maximal range of adjacent columns with equal height. This is a
+    /// compact fingerprint of the surface, e.g. for use as a lookup key.
+    pub fn contour_rle(&self) -> Vec<(u16, u16)> {
+        let mut runs: Vec<(u16, u16)> = vec![];
+        for j in 0..self.cols() {
+            let height = self.col_height(j);
+            match runs.last_mut() {
+                Some((h, len)) if *h == height => *len += 1,
+                _ => runs.push((height, 1)),
+            }
+        }
+        runs
+    }
+
+    /// Returns the number of occupied cells in row `i`.
+    pub fn row_cells(&self, i: u16) -> u16 {
+        // the high bits past the last column are set, so mask them out
+        let mask = (1u32 << self.cols) - 1;
+        (self.data[i as usize] as u32 & mask).count_ones() as u16
+    }
+
     /// Returns the extents of every gap in row `i`.
     pub fn gaps(&self, i: u16) -> impl Iterator<Item = Range<u16>> {
         let cols = self.cols();
@@ -159,7 +256,7 @@ impl BasicMatrix {
         // iterator *inclusive* so we hit the rightmost wall
         (0..=cols)
             .scan(None, move |gap, j| {
-                if row_bits & (1 << j) != 0 {
+                if j >= cols || row_bits & (1 << j) != 0 {
                     Some(gap.take())
                 } else {
                     gap.get_or_insert(j..j).end += 1;
@@ -170,22 +267,154 @@ impl BasicMatrix {
     }
 
     /// Appends a row to the top of the matrix,
+    ///
+    /// Panics if the matrix already has `MAX_ROWS` rows; see `try_push_row`.
     // NOTE: currently, only used by `basic_matrix!` macro
     // TODO: can we remove this entirely?
     #[cfg(test)]
     pub fn push_row(&mut self, cells: impl IntoIterator<Item = bool>) {
+        self.try_push_row(cells)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Like `push_row`, but returns an error, leaving this matrix unchanged, instead of
+    /// growing the matrix past `MAX_ROWS`.
+    pub fn try_push_row(
+        &mut self,
+        cells: impl IntoIterator<Item = bool>,
+    ) -> Result<(), RowLimitError> {
         let i = self.rows();
-        self.ensure_row(i);
+        self.try_ensure_row(i)?;
         for (j, cell) in cells.into_iter().enumerate().take(self.cols as usize) {
             if cell {
-                self.set((i, j as u16));
+                self.data[i as usize] |= 1 << j;
             }
         }
+        Ok(())
     }
 
     /// Inserts an empty row to the bottom of the matrix.
+    ///
+    /// Panics if the matrix already has `MAX_ROWS` rows; see
+    /// `try_insert_empty_bottom_row`.
     pub fn insert_empty_bottom_row(&mut self) {
+        self.try_insert_empty_bottom_row()
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Like `insert_empty_bottom_row`, but returns an error, leaving this matrix
+    /// unchanged, instead of growing the matrix past `MAX_ROWS`.
+    pub fn try_insert_empty_bottom_row(&mut self) -> Result<(), RowLimitError> {
+        if self.rows() >= MAX_ROWS {
+            return Err(RowLimitError(self.rows()));
+        }
         self.data.insert(0, empty_row_bits(self.cols));
+        Ok(())
+    }
+
+    /// Parses a matrix from the text grid format produced by `Display`: one row per line,
+    /// from the top row down to the bottom row. `#` or `x` are occupied cells, `.` or
+    /// space are empty cells. The number of columns is determined by the first row. A
+    /// single line of `-` is a matrix with no rows, as wide as the line.
+    pub fn from_str_grid(s: &str) -> Result<Self, ParseGridError> {
+        let lines = s.lines().collect::<Vec<_>>();
+        let cols = lines.first().map_or(0, |l| l.chars().count());
+        if cols > 16 {
+            return Err(ParseGridError::TooWide(cols));
+        }
+        if let [line] = lines[..] {
+            if cols > 0 && line.chars().all(|ch| ch == '-') {
+                return Ok(Self::with_cols(cols as u16));
+            }
+        }
+        if lines.len() > MAX_ROWS as usize {
+            return Err(ParseGridError::TooTall(lines.len()));
+        }
+        let mut bm = Self::with_cols(cols as u16);
+        for (i, line) in lines.iter().rev().enumerate() {
+            let i = i as u16;
+            bm.ensure_row(i);
+            let mut len = 0;
+            for (j, ch) in line.chars().enumerate() {
+                match ch {
+                    '#' | 'x' if j < cols => bm.set((i, j as u16)),
+                    '.' | ' ' | '#' | 'x' => {}
+                    _ => return Err(ParseGridError::InvalidCell(ch)),
+                }
+                len += 1;
+            }
+            if len != cols {
+                return Err(ParseGridError::RaggedRow {
+                    row: lines.len() - 1 - i as usize,
+                    len,
+                    expected: cols,
+                });
+            }
+        }
+        Ok(bm)
+    }
+
+    /// Returns a copy of this matrix flipped horizontally.
+    pub fn mirrored(&self) -> Self {
+        let empty_bits = empty_row_bits(self.cols);
+        let shift = 16 - self.cols as u32;
+        let data = self
+            .data
+            .iter()
+            .map(|&row_bits| ((row_bits & !empty_bits).reverse_bits() >> shift) | empty_bits)
+            .collect();
+        BasicMatrix {
+            cols: self.cols,
+            data,
+        }
+    }
+
+    /// Returns the smallest `(rows, cols)` ranges containing every occupied cell, or
+    /// `None` if no cells are occupied.
+    pub fn bounds(&self) -> Option<(Range<u16>, Range<u16>)> {
+        let mut bounds: Option<(Range<u16>, Range<u16>)> = None;
+        for i in 0..self.rows() {
+            for j in (0..self.cols).filter(|&j| self.get((i, j))) {
+                bounds = Some(match bounds {
+                    None => (i..i + 1, j..j + 1),
+                    Some((rows, cols)) => (
+                        rows.start..i + 1,
+                        std::cmp::min(cols.start, j)..std::cmp::max(cols.end, j + 1),
+                    ),
+                });
+            }
+        }
+        bounds
+    }
+
+    /// Divides the occupied rows into `rows_div` bands and the columns into `cols_div`
+    /// bands, and returns the fraction of occupied cells in each of the resulting regions,
+    /// starting from the bottom left region and going row by row. Regions that end up
+    /// without any cells (e.g. when there are fewer rows than bands) count as empty.
+    pub fn region_fills(&self, rows_div: u16, cols_div: u16) -> Vec<f32> {
+        let band = |n: u16, div: u16, k: u16| {
+            let edge = |k: u16| (n as u32 * k as u32 / div as u32) as u16;
+            edge(k)..edge(k + 1)
+        };
+        let mut fills = Vec::with_capacity(rows_div as usize * cols_div as usize);
+        for ri in 0..rows_div {
+            let rows = band(self.rows(), rows_div, ri);
+            for ci in 0..cols_div {
+                let cols = band(self.cols, cols_div, ci);
+                let area = rows.len() * cols.len();
+                let filled = rows
+                    .clone()
+                    .flat_map(|i| cols.clone().map(move |j| (i, j)))
+                    .filter(|&ij| self.get(ij))
+                    .count();
+                fills.push(if area == 0 {
+                    0.0
+                } else {
+                    filled as f32 / area as f32
+                });
+            }
+        }
+        fills
     }
 }
 
@@ -207,6 +436,56 @@ impl std::fmt::Debug for BasicMatrix {
     }
 }
 
+/// Renders the matrix one row per line, from the top row down to the bottom row, using
+/// `#` for occupied cells and `.` for empty cells. A matrix with no rows is rendered as a
+/// single line of `-`, one per column, so that its width isn't lost.
+impl std::fmt::Display for BasicMatrix {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.rows() == 0 {
+            for _ in 0..self.cols() {
+                f.write_str("-")?;
+            }
+            return Ok(());
+        }
+        for i in (0..self.rows()).rev() {
+            for j in 0..self.cols() {
+                f.write_str(if self.get((i, j)) { "#" } else { "." })?;
+            }
+            if i > 0 {
+                f.write_str("\n")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for BasicMatrix {
+    type Err = ParseGridError;
+    fn from_str(s: &str) -> Result<Self, ParseGridError> {
+        Self::from_str_grid(s)
+    }
+}
+
+#[derive(Debug, Error, Eq, PartialEq)]
+pub enum ParseGridError {
+    #[error("invalid cell character {0:?}")]
+    InvalidCell(char),
+    #[error("line {row} has {len} cells, expected {expected}")]
+    RaggedRow {
+        row: usize,
+        len: usize,
+        expected: usize,
+    },
+    #[error("too many columns ({0}), at most 16 are supported")]
+    TooWide(usize),
+    #[error("too many rows ({0}), at most {} are supported", MAX_ROWS)]
+    TooTall(usize),
+}
+
+#[derive(Debug, Error, Eq, PartialEq)]
+#[error("row {0} is out of range, at most {} rows are supported", MAX_ROWS)]
+pub struct RowLimitError(pub u16);
+
 #[derive(Serialize, Deserialize)]
 struct MatrixWireFormat {
     cols: u16,
@@ -214,8 +493,12 @@ struct MatrixWireFormat {
 }
 
 #[derive(Debug, Error)]
-#[error("encountered invald byte in matrix specification")]
-struct MatrixFormatError;
+enum MatrixFormatError {
+    #[error("encountered invald byte in matrix specification")]
+    InvalidByte,
+    #[error(transparent)]
+    TooTall(#[from] RowLimitError),
+}
 
 impl<'a> From<&'a BasicMatrix> for MatrixWireFormat {
     fn from(bm: &'a BasicMatrix) -> Self {
@@ -240,7 +523,7 @@ impl TryFrom<MatrixWireFormat> for BasicMatrix {
         for ch in m.data.chars() {
             match ch {
                 'x' => {
-                    bm.set((i, j));
+                    bm.try_set((i, j))?;
                     j += 1;
                 }
                 '.' => {
@@ -250,7 +533,7 @@ impl TryFrom<MatrixWireFormat> for BasicMatrix {
                     j = 0;
                     i += 1;
                 }
-                _ => return Err(MatrixFormatError),
+                _ => return Err(MatrixFormatError::InvalidByte),
             }
         }
         Ok(bm)
@@ -422,6 +705,12 @@ mod test {
         assert_eq!(gaps(2), [2..4]);
         assert_eq!(gaps(3), [2..4, 6..7]);
         assert_eq!(gaps(4), [0..4, 6..8]);
+        let row_cells = (0..mat.rows())
+            .map(|i| mat.row_cells(i))
+            .collect::<Vec<_>>();
+        assert_eq!(row_cells, [0, 8, 6, 5, 2]);
+        let full = basic_matrix![[xx; 16]];
+        assert_eq!(full.row_cells(0), 16);
     }
 
     #[test]
@@ -501,4 +790,245 @@ mod test {
             ]
         );
     }
+
+    #[test]
+    fn test_display() {
+        let (xx, __) = (true, false);
+        let mat = basic_matrix![
+            [xx, xx, xx, __, xx],
+            [xx, __, __, __, xx],
+            [__, __, xx, __, __],
+        ];
+        assert_eq!(mat.to_string(), "..#..\n#...#\n###.#");
+        assert_eq!(BasicMatrix::with_cols(5).to_string(), "-----");
+        assert_eq!(BasicMatrix::with_cols(0).to_string(), "");
+    }
+
+    #[test]
+    fn test_from_str_grid() {
+        let (xx, __) = (true, false);
+        let mat = basic_matrix![
+            [xx, xx, xx, __, xx],
+            [xx, __, __, __, xx],
+            [__, __, xx, __, __],
+        ];
+        assert_eq!(
+            BasicMatrix::from_str_grid("..x..\n#   #\n###.#\n"),
+            Ok(mat.clone())
+        );
+        assert_eq!(mat.to_string().parse::<BasicMatrix>(), Ok(mat.clone()));
+        assert_eq!(
+            BasicMatrix::from_str_grid(&mat.to_string())
+                .unwrap()
+                .to_string(),
+            mat.to_string()
+        );
+        for cols in [0, 1, 5, 10] {
+            let empty = BasicMatrix::with_cols(cols);
+            assert_eq!(empty.to_string().parse::<BasicMatrix>(), Ok(empty));
+        }
+        let mut one_row = BasicMatrix::with_cols(5);
+        one_row.ensure_row(0);
+        assert_eq!(one_row.to_string().parse::<BasicMatrix>(), Ok(one_row));
+    }
+
+    #[test]
+    fn test_from_str_grid_16_cols() {
+        let grid = "................\n#.#.#.#.#.#.#.#.\n###############.";
+        let mut mat = BasicMatrix::from_str_grid(grid).unwrap();
+        assert_eq!((mat.rows(), mat.cols()), (3, 16));
+        assert_eq!(mat.to_string(), grid);
+        assert!(!mat.get((2, 0)));
+        assert!(!mat.get((0, 15)));
+        assert!(mat.get((0, 16)));
+        assert_eq!(mat.gaps(0).collect::<Vec<_>>(), [15..16]);
+        assert_eq!(mat.gaps(2).collect::<Vec<_>>(), [0..16]);
+        // only the empty top row is removed
+        assert!(!mat.sift_rows());
+        assert_eq!(mat.to_string(), "#.#.#.#.#.#.#.#.\n###############.");
+        mat.set((0, 15));
+        assert!(mat.sift_rows());
+        assert_eq!(mat.to_string(), "#.#.#.#.#.#.#.#.");
+    }
+
+    #[test]
+    fn test_from_str_grid_errors() {
+        assert_eq!(
+            BasicMatrix::from_str_grid("..#..\n#..#\n###.#"),
+            Err(ParseGridError::RaggedRow {
+                row: 1,
+                len: 4,
+                expected: 5,
+            })
+        );
+        assert_eq!(
+            BasicMatrix::from_str_grid("..#..\n#...##"),
+            Err(ParseGridError::RaggedRow {
+                row: 1,
+                len: 6,
+                expected: 5,
+            })
+        );
+        assert_eq!(
+            BasicMatrix::from_str_grid("..#..\n#.o.#"),
+            Err(ParseGridError::InvalidCell('o'))
+        );
+        assert_eq!(
+            BasicMatrix::from_str_grid(&".".repeat(17)),
+            Err(ParseGridError::TooWide(17))
+        );
+    }
+
+    #[test]
+    fn test_max_rows() {
+        let mut mat = BasicMatrix::with_cols(4);
+        assert_eq!(mat.try_set((MAX_ROWS - 1, 0)), Ok(()));
+        assert_eq!(mat.rows(), MAX_ROWS);
+        assert_eq!(mat.try_set((MAX_ROWS, 0)), Err(RowLimitError(MAX_ROWS)));
+        assert_eq!(mat.try_push_row([true; 4]), Err(RowLimitError(MAX_ROWS)));
+        assert_eq!(
+            mat.try_insert_empty_bottom_row(),
+            Err(RowLimitError(MAX_ROWS))
+        );
+        assert_eq!(mat.rows(), MAX_ROWS);
+        assert!(mat.get((MAX_ROWS - 1, 0)));
+
+        // placing a piece that would stick out past the limit leaves the matrix alone
+        let piece = BasicMatrix::from_str_grid("x\nx").unwrap();
+        let mut stack = BasicMatrix::with_capacity(4, u16::MAX);
+        assert_eq!(stack.try_blit(&piece, (MAX_ROWS - 2, 0)), Ok(()));
+        assert_eq!(stack.rows(), MAX_ROWS);
+        let mut stack = BasicMatrix::with_cols(4);
+        assert_eq!(
+            stack.try_blit(&piece, (MAX_ROWS - 1, 0)),
+            Err(RowLimitError(MAX_ROWS))
+        );
+        assert_eq!(stack.rows(), 0);
+
+        let tall = "x...\n".repeat(MAX_ROWS as usize + 1);
+        assert_eq!(
+            BasicMatrix::from_str_grid(&tall),
+            Err(ParseGridError::TooTall(MAX_ROWS as usize + 1))
+        );
+        assert_eq!(
+            BasicMatrix::from_str_grid(&tall[5..]).map(|m| m.rows()),
+            Ok(MAX_ROWS)
+        );
+
+        let data = vec!["x..."; MAX_ROWS as usize + 1].join(" ");
+        let json = format!("{{\"cols\":4,\"data\":\"{}\"}}", data);
+        assert!(serde_json::from_str::<BasicMatrix>(&json).is_err());
+        let json = format!("{{\"cols\":4,\"data\":\"{}\"}}", &data[5..]);
+        assert_eq!(
+            serde_json::from_str::<BasicMatrix>(&json)
+                .map(|m| m.rows())
+                .ok(),
+            Some(MAX_ROWS)
+        );
+    }
+
+    #[test]
+    fn test_mirrored() {
+        let (xx, __) = (true, false);
+        let mat = basic_matrix![[xx, xx, xx, __], [xx, __, __, __]];
+        assert_eq!(
+            mat.mirrored(),
+            basic_matrix![[__, xx, xx, xx], [__, __, __, xx]]
+        );
+        assert_eq!(mat.mirrored().mirrored(), mat);
+    }
+
+    #[test]
+    fn test_surface_profile() {
+        let (xx, __) = (true, false);
+        let flat = basic_matrix![[xx, __, xx, __], [xx, xx, xx, xx]];
+        assert_eq!(flat.surface_profile(), [0, 0, 0]);
+        assert_eq!(BasicMatrix::with_cols(4).surface_profile(), [0, 0, 0]);
+        let step = basic_matrix![[xx, xx, xx, xx], [__, __, xx, xx]];
+        assert_eq!(step.surface_profile(), [0, 1, 0]);
+        assert_eq!(step.mirrored().surface_profile(), [0, -1, 0]);
+        assert!(BasicMatrix::with_cols(0).surface_profile().is_empty());
+    }
+
+    #[test]
+    fn test_contour_rle() {
+        let (xx, __) = (true, false);
+        let plateaus = basic_matrix![
+            [xx, xx, xx, xx, xx, xx],
+            [xx, xx, xx, xx, __, __],
+            [xx, xx, xx, xx, __, __],
+        ];
+        assert_eq!(plateaus.contour_rle(), [(3, 4), (1, 2)]);
+        assert_eq!(BasicMatrix::with_cols(4).contour_rle(), [(0, 4)]);
+        // holes don't affect the contour
+        let holes = basic_matrix![[xx, __, xx], [xx, xx, xx]];
+        assert_eq!(holes.contour_rle(), [(2, 3)]);
+        assert!(BasicMatrix::with_cols(0).contour_rle().is_empty());
+    }
+
+    #[test]
+    fn test_landing_height() {
+        let (xx, __) = (true, false);
+        let clean = basic_matrix![[xx, xx, __, xx], [xx, __, __, __]];
+        assert_eq!(clean.landing_height(2, 1), 0);
+        assert_eq!(clean.landing_height(1, 2), 1);
+        assert_eq!(clean.landing_height(0, 4), 2);
+        // the piece can't get under the overhang in column 1
+        let overhang = basic_matrix![[xx, __, __, xx], [xx, __, __, xx], [__, xx, __, __]];
+        assert_eq!(overhang.landing_height(1, 2), 3);
+        assert_eq!(overhang.landing_height(2, 1), 0);
+        assert_eq!(overhang.landing_height(2, 5), 2);
+        assert_eq!(BasicMatrix::with_cols(4).landing_height(0, 4), 0);
+    }
+
+    #[test]
+    fn test_bounds() {
+        let (xx, __) = (true, false);
+        assert_eq!(BasicMatrix::with_cols(10).bounds(), None);
+        assert_eq!(basic_matrix![[__, __, __]].bounds(), None);
+        let single = basic_matrix![[__, __, __, __], [__, __, xx, __]];
+        assert_eq!(single.bounds(), Some((1..2, 2..3)));
+        let corners = basic_matrix![[__, __, __, xx], [__, __, __, __], [xx, __, __, __],];
+        assert_eq!(corners.bounds(), Some((0..3, 0..4)));
+        let corners = basic_matrix![
+            [__, __, __, __],
+            [__, xx, __, __],
+            [__, __, __, __],
+            [__, __, xx, __],
+        ];
+        assert_eq!(corners.bounds(), Some((1..4, 1..3)));
+    }
+
+    #[test]
+    fn test_region_fills() {
+        let (xx, __) = (true, false);
+        let left = basic_matrix![
+            [xx, xx, __, __],
+            [xx, xx, __, __],
+            [xx, xx, __, __],
+            [xx, xx, __, __],
+        ];
+        assert_eq!(left.region_fills(2, 2), [1.0, 0.0, 1.0, 0.0]);
+        assert_eq!(left.region_fills(1, 1), [0.5]);
+        assert_eq!(left.region_fills(1, 3), [1.0, 1.0, 0.0]);
+        let bottom = basic_matrix![[xx, xx, xx, xx], [xx, __, xx, __]];
+        assert_eq!(bottom.region_fills(2, 1), [1.0, 0.5]);
+        assert_eq!(bottom.region_fills(3, 2), [0.0, 0.0, 1.0, 1.0, 0.5, 0.5]);
+        assert_eq!(BasicMatrix::with_cols(4).region_fills(2, 2), [0.0; 4]);
+        assert!(left.region_fills(0, 2).is_empty());
+    }
+
+    #[test]
+    fn test_with_capacity() {
+        let mut m1 = BasicMatrix::with_cols(4);
+        let mut m2 = BasicMatrix::with_capacity(4, 20);
+        assert_eq!(m1, m2);
+        for m in [&mut m1, &mut m2].iter_mut() {
+            m.set((2, 1));
+            m.insert_empty_bottom_row();
+            m.set((0, 3));
+        }
+        assert_eq!(m1, m2);
+        assert_eq!(m2.rows(), 4);
+    }
 }
@@ -1,8 +1,8 @@
 use crate::{
     shape::{NormalizedShapeTransform, ShapeRef, ShapeTable, Transform},
-    BasicMatrix, Color, Input, Orientation,
+    BasicMatrix, Color, Input, KickSystem, MovementModel, Orientation, PlacementOrder, MAX_ROWS,
 };
-use std::collections::HashSet;
+use std::{cmp::Reverse, collections::HashSet};
 
 /// Represents a piece placement, with data about the shape as well as the input sequence
 /// to get it into place.
@@ -38,12 +38,25 @@ impl<'s> Place<'s> {
     }
 
     /// Simulates the input `inp` on this placement. If the input succeeds without being
-    /// blocked by matrix `mat`, then returns `Some(updated_place)`. If the input is
-    /// invalid, returns `None`.
-    fn input(&self, matrix: &BasicMatrix, input: Input) -> Option<Self> {
-        let tf = self.shape.try_input(matrix, self.tf, input)?;
-        let tf = self.shape.sonic_drop(matrix, tf);
-        Some(Place { tf, ..self.clone() })
+    /// blocked by matrix `mat`, then returns `Some((updated_place, rows))`, where `rows`
+    /// is the number of rows the piece fell after the input. If the input is invalid,
+    /// returns `None`.
+    fn input(
+        &self,
+        matrix: &BasicMatrix,
+        input: Input,
+        kick_system: KickSystem,
+    ) -> Option<(Self, u16)> {
+        let tf = self.shape.try_input(matrix, self.tf, input, kick_system)?;
+        let drop_tf = self.shape.sonic_drop(matrix, tf);
+        let rows = (tf.0 - drop_tf.0) as u16;
+        Some((
+            Place {
+                tf: drop_tf,
+                ..self.clone()
+            },
+            rows,
+        ))
     }
 }
 
@@ -53,9 +66,19 @@ impl<'s> Place<'s> {
 /// algorithm multiple times.
 pub struct PlaceFinder<'s> {
     shtb: &'s ShapeTable,
+    movement: MovementModel,
+    order: PlacementOrder,
+    kick_system: KickSystem,
+    max_soft_drop: Option<u16>,
+    require_clear: bool,
     matrix: BasicMatrix,
-    // next placements to try (depth-first search)
-    queue: Vec<Place<'s>>,
+    // next placements to try (depth-first search), along with the number of rows each
+    // one fell after its last input
+    queue: Vec<(Place<'s>, u16)>,
+    // discovered placements waiting to be returned in sorted order, in reverse
+    sorted: Vec<Place<'s>>,
+    // number of placements returned so far in sorted order
+    sorted_count: usize,
     // prevent search cycles
     places_seen: HashSet<(Color, Transform)>,
     // prevent returning identical (normalized) shapes
@@ -63,26 +86,67 @@ pub struct PlaceFinder<'s> {
 }
 
 impl<'s> PlaceFinder<'s> {
-    /// Returns a new placements iterator using the given shape table.
+    /// Returns a new placements iterator using the given shape table, which only produces
+    /// placements reachable under movement model `movement`.
     ///
     /// Initially this will produce no placements; it needs to be configured with an
     /// initial state via `reset_matrix` and `push_color` first.
-    pub fn new(shtb: &'s ShapeTable) -> Self {
+    pub fn new(shtb: &'s ShapeTable, movement: MovementModel) -> Self {
         PlaceFinder {
             shtb,
+            movement,
+            order: PlacementOrder::Discovery,
+            kick_system: KickSystem::default(),
+            max_soft_drop: None,
+            require_clear: false,
             matrix: BasicMatrix::with_cols(0),
             queue: Vec::with_capacity(64),
+            sorted: Vec::with_capacity(64),
+            sorted_count: 0,
             places_seen: HashSet::with_capacity(64),
             normals_seen: HashSet::with_capacity(32),
         }
     }
 
+    /// Configures the order in which this iterator produces placements.
+    pub fn with_order(self, order: PlacementOrder) -> Self {
+        Self { order, ..self }
+    }
+
+    /// Configures how rotations are kicked. This affects which placements are reachable.
+    pub fn with_kick_system(self, kick_system: KickSystem) -> Self {
+        Self {
+            kick_system,
+            ..self
+        }
+    }
+
+    /// Configures the number of rows a piece may be soft dropped after being moved or
+    /// rotated, before it can be moved or rotated again. If `None`, soft drops are
+    /// unlimited. Dropping onto the stack from above doesn't count towards the limit.
+    pub fn with_max_soft_drop(self, max_soft_drop: Option<u16>) -> Self {
+        Self {
+            max_soft_drop,
+            ..self
+        }
+    }
+
+    /// Configures this iterator to only produce placements that clear at least one line.
+    pub fn with_require_clear(self, require_clear: bool) -> Self {
+        Self {
+            require_clear,
+            ..self
+        }
+    }
+
     /// Resets this iterator, configuring it to search for placements on the matrix `mat`.
     pub fn reset_matrix(&mut self, mat: &BasicMatrix) {
         self.matrix.clone_from(mat);
         self.places_seen.clear();
         self.normals_seen.clear();
         self.queue.clear();
+        self.sorted.clear();
+        self.sorted_count = 0;
     }
 
     /// Configures this iterator to start producing placements for the shape described by
@@ -95,30 +159,75 @@ impl<'s> PlaceFinder<'s> {
                 return;
             }
         };
+        let spawn_reachable = match self.kick_system {
+            KickSystem::Srs => None,
+            KickSystem::None => Some(self.spawn_reachable(shape)),
+        };
         for r in Orientation::iter_all() {
             for j in shape.valid_cols(r, self.matrix.cols()) {
+                if spawn_reachable
+                    .as_ref()
+                    .map_or(false, |seen| !seen.contains(&(j, r)))
+                {
+                    continue;
+                }
                 let i = shape.peak(&self.matrix, j, r);
                 let pl = Place::new(shape, (i, j, r), hold);
-                self.queue.push(pl);
+                self.queue.push((pl, 0));
             }
         }
     }
 
+    /// Returns the (column, orientation) pairs that `shape` can be moved or rotated into
+    /// at its spawn row without colliding. Without kicks, placements seeded anywhere else
+    /// may be impossible to reach from spawn.
+    fn spawn_reachable(&self, shape: ShapeRef<'s>) -> HashSet<(i16, Orientation)> {
+        let i = self.matrix.rows() as i16;
+        let mut seen = HashSet::new();
+        let mut stack = vec![(shape.spawn_col(), Orientation::R0)];
+        while let Some((j, r)) = stack.pop() {
+            for &inp in [Input::Left, Input::Right, Input::CW, Input::CCW].iter() {
+                if let Some((_, j, r)) =
+                    shape.try_input(&self.matrix, (i, j, r), inp, KickSystem::None)
+                {
+                    if seen.insert((j, r)) {
+                        stack.push((j, r));
+                    }
+                }
+            }
+        }
+        if !shape.intersects(&self.matrix, (i, shape.spawn_col(), Orientation::R0)) {
+            seen.insert((shape.spawn_col(), Orientation::R0));
+        }
+        seen
+    }
+
+    /// Returns `true` if further inputs may be applied to a placement that fell `rows` rows
+    /// following its last input.
+    fn can_expand(&self, rows: u16) -> bool {
+        match self.movement {
+            // only the initial (hard dropped) placements are allowed
+            MovementModel::HardDropOnly => false,
+            MovementModel::Full => self.max_soft_drop.map_or(true, |max| rows <= max),
+        }
+    }
+
     fn expand(&mut self, pl: &Place<'s>) {
         let matrix = &self.matrix;
+        let kick_system = self.kick_system;
         self.queue.extend(
             [Input::Left, Input::Right, Input::CW, Input::CCW]
                 .iter()
-                .filter_map(|&inp| pl.input(matrix, inp)),
+                .filter_map(|&inp| pl.input(matrix, inp, kick_system)),
         );
     }
 
-    fn pop(&mut self) -> Option<Place<'s>> {
-        self.queue.pop().map(|mut pl| {
+    fn pop(&mut self) -> Option<(Place<'s>, u16)> {
+        self.queue.pop().map(|(mut pl, rows)| {
             // number of places in `normals_seen` == number of places returned so far
             // == index of the next (valid) place
             pl.idx = self.normals_seen.len();
-            pl
+            (pl, rows)
         })
     }
 
@@ -132,19 +241,66 @@ impl<'s> PlaceFinder<'s> {
     fn is_repeat(&mut self, pl: &Place) -> bool {
         !self.normals_seen.insert(pl.normal())
     }
+
+    /// Returns `true` if `pl` should not be yielded from the iterator, because it would
+    /// grow the matrix past `MAX_ROWS`, or it doesn't clear any lines despite
+    /// `require_clear` being set.
+    fn is_excluded(&self, pl: &Place) -> bool {
+        if pl.shape.cells(pl.tf).any(|(i, _)| i >= MAX_ROWS as i16) {
+            return true;
+        }
+        if !self.require_clear {
+            return false;
+        }
+        let cells = pl.shape.cells(pl.tf).collect::<Vec<_>>();
+        !cells.iter().any(|&(i, _)| {
+            (0..self.matrix.cols() as i16)
+                .all(|j| cells.contains(&(i, j)) || self.matrix.get((i as u16, j as u16)))
+        })
+    }
+
+    /// Returns the next placement in the order they are discovered.
+    fn next_discovered(&mut self) -> Option<Place<'s>> {
+        loop {
+            let (pl, rows) = self.pop()?;
+            if self.can_expand(rows) {
+                if self.is_cycle(&pl) {
+                    continue;
+                }
+                self.expand(&pl);
+            }
+            // placements that can't be expanded are not marked as visited, since the
+            // same transform may be reached again by a shorter drop. excluded placements
+            // are not marked as repeats, so that the indexes of yielded ones stay dense
+            if !self.is_excluded(&pl) && !self.is_repeat(&pl) {
+                return Some(pl);
+            }
+        }
+    }
+
+    /// Returns the next placement in sorted order. Every remaining placement has to be
+    /// discovered first in order to sort them.
+    fn next_sorted(&mut self) -> Option<Place<'s>> {
+        if !self.queue.is_empty() {
+            while let Some(pl) = self.next_discovered() {
+                self.sorted.push(pl);
+            }
+            self.sorted
+                .sort_by_key(|pl| Reverse((pl.did_hold, pl.tf.2, pl.tf.1, pl.tf.0)));
+        }
+        let mut pl = self.sorted.pop()?;
+        pl.idx = self.sorted_count;
+        self.sorted_count += 1;
+        Some(pl)
+    }
 }
 
 impl<'s> Iterator for PlaceFinder<'s> {
     type Item = Place<'s>;
     fn next(&mut self) -> Option<Place<'s>> {
-        loop {
-            let pl = self.pop()?;
-            if !self.is_cycle(&pl) {
-                self.expand(&pl);
-                if !self.is_repeat(&pl) {
-                    return Some(pl);
-                }
-            }
+        match self.order {
+            PlacementOrder::Discovery => self.next_discovered(),
+            PlacementOrder::Sorted => self.next_sorted(),
         }
     }
 }
@@ -156,7 +312,7 @@ mod test {
 
     /// Returns a `PlaceFinder` iterator already "primed" with node state `st`.
     fn placements<'s>(shtb: &'s ShapeTable, ss: Snapshot) -> PlaceFinder<'s> {
-        let mut pfind = PlaceFinder::new(shtb);
+        let mut pfind = PlaceFinder::new(shtb, MovementModel::Full);
         pfind.reset_matrix(&ss.matrix);
         if let Some(&c) = ss.queue.get(0) {
             pfind.push_shape(c, false);
@@ -179,6 +335,30 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_placements_sorted_order() {
+        let srs = srs();
+        let mut pfind =
+            PlaceFinder::new(&srs, MovementModel::Full).with_order(PlacementOrder::Sorted);
+        let mut expected = vec![];
+        for (r, cols) in [(R0, 0..=7), (R1, -1..=7), (R2, 0..=7), (R3, 0..=8)] {
+            expected.extend(cols.map(|j| (r, j)));
+        }
+        for _ in 0..2 {
+            pfind.reset_matrix(&BasicMatrix::with_cols(10));
+            pfind.push_shape(Color::n('T'), false);
+            let places = pfind
+                .by_ref()
+                .enumerate()
+                .map(|(idx, pl)| {
+                    assert_eq!(pl.idx, idx);
+                    (pl.tf.2, pl.tf.1)
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(places, expected);
+        }
+    }
+
     #[test]
     fn test_overlapping_placements() {
         let snapshot = Snapshot {
@@ -257,19 +437,20 @@ mod test {
         let t = srs.shape(Color::n('T')).unwrap();
         let pl = Place::new(t, (2, 3, R0), false);
 
+        let srs_kicks = KickSystem::Srs;
         // right movement fails
-        assert!(pl.input(&mat, Right).is_none());
+        assert!(pl.input(&mat, Right, srs_kicks).is_none());
         // left movement succeeds
-        let pl = pl.input(&mat, Left).unwrap();
-        assert_eq!(pl.tf, (0, 2, R0));
+        let (pl, rows) = pl.input(&mat, Left, srs_kicks).unwrap();
+        assert_eq!((pl.tf, rows), ((0, 2, R0), 2));
         // 2nd left movement succeeds
-        let pl = pl.input(&mat, Left).unwrap();
-        assert_eq!(pl.tf, (0, 1, R0));
+        let (pl, rows) = pl.input(&mat, Left, srs_kicks).unwrap();
+        assert_eq!((pl.tf, rows), ((0, 1, R0), 0));
         // 3rd left movement succeeds
-        let pl = pl.input(&mat, Left).unwrap();
-        assert_eq!(pl.tf, (-1, 0, R0));
+        let (pl, rows) = pl.input(&mat, Left, srs_kicks).unwrap();
+        assert_eq!((pl.tf, rows), ((-1, 0, R0), 1));
         // 4th left movement fails
-        assert!(pl.input(&mat, Left).is_none());
+        assert!(pl.input(&mat, Left, srs_kicks).is_none());
     }
 
     fn all_places(matrix: BasicMatrix, (color_char, r): (char, Orientation)) -> Vec<(i16, i16)> {
@@ -340,6 +521,27 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_hard_drop_only_no_tucks() {
+        let (xx, __) = (true, false);
+        let snapshot = Snapshot {
+            hold: None,
+            queue: vec![Color::n('T')],
+            matrix: basic_matrix![[__, __, __, __, __], [xx, __, __, __, __]],
+        };
+        let srs = srs();
+        let mut pfind = PlaceFinder::new(&srs, MovementModel::HardDropOnly);
+        pfind.reset_matrix(&snapshot.matrix);
+        pfind.push_shape(Color::n('T'), false);
+        let mut places: Vec<_> = pfind
+            .filter(|pl| pl.tf.2 == R0)
+            .map(|pl| (pl.tf.0, pl.tf.1))
+            .collect();
+        places.sort();
+        // compare to `test_tuck_easy`: (-1, 0) is absent
+        assert_eq!(places, [(-1, 1), (-1, 2), (1, 0)]);
+    }
+
     #[test]
     fn test_tuck_double_sd() {
         let (xx, __) = (true, false);
@@ -370,6 +572,64 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_max_soft_drop() {
+        let (xx, __) = (true, false);
+        let mat = basic_matrix![
+            [__, __, __, xx, xx],
+            [__, __, __, xx, xx],
+            [__, __, __, xx, xx],
+            [__, __, __, xx, xx],
+            [__, __, __, xx, xx],
+            [__, __, __, __, __],
+            [__, __, __, __, __],
+            [xx, xx, xx, __, __],
+        ];
+        let srs = srs();
+        let o_places = |max_soft_drop| {
+            let mut pfind =
+                PlaceFinder::new(&srs, MovementModel::Full).with_max_soft_drop(max_soft_drop);
+            pfind.reset_matrix(&mat);
+            pfind.push_shape(Color::n('O'), false);
+            pfind.map(|pl| (pl.tf.0, pl.tf.1)).collect::<Vec<_>>()
+        };
+        // x x x . .      x x x . .      x x x . .      x x x . .
+        // . . . . .      . . O O .      . . . . .      . . . . .
+        // . . . . .  ->  . . O O .  ->  . . . . .  ->  . . . . .
+        // . . . x x      . . . x x      . . . x x      . . . x x
+        //    ...            ...            ...            ...
+        // . . . x x      . . . x x      . O O x x      O O . x x
+        // . . . x x      . . . x x      . O O x x      O O . x x
+        //                  (4,1)          (-1,0)         (-1,-1)
+        //                SD,L           SD,L,L         SD,L,L,SD,L
+        assert!(o_places(None).contains(&(-1, -1)));
+        assert!(o_places(Some(5)).contains(&(-1, -1)));
+        let places = o_places(Some(3));
+        assert!(places.contains(&(-1, 0)), "{:?}", places);
+        assert!(!places.contains(&(-1, -1)), "{:?}", places);
+    }
+
+    #[test]
+    fn test_require_clear() {
+        let (xx, __) = (true, false);
+        let mat = basic_matrix![[xx, xx, xx, __, __], [xx, xx, xx, __, __]];
+        let srs = srs();
+        let o_places = |require_clear| {
+            let mut pfind =
+                PlaceFinder::new(&srs, MovementModel::Full).with_require_clear(require_clear);
+            pfind.reset_matrix(&mat);
+            pfind.push_shape(Color::n('O'), false);
+            pfind
+                .map(|pl| (pl.idx, pl.tf.0, pl.tf.1))
+                .collect::<Vec<_>>()
+        };
+        assert!(o_places(false).len() > 1);
+        // . . . . .
+        // x x x O O
+        // x x x O O
+        assert_eq!(o_places(true), [(0, -1, 2)]);
+    }
+
     #[test]
     fn test_tuck_ambiguous() {
         let (xx, __) = (true, false);
@@ -448,6 +708,62 @@ mod test {
         assert!(places.contains(&(0, 0)), "{:?}", places);
     }
 
+    #[test]
+    fn test_no_kicks() {
+        use crate::finesse::FinesseFinder;
+        let (xx, __) = (true, false);
+        // . . . x x x x x x x
+        // . . . . x x x x x x
+        // x x x . x x x x x x
+        // x x . . x x x x x x
+        // x x x . x x x x x x
+        let mat = basic_matrix![
+            [xx, xx, xx, __, xx, xx, xx, xx, xx, xx],
+            [xx, xx, __, __, xx, xx, xx, xx, xx, xx],
+            [xx, xx, xx, __, xx, xx, xx, xx, xx, xx],
+            [__, __, __, __, xx, xx, xx, xx, xx, xx],
+            [__, __, __, xx, xx, xx, xx, xx, xx, xx],
+        ];
+        let srs = srs();
+        let t = srs.shape(Color::n('T')).unwrap();
+        // T-spin triple, which requires a kick to get under the overhang
+        let tst = t.normalize((0, 2, R3));
+        let has_tst = |kick_system| {
+            let mut pfind =
+                PlaceFinder::new(&srs, MovementModel::Full).with_kick_system(kick_system);
+            pfind.reset_matrix(&mat);
+            pfind.push_shape(Color::n('T'), false);
+            pfind.any(|pl| pl.normal() == tst)
+        };
+        assert!(has_tst(KickSystem::Srs));
+        assert!(!has_tst(KickSystem::None));
+
+        let mut ffind = FinesseFinder::new(MovementModel::Full);
+        assert!(ffind.find(&mat, t, tst).is_ok());
+        let mut ffind = FinesseFinder::new(MovementModel::Full).with_kick_system(KickSystem::None);
+        assert!(ffind.find(&mat, t, tst).is_err());
+    }
+
+    #[test]
+    fn test_no_kicks_from_spawn() {
+        let srs = srs();
+        let i_places = |cols, kick_system| {
+            let mut pfind =
+                PlaceFinder::new(&srs, MovementModel::Full).with_kick_system(kick_system);
+            pfind.reset_matrix(&BasicMatrix::with_cols(cols));
+            pfind.push_shape(Color::n('I'), false);
+            pfind.count()
+        };
+        // the I piece spawns partially out of bounds, and can't get back in bounds without
+        // kicks
+        assert!(i_places(4, KickSystem::Srs) > 0);
+        assert_eq!(i_places(4, KickSystem::None), 0);
+        assert_eq!(
+            i_places(10, KickSystem::None),
+            i_places(10, KickSystem::Srs)
+        );
+    }
+
     #[test]
     fn test_s_spin_triple_overhangless() {
         let (xx, __) = (true, false);
@@ -485,4 +801,28 @@ mod test {
             r2_places
         );
     }
+
+    #[test]
+    fn test_max_rows() {
+        let srs = srs();
+        let mut pfind = PlaceFinder::new(&srs, MovementModel::Full);
+        // a tower in the leftmost column, `height` rows tall
+        let mut i_placements = |height: u16| {
+            let mut mat = BasicMatrix::with_cols(10);
+            for i in 0..height {
+                mat.try_set((i, 0)).unwrap();
+            }
+            pfind.reset_matrix(&mat);
+            pfind.push_shape(Color::n('I'), false);
+            pfind
+                .by_ref()
+                .map(|pl| pl.shape.cells(pl.tf).map(|(i, _)| i).max().unwrap())
+                .collect::<Vec<_>>()
+        };
+        let low = i_placements(MAX_ROWS - 10);
+        // a vertical I on top of the tower would stick out past the limit
+        let high = i_placements(MAX_ROWS - 2);
+        assert_eq!(high.len(), low.len() - 1);
+        assert!(high.iter().all(|&i| i < MAX_ROWS as i16), "{:?}", high);
+    }
 }
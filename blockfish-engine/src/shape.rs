@@ -1,4 +1,4 @@
-use crate::{BasicMatrix, Color, Input, Orientation};
+use crate::{BasicMatrix, Color, Input, KickSystem, Orientation};
 use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, ops::RangeInclusive};
 use thiserror::Error;
@@ -17,6 +17,30 @@ impl ShapeTable {
         let data = self.shapes.get(&color)?;
         Some(ShapeRef(&self, data, color))
     }
+
+    /// Returns the color of every shape in the table, in sorted order.
+    pub fn colors(&self) -> Vec<Color> {
+        let mut colors = self.shapes.keys().cloned().collect::<Vec<_>>();
+        colors.sort();
+        colors
+    }
+
+    /// Returns the absolute (row, col) coordinates of the cells occupied by shape `shape`
+    /// in orientation `orientation` at position (`row`, `col`). Yields nothing if the
+    /// table has no such shape.
+    pub fn cells(
+        &self,
+        shape: Color,
+        orientation: Orientation,
+        row: u16,
+        col: u16,
+    ) -> impl Iterator<Item = (u16, u16)> + '_ {
+        let tf = (row as i16, col as i16, orientation);
+        self.shape(shape)
+            .into_iter()
+            .flat_map(move |shape| shape.cells(tf))
+            .map(|(i, j)| (i as u16, j as u16))
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -51,6 +75,15 @@ pub type Transform = (i16, i16, Orientation);
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
 pub struct NormalizedShapeTransform(usize, i16, i16);
 
+impl NormalizedShapeTransform {
+    /// Packs this transform into an integer. The result is unique as long as the shape
+    /// table has at most 65536 matrices, which covers every coordinate a shape can be
+    /// placed at.
+    pub fn to_bits(self) -> u64 {
+        ((self.0 as u64 & 0xffff) << 32) | ((self.1 as u16 as u64) << 16) | (self.2 as u16 as u64)
+    }
+}
+
 impl<'a> ShapeRef<'a> {
     /// Returns `true` if this shape intersects matrix `tgt` if transformed by `tf`.
     pub fn intersects(&self, tgt: &BasicMatrix, tf: Transform) -> bool {
@@ -76,6 +109,30 @@ impl<'a> ShapeRef<'a> {
         tgt.blit(mat, (i as u16, j as u16));
     }
 
+    /// Returns the absolute (row, col) coordinates of the cells occupied by this shape
+    /// when transformed by `tf`.
+    pub fn cells(&self, tf: Transform) -> impl Iterator<Item = (i16, i16)> + 'a {
+        let (i0, j0, mat) = self.transform(tf);
+        (0..mat.rows()).flat_map(move |i| {
+            (0..mat.cols())
+                .filter(move |&j| mat.get((i, j)))
+                .map(move |j| (i0 + i as i16, j0 + j as i16))
+        })
+    }
+
+    /// Returns the first orientation, starting from `R0`, that has the same cells as
+    /// orientation `r` up to translation. Symmetrical shapes have fewer distinct
+    /// orientations, e.g. every orientation of the O piece is equivalent to `R0`.
+    pub fn canonical_orientation(&self, r: Orientation) -> Orientation {
+        use Orientation::*;
+        let mat_idx = self.1.orientations[r as usize].matrix_index;
+        [R0, R1, R2, R3]
+            .iter()
+            .cloned()
+            .find(|&r| self.1.orientations[r as usize].matrix_index == mat_idx)
+            .unwrap_or(r)
+    }
+
     /// Returns the initial spawn column for this shape.
     pub fn spawn_col(&self) -> i16 {
         self.1.spawn_col
@@ -121,12 +178,14 @@ impl<'a> ShapeRef<'a> {
 
     /// Try to perform input `Input` to a piece with this shape at transform `tf`. If it
     /// would successfully move the piece, returns the final transformation. If it would
-    /// collide with `matrix`, returns `None`.
+    /// collide with `matrix`, returns `None`. Rotations are kicked according to
+    /// `kick_system`.
     pub fn try_input(
         &self,
         matrix: &BasicMatrix,
         tf: Transform,
         input: Input,
+        kick_system: KickSystem,
     ) -> Option<Transform> {
         // get a list of potential offsets to try (particularly, from the kick table in
         // case of rotation).
@@ -141,6 +200,11 @@ impl<'a> ShapeRef<'a> {
                 return None;
             }
         };
+        // without kicks, only the first offset (the basic rotation) is tried
+        let offsets = match kick_system {
+            KickSystem::Srs => offsets,
+            KickSystem::None => &offsets[..std::cmp::min(offsets.len(), 1)],
+        };
 
         // run SRS algorithm: find the first valid offset if any.
         let (i, j) = offsets
@@ -354,6 +418,24 @@ mod test {
         assert_eq!(mat, basic_matrix![[false, false, true], [true, true, true]]);
     }
 
+    #[test]
+    fn test_shape_cells() {
+        let srs = srs();
+        let cells = |r, i, j| {
+            let mut cells = srs.cells(Color::n('I'), r, i, j).collect::<Vec<_>>();
+            cells.sort();
+            cells
+        };
+        assert_eq!(
+            cells(Orientation::R0, 2, 3),
+            vec![(4, 3), (4, 4), (4, 5), (4, 6)]
+        );
+        assert_eq!(
+            cells(Orientation::R1, 2, 3),
+            vec![(2, 5), (3, 5), (4, 5), (5, 5)]
+        );
+    }
+
     #[test]
     fn test_srs_col_range() {
         let srs = srs();
@@ -486,5 +568,28 @@ mod test {
         let i = srs.shape(Color::n('I')).unwrap();
         assert_eq!(i.normalize((5, 5, R0)), i.normalize((6, 5, R2)));
         assert_eq!(i.normalize((5, 5, R1)), i.normalize((5, 6, R3)));
+        // rows past a byte still give distinct bits
+        let tall = (crate::MAX_ROWS - 4) as i16;
+        assert_ne!(
+            i.normalize((5, 5, R0)).to_bits(),
+            i.normalize((5 + 256, 5, R0)).to_bits()
+        );
+        assert_ne!(
+            i.normalize((tall, 5, R1)).to_bits(),
+            i.normalize((tall - 1, 5, R1)).to_bits()
+        );
+    }
+
+    #[test]
+    fn test_canonical_orientation() {
+        use crate::Orientation::*;
+        let srs = srs();
+        let canonical = |c, r| srs.shape(Color::n(c)).unwrap().canonical_orientation(r);
+        for &r in &[R0, R1, R2, R3] {
+            assert_eq!(canonical('O', r), R0);
+            assert_eq!(canonical('T', r), r);
+        }
+        assert_eq!(canonical('S', R2), R0);
+        assert_eq!(canonical('I', R3), R1);
     }
 }